@@ -0,0 +1,105 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Compression applied to an output file
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Compression {
+    /// gzip, written as `.gz`
+    Gzip,
+    /// Zstandard, written as `.zst` by the `zstd` command
+    Zstd,
+}
+
+impl Compression {
+    /// File extension of the compressed output, without the leading dot
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+
+    /// Infers the compression from an output path's extension
+    ///
+    /// # Examples
+    /// ```
+    /// assert_eq!(Compression::from_path(Path::new("dump.txt.gz")), Some(Compression::Gzip));
+    /// assert_eq!(Compression::from_path(Path::new("dump.txt")), None);
+    /// ```
+    pub(crate) fn from_path(path: &Path) -> Option<Compression> {
+        let extension = path.extension()?.to_string_lossy().to_lowercase();
+        match extension.as_str() {
+            "gz" | "gzip" => Some(Compression::Gzip),
+            "zst" | "zstd" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Compresses the contents of an output
+    ///
+    /// # Arguments
+    /// * `data` - The uncompressed output
+    ///
+    /// # Returns
+    /// The compressed bytes, or an error if the `zstd` command is missing or fails
+    pub(crate) fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data).context("Failed to gzip output")?;
+                encoder.finish().context("Failed to gzip output")
+            }
+            Compression::Zstd => {
+                let mut child = Command::new("zstd")
+                    .args(["--quiet", "--stdout"])
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn()
+                    .context("Failed to run zstd, which --compress zstd requires")?;
+                let mut stdin = child.stdin.take().context("Failed to open zstd input")?;
+                // Feed the input from another thread so a full output pipe cannot stall it
+                let output = std::thread::scope(|scope| {
+                    let writer = scope.spawn(move || stdin.write_all(data));
+                    let output = child.wait_with_output();
+                    (writer.join(), output)
+                });
+                let result = match output {
+                    (Ok(Ok(())), Ok(result)) => result,
+                    (_, Err(error)) => return Err(error).context("Failed to wait for zstd"),
+                    (Ok(Err(error)), _) => {
+                        return Err(error).context("Failed to stream output to zstd")
+                    }
+                    (Err(_), _) => anyhow::bail!("Failed to stream output to zstd"),
+                };
+                if !result.status.success() {
+                    anyhow::bail!(
+                        "zstd failed: {}",
+                        String::from_utf8_lossy(&result.stderr).trim()
+                    );
+                }
+                Ok(result.stdout)
+            }
+        }
+    }
+}
+
+/// Removes a compression extension from an output path
+///
+/// # Returns
+/// The path without a trailing `.gz` or `.zst`, and the compression it named
+///
+/// # Examples
+/// ```
+/// assert_eq!(strip_extension(Path::new("dump.md.gz")), (PathBuf::from("dump.md"), Some(Compression::Gzip)));
+/// ```
+pub(crate) fn strip_extension(path: &Path) -> (PathBuf, Option<Compression>) {
+    match Compression::from_path(path) {
+        Some(compression) => (path.with_extension(""), Some(compression)),
+        None => (path.to_path_buf(), None),
+    }
+}
@@ -0,0 +1,304 @@
+use anyhow::{Context, Result};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directory at the root of a project holding repodump defaults
+pub(crate) const CONFIG_DIR: &str = ".repodump";
+
+/// Config file found in the target directory or any of its parents
+pub(crate) const CONFIG_FILE: &str = "repodump.toml";
+
+/// Options that run commands or write outside the output, only read from config files when
+/// the user passes `--trust-config`
+pub(crate) const UNTRUSTED_KEYS: &[&str] = &[
+    "run",
+    "upload",
+    "upload-only",
+    "output",
+    "clipboard",
+    "content-hash-dedup-across-runs",
+    "summary-file",
+    "manifest-json",
+];
+
+/// A value in a config file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum ConfigValue {
+    Bool(bool),
+    Integer(i64),
+    String(String),
+    Array(Vec<String>),
+}
+
+/// Parses the subset of TOML used by repodump config files
+///
+/// Each line is blank, a `#` comment, or `key = value` where the value is a boolean, an
+/// integer, a basic (`"..."`) or literal (`'...'`) string, or a single-line array of
+/// strings. Keys name long command line options; underscores are read as dashes.
+///
+/// # Arguments
+/// * `text` - Contents of the config file
+///
+/// # Returns
+/// The `(key, value)` pairs in file order
+///
+/// # Examples
+/// ```
+/// let entries = parse_config("format = \"markdown\"\nexclude = [\"*.lock\"]\n")?;
+/// assert_eq!(entries[0], ("format".to_string(), ConfigValue::String("markdown".to_string())));
+/// ```
+pub(crate) fn parse_config(text: &str) -> Result<Vec<(String, ConfigValue)>> {
+    let mut entries = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .with_context(|| format!("line {}: expected `key = value`", index + 1))?;
+        let key = key.trim().trim_matches('"').replace('_', "-");
+        if key.is_empty() {
+            anyhow::bail!("line {}: missing key", index + 1);
+        }
+        let value = parse_value(value.trim())
+            .with_context(|| format!("line {}: invalid value for `{}`", index + 1, key))?;
+        entries.push((key, value));
+    }
+    Ok(entries)
+}
+
+fn parse_value(text: &str) -> Result<ConfigValue> {
+    if let Some(inner) = text.strip_prefix('[') {
+        let (items, rest) = split_array(inner)?;
+        ensure_trailing_comment(rest)?;
+        return Ok(ConfigValue::Array(items));
+    }
+    if text.starts_with('"') || text.starts_with('\'') {
+        let (value, rest) = parse_string(text)?;
+        ensure_trailing_comment(rest)?;
+        return Ok(ConfigValue::String(value));
+    }
+
+    let bare = text.split('#').next().unwrap_or_default().trim();
+    match bare {
+        "true" => Ok(ConfigValue::Bool(true)),
+        "false" => Ok(ConfigValue::Bool(false)),
+        _ => bare
+            .replace('_', "")
+            .parse()
+            .map(ConfigValue::Integer)
+            .with_context(|| format!("unsupported value: {}", bare)),
+    }
+}
+
+/// Parses the strings of an array after its opening bracket, returning the remaining text
+fn split_array(mut text: &str) -> Result<(Vec<String>, &str)> {
+    let mut items = Vec::new();
+    loop {
+        text = text.trim_start();
+        if let Some(rest) = text.strip_prefix(']') {
+            return Ok((items, rest));
+        }
+        let (item, rest) = parse_string(text)?;
+        items.push(item);
+        text = rest.trim_start();
+        if let Some(rest) = text.strip_prefix(',') {
+            text = rest;
+        } else if !text.starts_with(']') {
+            anyhow::bail!("expected `,` or `]` in array");
+        }
+    }
+}
+
+/// Parses a quoted string at the start of `text`, returning it and the remaining text
+fn parse_string(text: &str) -> Result<(String, &str)> {
+    let mut chars = text.char_indices();
+    let quote = match chars.next() {
+        Some((_, quote @ ('"' | '\''))) => quote,
+        _ => anyhow::bail!("expected a quoted string"),
+    };
+
+    let mut value = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c == quote => return Ok((value, &text[i + 1..])),
+            '\\' if quote == '"' => match chars.next().map(|(_, escaped)| escaped) {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some('r') => value.push('\r'),
+                Some('"') => value.push('"'),
+                Some('\\') => value.push('\\'),
+                Some(other) => anyhow::bail!("unsupported escape: \\{}", other),
+                None => break,
+            },
+            c => value.push(c),
+        }
+    }
+    anyhow::bail!("unterminated string")
+}
+
+fn ensure_trailing_comment(rest: &str) -> Result<()> {
+    let rest = rest.trim();
+    if rest.is_empty() || rest.starts_with('#') {
+        Ok(())
+    } else {
+        anyhow::bail!("unexpected text after value: {}", rest)
+    }
+}
+
+/// Converts config entries into long command line options
+///
+/// `true` becomes a bare flag, `false` is omitted, and arrays repeat the option once per
+/// item. Values are attached with `=` so that they may start with a dash.
+///
+/// # Arguments
+/// * `entries` - Parsed config entries
+///
+/// # Returns
+/// The equivalent command line arguments
+pub(crate) fn config_to_args(entries: &[(String, ConfigValue)]) -> Vec<OsString> {
+    let mut args = Vec::new();
+    for (key, value) in entries {
+        match value {
+            ConfigValue::Bool(true) => args.push(format!("--{}", key).into()),
+            ConfigValue::Bool(false) => {}
+            ConfigValue::Integer(number) => args.push(format!("--{}={}", key, number).into()),
+            ConfigValue::String(text) => args.push(format!("--{}={}", key, text).into()),
+            ConfigValue::Array(items) => args.extend(
+                items
+                    .iter()
+                    .map(|item| format!("--{}={}", key, item).into()),
+            ),
+        }
+    }
+    args
+}
+
+/// Finds the `repodump.toml` closest to the target directory
+///
+/// The search stops at the root of the git repository holding the target, so a config file
+/// outside the repository is never picked up. Outside a repository only the target itself is
+/// searched.
+///
+/// # Arguments
+/// * `root` - The target directory, searched first before each of its parents
+///
+/// # Returns
+/// The path of the config file, or `None` if no directory up to the repository root has one
+pub(crate) fn find_config_file(root: &Path) -> Option<PathBuf> {
+    let root = fs::canonicalize(root).ok()?;
+    let repo_root = root.ancestors().find(|dir| dir.join(".git").exists());
+    let last = repo_root.unwrap_or(&root);
+    for dir in root.ancestors() {
+        let path = dir.join(CONFIG_FILE);
+        if path.is_file() {
+            return Some(path);
+        }
+        if dir == last {
+            break;
+        }
+    }
+    None
+}
+
+/// Reads and parses a config file
+fn read_config_file(path: &Path) -> Result<Vec<(String, ConfigValue)>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+    parse_config(&text).with_context(|| format!("Invalid config file: {}", path.display()))
+}
+
+/// Loads the defaults of a project from a config file and its `.repodump/` directory
+///
+/// The sources are read in this order, each optional:
+/// 1. `config_file` - a `repodump.toml` found above the target or given with `--config`
+/// 2. `.repodump/config.toml` - options keyed by their long name, e.g. `format = "markdown"`
+/// 3. `.repodump/exclude` - one exclude glob per line; blank lines and `#` comments are
+///    skipped
+/// 4. `.repodump/prompt.txt` - the prompt text, replacing any `prompt` key from `config.toml`
+///
+/// Options set in `.repodump/config.toml` replace those of the same name from `config_file`.
+///
+/// # Arguments
+/// * `root` - The target directory
+/// * `config_file` - The `repodump.toml` to read before the `.repodump/` directory
+/// * `replaced` - Repeatable options given on the command line whose defaults should be
+///   dropped rather than extended, such as `format` and `output`
+/// * `trusted` - Whether options in [`UNTRUSTED_KEYS`] may be set; if not, they are refused
+///
+/// # Returns
+/// Command line arguments to place before the user's own, so that options given on the
+/// command line override single values and extend lists
+pub(crate) fn load_project_defaults(
+    root: &Path,
+    config_file: Option<&Path>,
+    replaced: &[&str],
+    trusted: bool,
+) -> Result<Vec<OsString>> {
+    let mut entries = match config_file {
+        Some(path) => read_config_file(path)?,
+        None => Vec::new(),
+    };
+
+    let dir = root.join(CONFIG_DIR);
+    if !dir.is_dir() {
+        entries.retain(|(key, _)| !replaced.contains(&key.as_str()));
+        return checked_args(&entries, trusted);
+    }
+
+    let config_path = dir.join("config.toml");
+    if config_path.is_file() {
+        let project_entries = read_config_file(&config_path)?;
+        entries.retain(|(key, _)| {
+            !project_entries
+                .iter()
+                .any(|(project_key, _)| project_key == key)
+        });
+        entries.extend(project_entries);
+    }
+
+    let exclude_path = dir.join("exclude");
+    if exclude_path.is_file() {
+        let text = fs::read_to_string(&exclude_path)
+            .with_context(|| format!("Failed to read exclude file: {}", exclude_path.display()))?;
+        let patterns = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect();
+        entries.push(("exclude".to_string(), ConfigValue::Array(patterns)));
+    }
+
+    let prompt_path = dir.join("prompt.txt");
+    if prompt_path.is_file() {
+        let prompt = fs::read_to_string(&prompt_path)
+            .with_context(|| format!("Failed to read prompt file: {}", prompt_path.display()))?;
+        entries.retain(|(key, _)| key != "prompt");
+        entries.push((
+            "prompt".to_string(),
+            ConfigValue::String(prompt.trim_end().to_string()),
+        ));
+    }
+
+    entries.retain(|(key, _)| !replaced.contains(&key.as_str()));
+    checked_args(&entries, trusted)
+}
+
+/// Converts config entries to arguments, refusing untrusted options unless `trusted` is set
+fn checked_args(entries: &[(String, ConfigValue)], trusted: bool) -> Result<Vec<OsString>> {
+    if !trusted {
+        if let Some((key, _)) = entries
+            .iter()
+            .find(|(key, _)| UNTRUSTED_KEYS.contains(&key.as_str()))
+        {
+            anyhow::bail!(
+                "Refusing to apply `{}` from project defaults; pass --trust-config to allow it",
+                key
+            );
+        }
+    }
+    Ok(config_to_args(entries))
+}
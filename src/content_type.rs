@@ -0,0 +1,266 @@
+use clap::ValueEnum;
+use encoding_rs::Encoding;
+use std::borrow::Cow;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Broad category of a file detected from its leading bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ContentType {
+    /// PNG, JPEG, GIF, WebP, ICO, TIFF
+    Image,
+    /// ZIP, gzip, bzip2, xz, 7z, RAR, zstd, tar
+    Archive,
+    /// MP3, FLAC, Ogg, WAV
+    Audio,
+    /// MP4/MOV, Matroska/WebM, AVI
+    Video,
+    /// WOFF, WOFF2, TrueType, OpenType
+    Font,
+    /// PDF
+    Document,
+    /// ELF, PE, Mach-O, WebAssembly
+    Executable,
+}
+
+/// Number of bytes read from the start of a file to detect its type
+const SNIFF_BYTES: u64 = 512;
+
+/// Magic numbers at the start of a file and the type they identify
+const SIGNATURES: &[(&[u8], ContentType)] = &[
+    (b"\x89PNG\r\n\x1a\n", ContentType::Image),
+    (b"\xff\xd8\xff", ContentType::Image),
+    (b"GIF87a", ContentType::Image),
+    (b"GIF89a", ContentType::Image),
+    (b"\x00\x00\x01\x00", ContentType::Image),
+    (b"II*\x00", ContentType::Image),
+    (b"MM\x00*", ContentType::Image),
+    (b"PK\x03\x04", ContentType::Archive),
+    (b"\x1f\x8b", ContentType::Archive),
+    (b"\xfd7zXZ\x00", ContentType::Archive),
+    (b"7z\xbc\xaf\x27\x1c", ContentType::Archive),
+    (b"Rar!\x1a\x07", ContentType::Archive),
+    (b"\x28\xb5\x2f\xfd", ContentType::Archive),
+    (b"fLaC", ContentType::Audio),
+    (b"OggS", ContentType::Audio),
+    (b"\x1a\x45\xdf\xa3", ContentType::Video),
+    (b"wOFF", ContentType::Font),
+    (b"wOF2", ContentType::Font),
+    (b"\x00\x01\x00\x00\x00", ContentType::Font),
+    (b"OTTO", ContentType::Font),
+    (b"%PDF", ContentType::Document),
+    (b"\x7fELF", ContentType::Executable),
+    (b"\xfe\xed\xfa\xce", ContentType::Executable),
+    (b"\xfe\xed\xfa\xcf", ContentType::Executable),
+    (b"\xce\xfa\xed\xfe", ContentType::Executable),
+    (b"\xcf\xfa\xed\xfe", ContentType::Executable),
+    (b"\x00asm", ContentType::Executable),
+];
+
+/// Magic numbers short enough to begin ordinary text, such as a line starting `MZ` or
+/// `ID3`, which only count when the bytes also look binary
+const WEAK_SIGNATURES: &[(&[u8], ContentType)] = &[
+    (b"BZh", ContentType::Archive),
+    (b"ID3", ContentType::Audio),
+    (b"\xff\xfb", ContentType::Audio),
+    (b"MZ", ContentType::Executable),
+];
+
+/// Detects the content type of a file from its leading bytes
+///
+/// # Arguments
+/// * `head` - The first bytes of the file
+///
+/// # Returns
+/// The detected type, or `None` for text and unrecognised formats
+///
+/// # Examples
+/// ```
+/// assert_eq!(detect_content_type(b"%PDF-1.7"), Some(ContentType::Document));
+/// assert_eq!(detect_content_type(b"fn main() {}"), None);
+/// assert_eq!(detect_content_type(b"MZ is a prefix, not a header"), None);
+/// ```
+pub(crate) fn detect_content_type(head: &[u8]) -> Option<ContentType> {
+    // RIFF containers carry their format at offset 8
+    if head.len() >= 12 && &head[..4] == b"RIFF" {
+        return match &head[8..12] {
+            b"WEBP" => Some(ContentType::Image),
+            b"WAVE" => Some(ContentType::Audio),
+            b"AVI " => Some(ContentType::Video),
+            _ => None,
+        };
+    }
+    // ISO base media files (MP4, MOV) have an `ftyp` box at offset 4
+    if head.len() >= 8 && &head[4..8] == b"ftyp" {
+        return Some(ContentType::Video);
+    }
+    // POSIX tar archives have `ustar` at offset 257
+    if head.len() >= 262 && &head[257..262] == b"ustar" {
+        return Some(ContentType::Archive);
+    }
+
+    SIGNATURES
+        .iter()
+        .find(|(magic, _)| head.starts_with(magic))
+        .or_else(|| {
+            WEAK_SIGNATURES
+                .iter()
+                .find(|(magic, _)| head.starts_with(magic))
+                .filter(|_| looks_binary(head))
+        })
+        .map(|(_, content_type)| *content_type)
+}
+
+/// Detects the content type of a file on disk by reading its first bytes
+///
+/// # Arguments
+/// * `path` - Full path of the file
+///
+/// # Returns
+/// The detected type, or `None` if it is unrecognised or cannot be read
+pub(crate) fn sniff_file(path: &Path) -> Option<ContentType> {
+    let file = fs::File::open(path).ok()?;
+    let mut head = Vec::new();
+    file.take(SNIFF_BYTES).read_to_end(&mut head).ok()?;
+    detect_content_type(&head)
+}
+
+/// Number of leading bytes inspected by `is_probably_binary`
+const BINARY_SAMPLE_BYTES: u64 = 8 * 1024;
+
+/// Determines if leading bytes look like binary rather than text
+///
+/// The bytes are text if `detect_encoding` finds an encoding for them.
+///
+/// # Examples
+/// ```
+/// assert!(looks_binary(b"\x89PNG\r\n\x1a\n\x00\x00"));
+/// assert!(!looks_binary("caf\u{e9}".as_bytes()));
+/// ```
+pub(crate) fn looks_binary(sample: &[u8]) -> bool {
+    detect_encoding(sample).is_none()
+}
+
+/// Guesses the text encoding of a file's bytes
+///
+/// A byte order mark decides the encoding. Otherwise NUL bytes at every other position mean
+/// UTF-16, and valid UTF-8 is UTF-8. Anything else without NUL bytes is read as Windows-1252,
+/// the superset of Latin-1 that legacy files are usually written in, unless control
+/// characters show it is binary. A multi-byte character cut off at the end of a sample does
+/// not count as invalid UTF-8.
+///
+/// # Returns
+/// The encoding, or `None` if the bytes look binary
+///
+/// # Examples
+/// ```
+/// assert_eq!(detect_encoding(b"caf\xe9"), Some(encoding_rs::WINDOWS_1252));
+/// assert_eq!(detect_encoding(b"h\x00i\x00"), Some(encoding_rs::UTF_16LE));
+/// assert_eq!(detect_encoding(b"\x00\x01\x02\x03"), None);
+/// ```
+pub(crate) fn detect_encoding(bytes: &[u8]) -> Option<&'static Encoding> {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return Some(encoding);
+    }
+    if let Some(encoding) = detect_utf16(bytes) {
+        return Some(encoding);
+    }
+    if bytes.contains(&0) {
+        return None;
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(_) => return Some(encoding_rs::UTF_8),
+        // `error_len` is `None` when the bytes merely end inside a character
+        Err(error) if error.error_len().is_none() => return Some(encoding_rs::UTF_8),
+        Err(_) => {}
+    }
+    let controls = bytes
+        .iter()
+        .filter(|&&byte| is_control(byte.into()))
+        .count();
+    (controls * 50 <= bytes.len()).then_some(encoding_rs::WINDOWS_1252)
+}
+
+/// Whether a character is a control character that text rarely holds, unlike whitespace
+/// and the escape that starts terminal colors
+fn is_control(c: u32) -> bool {
+    c < 0x20 && !matches!(c, 0x09 | 0x0a | 0x0c | 0x0d | 0x1b)
+}
+
+/// Recognises UTF-16 without a byte order mark from the NUL high bytes of ASCII characters
+fn detect_utf16(bytes: &[u8]) -> Option<&'static Encoding> {
+    let pairs = bytes.len() / 2;
+    if pairs == 0 {
+        return None;
+    }
+    let nuls_at = |offset: usize| {
+        bytes
+            .chunks_exact(2)
+            .filter(|pair| pair[offset] == 0)
+            .count()
+    };
+    let (even, odd) = (nuls_at(0), nuls_at(1));
+    // Mostly NULs on one side and almost none on the other
+    let (encoding, high) = if odd * 10 >= pairs * 4 && even * 20 <= pairs {
+        (encoding_rs::UTF_16LE, 1)
+    } else if even * 10 >= pairs * 4 && odd * 20 <= pairs {
+        (encoding_rs::UTF_16BE, 0)
+    } else {
+        return None;
+    };
+    let controls = bytes
+        .chunks_exact(2)
+        .filter(|pair| pair[high] == 0 && is_control(pair[1 - high].into()))
+        .count();
+    (controls * 50 <= pairs).then_some(encoding)
+}
+
+/// Decodes a file's bytes into UTF-8 text
+///
+/// # Arguments
+/// * `bytes` - The file contents
+/// * `encoding` - Encoding given with `--encoding`, or `None` to detect it
+///
+/// # Returns
+/// The text without a byte order mark and the encoding it was decoded from, or `None` if the
+/// bytes look binary
+pub(crate) fn decode_text<'a>(
+    bytes: &'a [u8],
+    encoding: Option<&'static Encoding>,
+) -> Option<(Cow<'a, str>, &'static Encoding)> {
+    let encoding = match encoding {
+        // NUL bytes are only text in UTF-16
+        Some(encoding)
+            if encoding == encoding_rs::UTF_16LE || encoding == encoding_rs::UTF_16BE =>
+        {
+            encoding
+        }
+        Some(_) if bytes.contains(&0) => return None,
+        Some(encoding) => encoding,
+        None => detect_encoding(bytes)?,
+    };
+    // A byte order mark still overrides the encoding, as browsers do
+    let (text, encoding, _) = encoding.decode(bytes);
+    Some((text, encoding))
+}
+
+/// Parses an encoding label such as `latin1`, `utf-16le` or `shift_jis` for `--encoding`
+pub(crate) fn parse_encoding(label: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| format!("unknown encoding `{}`", label))
+}
+
+/// Determines if a file is probably binary by sampling its first 8 KiB
+///
+/// # Arguments
+/// * `path` - Full path of the file
+///
+/// # Returns
+/// `true` if the sample looks binary, or an error if the file cannot be read
+pub(crate) fn is_probably_binary(path: &Path) -> io::Result<bool> {
+    let file = fs::File::open(path)?;
+    let mut sample = Vec::new();
+    file.take(BINARY_SAMPLE_BYTES).read_to_end(&mut sample)?;
+    Ok(looks_binary(&sample))
+}
@@ -0,0 +1,777 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::notice;
+
+/// A submodule of the repository containing the target directory
+pub(crate) struct SubmoduleInfo {
+    /// Path of the submodule working tree relative to the target directory
+    pub(crate) path: PathBuf,
+    /// Whether the submodule's working tree is checked out
+    pub(crate) initialized: bool,
+}
+
+/// Finds the submodules of the git repository containing the target directory
+///
+/// # Arguments
+/// * `root_path` - The target directory being dumped
+///
+/// # Returns
+/// The submodules whose working trees lie inside the target directory, or an empty list
+/// if the target is not inside a git repository
+pub(crate) fn find_submodules(root_path: &Path) -> Result<Vec<SubmoduleInfo>> {
+    let repo = match gix::discover(root_path) {
+        Ok(repo) => repo,
+        Err(_) => return Ok(Vec::new()),
+    };
+    // Without a checked-out .gitmodules gix falls back to HEAD, which fails in fresh repositories
+    let has_gitmodules = repo
+        .work_dir()
+        .is_some_and(|work_dir| work_dir.join(".gitmodules").is_file());
+    if !has_gitmodules {
+        return Ok(Vec::new());
+    }
+    let submodules = match repo.submodules().context("Failed to read submodules")? {
+        Some(submodules) => submodules,
+        None => return Ok(Vec::new()),
+    };
+    let canonical_root = fs::canonicalize(root_path).context("Failed to resolve target path")?;
+
+    let mut found = Vec::new();
+    for submodule in submodules {
+        let work_dir = submodule
+            .work_dir()
+            .context("Failed to resolve submodule path")?;
+        // Submodules whose directory is missing entirely have nothing to walk
+        let Ok(work_dir) = fs::canonicalize(&work_dir) else {
+            continue;
+        };
+        let Ok(relative_path) = work_dir.strip_prefix(&canonical_root) else {
+            continue;
+        };
+        let initialized = submodule
+            .state()
+            .map(|state| state.worktree_checkout)
+            .unwrap_or(false);
+
+        found.push(SubmoduleInfo {
+            path: relative_path.to_path_buf(),
+            initialized,
+        });
+    }
+
+    found.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(found)
+}
+
+/// Opens the git repository containing the target directory
+///
+/// # Arguments
+/// * `root_path` - The target directory being dumped
+///
+/// # Returns
+/// The repository and the target directory's path relative to the repository work tree
+pub(crate) fn open_repository(root_path: &Path) -> Result<(gix::Repository, PathBuf)> {
+    let repo = gix::discover(root_path)
+        .with_context(|| format!("Not a git repository: {}", root_path.display()))?;
+    let work_dir = repo
+        .work_dir()
+        .context("Git repository has no working tree")?;
+    let canonical_work_dir =
+        fs::canonicalize(work_dir).context("Failed to resolve repository root")?;
+    let canonical_root = fs::canonicalize(root_path).context("Failed to resolve target path")?;
+    let prefix = canonical_root
+        .strip_prefix(&canonical_work_dir)
+        .context("Target directory is outside the repository working tree")?
+        .to_path_buf();
+    Ok((repo, prefix))
+}
+
+/// The state of the repository containing the target directory, for `--git-info`
+pub(crate) struct RepoInfo {
+    /// Name of the checked-out branch, or `None` with a detached `HEAD`
+    pub(crate) branch: Option<String>,
+    /// Full SHA of the `HEAD` commit, or `None` before the first commit
+    pub(crate) commit: Option<String>,
+    /// First line of the `HEAD` commit's message
+    pub(crate) summary: Option<String>,
+    /// Fetch URL of the `origin` remote, without any user name or password it holds
+    pub(crate) remote: Option<String>,
+}
+
+/// Reads the branch, `HEAD` commit and `origin` remote of the repository containing the
+/// target directory
+///
+/// # Arguments
+/// * `root_path` - The target directory being dumped
+///
+/// # Returns
+/// The repository state, or `None` if the target is not inside a git repository
+pub(crate) fn repo_info(root_path: &Path) -> Option<RepoInfo> {
+    let repo = gix::discover(root_path).ok()?;
+    let branch = repo
+        .head_name()
+        .ok()
+        .flatten()
+        .map(|name| name.shorten().to_string());
+    let head = repo.head_commit().ok();
+    let commit = head.as_ref().map(|commit| commit.id.to_string());
+    let summary = head.as_ref().and_then(|commit| {
+        let message = commit.message().ok()?;
+        Some(message.summary().to_string())
+    });
+    let remote = repo.find_remote("origin").ok().and_then(|remote| {
+        let mut url = remote.url(gix::remote::Direction::Fetch)?.clone();
+        // Tokens are often embedded as the user or password of HTTPS remotes
+        url.set_user(None);
+        url.set_password(None);
+        Some(url.to_bstring().to_string())
+    });
+    Some(RepoInfo {
+        branch,
+        commit,
+        summary,
+        remote,
+    })
+}
+
+/// Lists the files tracked in the git index under the target directory
+///
+/// # Arguments
+/// * `root_path` - The target directory being dumped
+///
+/// # Returns
+/// Paths relative to the target directory, in path order; submodules are left out, and an
+/// error is returned if the target is not inside a git repository
+pub(crate) fn tracked_files(root_path: &Path) -> Result<Vec<PathBuf>> {
+    let (repo, prefix) = open_repository(root_path)?;
+    let index = repo.index_or_empty().context("Failed to read git index")?;
+
+    let mut files: Vec<PathBuf> = index
+        .entries()
+        .iter()
+        .filter(|entry| !entry.mode.is_submodule())
+        .filter_map(|entry| {
+            let path = PathBuf::from(entry.path(&index).to_string());
+            path.strip_prefix(&prefix).ok().map(Path::to_path_buf)
+        })
+        .collect();
+    // Conflicted paths have an index entry per stage
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// Finds when each file was last changed by a commit
+///
+/// First-parent history is walked back from `HEAD` once, diffing each commit's tree against
+/// its parent's, and a file is attributed to the newest commit that changed it. The walk
+/// stops as soon as every file has a time.
+///
+/// # Arguments
+/// * `root_path` - The target directory being dumped
+/// * `files` - Paths relative to the target directory
+///
+/// # Returns
+/// The commit time, in seconds since the Unix epoch, of each file present at `HEAD`; files
+/// that are untracked, or all files in a repository without commits, are left out
+pub(crate) fn last_commit_times(
+    root_path: &Path,
+    files: &[PathBuf],
+) -> Result<BTreeMap<PathBuf, i64>> {
+    let (repo, prefix) = open_repository(root_path)?;
+    let mut times = BTreeMap::new();
+    let Ok(mut commit) = repo.head_commit() else {
+        return Ok(times);
+    };
+
+    // Files missing from HEAD are untracked and would otherwise be looked for to the root
+    let head_tree = commit.tree().context("Failed to read commit tree")?;
+    let mut buf = Vec::new();
+    let mut pending: BTreeMap<gix::bstr::BString, PathBuf> = BTreeMap::new();
+    for file in files {
+        let path = prefix.join(file);
+        if let Ok(Some(_)) = head_tree.lookup_entry_by_path(&path, &mut buf) {
+            let key = path.to_string_lossy().replace('\\', "/");
+            pending.insert(key.into(), file.clone());
+        }
+    }
+
+    while !pending.is_empty() {
+        let time = commit.time().context("Failed to read commit time")?.seconds;
+        let tree = commit.tree().context("Failed to read commit tree")?;
+        let parent = match commit.parent_ids().next() {
+            Some(id) => Some(
+                id.object()
+                    .context("Failed to read parent commit")?
+                    .try_into_commit()
+                    .context("Parent is not a commit")?,
+            ),
+            None => None,
+        };
+        // The root commit added everything it contains
+        let parent_tree = match &parent {
+            Some(parent) => parent.tree().context("Failed to read commit tree")?,
+            None => repo.empty_tree(),
+        };
+
+        parent_tree
+            .changes()
+            .context("Failed to configure git diff")?
+            .track_path()
+            .track_rewrites(None)
+            .for_each_to_obtain_tree(&tree, |change| {
+                if let Some(file) = pending.remove(change.location) {
+                    times.insert(file, time);
+                }
+                Ok::<_, std::convert::Infallible>(gix::object::tree::diff::Action::Continue)
+            })
+            .context("Failed to diff commit against its parent")?;
+
+        match parent {
+            Some(parent) => commit = parent,
+            None => break,
+        }
+    }
+    Ok(times)
+}
+
+/// Reads file contents as of a fixed git revision
+pub(crate) struct RevisionReader {
+    repo: gix::Repository,
+    tree_id: gix::ObjectId,
+    prefix: PathBuf,
+}
+
+impl RevisionReader {
+    /// Opens the repository containing the target directory at the given revision
+    ///
+    /// # Arguments
+    /// * `root_path` - The target directory being dumped
+    /// * `rev` - A revision spec such as `HEAD`, a branch name, or a commit SHA
+    pub(crate) fn open(root_path: &Path, rev: &str) -> Result<Self> {
+        let (repo, prefix) = open_repository(root_path)?;
+        let tree_id = repo
+            .rev_parse_single(rev)
+            .with_context(|| format!("Failed to resolve git revision: {}", rev))?
+            .object()
+            .context("Failed to read git object")?
+            .peel_to_tree()
+            .with_context(|| format!("Revision does not point to a tree: {}", rev))?
+            .id;
+        Ok(RevisionReader {
+            repo,
+            tree_id,
+            prefix,
+        })
+    }
+
+    /// Reads a file as it was at the revision
+    ///
+    /// # Arguments
+    /// * `relative_path` - Path relative to the target directory
+    ///
+    /// # Returns
+    /// The file's bytes, or `None` if it did not exist at the revision
+    pub(crate) fn read(&self, relative_path: &Path) -> Result<Option<Vec<u8>>> {
+        let tree = self
+            .repo
+            .find_tree(self.tree_id)
+            .context("Failed to read git tree")?;
+        let mut buf = Vec::new();
+        let entry = tree
+            .lookup_entry_by_path(self.prefix.join(relative_path), &mut buf)
+            .context("Failed to look up path in git tree")?;
+        match entry {
+            Some(entry) if entry.mode().is_blob() => {
+                let blob = entry.object().context("Failed to read git blob")?;
+                Ok(Some(blob.data.clone()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Lists the files under the target directory as of the revision
+    ///
+    /// # Returns
+    /// Paths relative to the target directory, in path order
+    pub(crate) fn files(&self) -> Result<Vec<PathBuf>> {
+        let tree = self
+            .repo
+            .find_tree(self.tree_id)
+            .context("Failed to read git tree")?;
+        let mut recorder = gix::traverse::tree::Recorder::default();
+        tree.traverse()
+            .breadthfirst(&mut recorder)
+            .context("Failed to walk git tree")?;
+
+        let mut files: Vec<PathBuf> = recorder
+            .records
+            .into_iter()
+            .filter(|entry| entry.mode.is_blob())
+            .filter_map(|entry| {
+                let path = PathBuf::from(entry.filepath.to_string());
+                path.strip_prefix(&self.prefix).ok().map(Path::to_path_buf)
+            })
+            .collect();
+        files.sort();
+        Ok(files)
+    }
+}
+
+/// The files that differ between a git revision and the working tree
+pub(crate) struct ChangedFiles {
+    /// Current files that are new or modified since the revision, in path order
+    pub(crate) changed: Vec<PathBuf>,
+    /// Files present at the revision but no longer on disk, in path order
+    pub(crate) deleted: Vec<PathBuf>,
+}
+
+/// Finds the files that differ from a git revision
+///
+/// Files are compared by content, so untracked files count as new. Renames are not tracked
+/// as such: the new path is a changed file and the old path a deleted one.
+///
+/// # Arguments
+/// * `root_path` - The target directory being dumped
+/// * `rev` - A revision spec such as `HEAD`, a branch name, or a commit SHA
+/// * `files` - Current files to compare, relative to the target directory
+///
+/// # Returns
+/// The changed and deleted files, or an error if the revision cannot be resolved
+pub(crate) fn changed_files(
+    root_path: &Path,
+    rev: &str,
+    files: &[PathBuf],
+) -> Result<ChangedFiles> {
+    let reader = RevisionReader::open(root_path, rev)?;
+    let mut changed = Vec::new();
+    for file in files {
+        let current = fs::read(root_path.join(file)).ok();
+        if current.is_none() || reader.read(file)? != current {
+            changed.push(file.clone());
+        }
+    }
+    let deleted = reader
+        .files()?
+        .into_iter()
+        .filter(|file| fs::symlink_metadata(root_path.join(file)).is_err())
+        .collect();
+    Ok(ChangedFiles { changed, deleted })
+}
+
+/// Lines added and removed in one file, as listed by `--diff-stat`
+pub(crate) struct DiffStat {
+    /// Path relative to the target directory
+    pub(crate) path: PathBuf,
+    pub(crate) insertions: usize,
+    pub(crate) deletions: usize,
+}
+
+/// A file that differs from a git revision, with both versions of its contents
+struct ChangedVersions {
+    /// Path relative to the target directory
+    path: PathBuf,
+    /// Contents at the revision, empty for new files
+    base: Vec<u8>,
+    /// Current contents, empty for deleted files
+    current: Vec<u8>,
+}
+
+/// Reads both versions of every file that differs from a git revision
+///
+/// # Arguments
+/// * `root_path` - The target directory being dumped
+/// * `rev` - The revision to compare against
+/// * `files` - Current files to compare, relative to the target directory
+/// * `keep_deleted` - Decides whether a file that exists at the revision but not on disk
+///   is listed as deleted
+///
+/// # Returns
+/// One entry per added, modified or deleted file, in path order
+fn changed_versions(
+    root_path: &Path,
+    rev: &str,
+    files: &[PathBuf],
+    keep_deleted: &dyn Fn(&Path) -> bool,
+) -> Result<Vec<ChangedVersions>> {
+    let reader = RevisionReader::open(root_path, rev)?;
+    let mut changes = Vec::new();
+    for file in files {
+        let current = match fs::read(root_path.join(file)) {
+            Ok(current) => current,
+            // Removed since it was listed, so it is reported below as deleted
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(error) => {
+                return Err(error)
+                    .with_context(|| format!("Failed to read file: {}", file.display()))
+            }
+        };
+        let base = reader.read(file)?.unwrap_or_default();
+        if base != current {
+            changes.push(ChangedVersions {
+                path: file.clone(),
+                base,
+                current,
+            });
+        }
+    }
+
+    for file in reader.files()? {
+        if !root_path.join(&file).exists() && (files.contains(&file) || keep_deleted(&file)) {
+            let base = reader.read(&file)?.unwrap_or_default();
+            changes.push(ChangedVersions {
+                path: file,
+                base,
+                current: Vec::new(),
+            });
+        }
+    }
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(changes)
+}
+
+/// Computes the changed lines of every file that differs from a git revision
+///
+/// Like `git diff --stat`, only files tracked in the git index are compared, so untracked
+/// files are left out. A file that is missing from disk counts as deleted.
+///
+/// # Arguments
+/// * `root_path` - The target directory being dumped
+/// * `rev` - The revision to compare against
+/// * `files` - Current files to compare, relative to the target directory
+/// * `keep_deleted` - Decides whether a file that exists at the revision but not on disk
+///   is listed as deleted
+///
+/// # Returns
+/// One entry per added, modified or deleted file, in path order
+pub(crate) fn diff_stat(
+    root_path: &Path,
+    rev: &str,
+    files: &[PathBuf],
+    keep_deleted: &dyn Fn(&Path) -> bool,
+) -> Result<Vec<DiffStat>> {
+    let tracked = tracked_files(root_path)?;
+    let files: Vec<PathBuf> = files
+        .iter()
+        .filter(|file| tracked.binary_search(file).is_ok())
+        .cloned()
+        .collect();
+    let changes = changed_versions(root_path, rev, &files, keep_deleted)?;
+    Ok(changes
+        .into_iter()
+        .map(|change| {
+            let (insertions, deletions) = count_line_changes(
+                &String::from_utf8_lossy(&change.base),
+                &String::from_utf8_lossy(&change.current),
+            );
+            DiffStat {
+                path: change.path,
+                insertions,
+                deletions,
+            }
+        })
+        .collect())
+}
+
+/// The unified diff of one file, as listed by `--diff`
+pub(crate) struct FileDiff {
+    /// Path relative to the target directory
+    pub(crate) path: PathBuf,
+    pub(crate) insertions: usize,
+    pub(crate) deletions: usize,
+    /// The `@@` hunks of the diff, or `None` if either version is not UTF-8 text
+    pub(crate) hunks: Option<String>,
+}
+
+/// Computes the unified diff of every file that differs from a git revision
+///
+/// # Arguments
+/// * `root_path` - The target directory being dumped
+/// * `rev` - The revision to compare against
+/// * `files` - Current files to compare, relative to the target directory
+/// * `keep_deleted` - Decides whether a file that exists at the revision but not on disk
+///   is listed as deleted
+///
+/// # Returns
+/// One entry per added, modified or deleted file, in path order
+pub(crate) fn file_diffs(
+    root_path: &Path,
+    rev: &str,
+    files: &[PathBuf],
+    keep_deleted: &dyn Fn(&Path) -> bool,
+) -> Result<Vec<FileDiff>> {
+    let changes = changed_versions(root_path, rev, files, keep_deleted)?;
+    Ok(changes
+        .into_iter()
+        .map(|change| {
+            let texts = std::str::from_utf8(&change.base)
+                .ok()
+                .zip(std::str::from_utf8(&change.current).ok());
+            match texts {
+                Some((base, current)) => {
+                    let (insertions, deletions) = count_line_changes(base, current);
+                    FileDiff {
+                        path: change.path,
+                        insertions,
+                        deletions,
+                        hunks: Some(unified_diff(base, current)),
+                    }
+                }
+                None => FileDiff {
+                    path: change.path,
+                    insertions: 0,
+                    deletions: 0,
+                    hunks: None,
+                },
+            }
+        })
+        .collect())
+}
+
+/// Formats the hunks of a unified diff with three lines of context, as `git diff` does
+///
+/// # Returns
+/// The `@@ -a,b +c,d @@` hunks, without the `---`/`+++` file header
+///
+/// # Examples
+/// ```
+/// assert_eq!(unified_diff("a\nb\n", "a\nc\n"), "@@ -1,2 +1,2 @@\n a\n-b\n+c\n");
+/// assert_eq!(unified_diff("", "a\n"), "@@ -0,0 +1,1 @@\n+a\n");
+/// ```
+pub(crate) fn unified_diff(old: &str, new: &str) -> String {
+    use gix::diff::blob::{diff, intern::InternedInput, Algorithm, UnifiedDiffBuilder};
+
+    let input = InternedInput::new(old, new);
+    let hunks = diff(
+        Algorithm::Histogram,
+        &input,
+        UnifiedDiffBuilder::new(&input),
+    );
+    // An empty side starts before the first line, as in `@@ -0,0 +1,3 @@`
+    hunks
+        .replacen("@@ -1,0 ", "@@ -0,0 ", 1)
+        .replacen(" +1,0 @@", " +0,0 @@", 1)
+}
+
+/// Counts the lines inserted and deleted between two versions of a file
+///
+/// # Returns
+/// The `(insertions, deletions)` line counts
+pub(crate) fn count_line_changes(old: &str, new: &str) -> (usize, usize) {
+    use gix::diff::blob::{diff, intern::InternedInput, Algorithm};
+
+    let input = InternedInput::new(old, new);
+    let (mut insertions, mut deletions) = (0, 0);
+    diff(
+        Algorithm::Histogram,
+        &input,
+        |before: std::ops::Range<u32>, after: std::ops::Range<u32>| {
+            deletions += before.len();
+            insertions += after.len();
+        },
+    );
+    (insertions, deletions)
+}
+
+/// Extracts the changed lines of a file plus surrounding context
+///
+/// Unchanged stretches between the kept ranges are replaced by a
+/// `[[repodump:truncated lines=A..B reason=unchanged]]` notice line.
+///
+/// # Arguments
+/// * `old` - The file content at the base revision
+/// * `new` - The current file content
+/// * `context` - Number of unchanged lines to keep around each change
+///
+/// # Returns
+/// The excerpt, or `None` if the file is unchanged
+pub(crate) fn changed_excerpt(old: &str, new: &str, context: usize) -> Option<String> {
+    use gix::diff::blob::{diff, intern::InternedInput, Algorithm};
+
+    let input = InternedInput::new(old, new);
+    let mut changed: Vec<(usize, usize)> = Vec::new();
+    diff(
+        Algorithm::Histogram,
+        &input,
+        |_before: std::ops::Range<u32>, after: std::ops::Range<u32>| {
+            changed.push((after.start as usize, after.end as usize));
+        },
+    );
+    if changed.is_empty() {
+        return None;
+    }
+
+    let lines: Vec<&str> = new.split_inclusive('\n').collect();
+
+    // Expand each change by the context and merge overlapping ranges
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in changed {
+        let start = start.saturating_sub(context);
+        let end = (end + context).min(lines.len());
+        match ranges.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    let mut excerpt = String::new();
+    let mut position = 0;
+    for (start, end) in ranges {
+        if start > position {
+            excerpt.push_str(&notice::truncated_lines(position + 1, start, "unchanged"));
+        }
+        for line in &lines[start..end] {
+            excerpt.push_str(line);
+            if !line.ends_with('\n') {
+                excerpt.push('\n');
+            }
+        }
+        position = end;
+    }
+    if position < lines.len() {
+        excerpt.push_str(&notice::truncated_lines(
+            position + 1,
+            lines.len(),
+            "unchanged",
+        ));
+    }
+    Some(excerpt)
+}
+
+/// Whether a path argument names a remote repository rather than a local directory
+///
+/// # Examples
+/// ```
+/// assert!(is_remote_url("https://github.com/user/repo"));
+/// assert!(is_remote_url("git@github.com:user/repo.git"));
+/// assert!(!is_remote_url("src/main.rs"));
+/// ```
+pub(crate) fn is_remote_url(text: &str) -> bool {
+    let schemes = [
+        "https://",
+        "http://",
+        "git://",
+        "ssh://",
+        "git+ssh://",
+        "file://",
+    ];
+    if schemes.iter().any(|scheme| text.starts_with(scheme)) {
+        return true;
+    }
+    // scp-like `user@host:path`, which never starts with a path separator
+    match text.split_once(':') {
+        Some((host, path)) => {
+            host.contains('@')
+                && !host.contains('/')
+                && !path.is_empty()
+                && !Path::new(text).exists()
+        }
+        None => false,
+    }
+}
+
+/// Name of the repository a remote URL points at, used to name the clone
+///
+/// # Examples
+/// ```
+/// assert_eq!(remote_repo_name("https://github.com/user/repo.git"), "repo");
+/// ```
+pub(crate) fn remote_repo_name(url: &str) -> String {
+    let name = url
+        .trim_end_matches('/')
+        .rsplit(['/', ':'])
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches(".git");
+    if name.is_empty() {
+        "repository".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// Makes a shallow clone of a remote repository with the `git` command
+///
+/// # Arguments
+/// * `url` - The repository URL
+/// * `branch` - Branch or tag to check out instead of the remote's default
+/// * `destination` - Directory to clone into, which must not exist yet
+///
+/// # Returns
+/// An error with git's message if the clone fails or `git` is missing
+pub(crate) fn shallow_clone(url: &str, branch: Option<&str>, destination: &Path) -> Result<()> {
+    let mut command = std::process::Command::new("git");
+    command.args(["clone", "--quiet", "--depth", "1", "--single-branch"]);
+    if let Some(branch) = branch {
+        command.args(["--branch", branch]);
+    }
+    // `--` keeps a URL starting with a dash from being read as an option
+    command.arg("--").arg(url).arg(destination);
+    let output = command
+        .stdin(std::process::Stdio::null())
+        .output()
+        .context("Failed to run git, which cloning a remote repository requires")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to clone {}: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// A commit listed by `--git-log`
+pub(crate) struct LogEntry {
+    /// Abbreviated commit hash
+    pub(crate) id: String,
+    /// Author name
+    pub(crate) author: String,
+    /// Commit time in seconds since the Unix epoch
+    pub(crate) time: i64,
+    /// Full commit message, subject and body, without trailing whitespace
+    pub(crate) message: String,
+}
+
+/// Lists the most recent commits of the current branch, following first parents
+///
+/// # Arguments
+/// * `root_path` - The target directory being dumped
+/// * `count` - Maximum number of commits to list
+///
+/// # Returns
+/// Up to `count` commits, newest first; fewer when the history, or a shallow clone of it,
+/// ends sooner, and none in a repository without commits
+pub(crate) fn recent_commits(root_path: &Path, count: usize) -> Result<Vec<LogEntry>> {
+    let (repo, _) = open_repository(root_path)?;
+    let mut commits = Vec::new();
+    let Ok(mut commit) = repo.head_commit() else {
+        return Ok(commits);
+    };
+    while commits.len() < count {
+        let message = commit
+            .message_raw()
+            .context("Failed to read commit message")?;
+        let author = commit.author().context("Failed to read commit author")?;
+        commits.push(LogEntry {
+            id: commit.id().shorten_or_id().to_string(),
+            author: author.name.to_string(),
+            time: author.time.seconds,
+            message: message.to_string().trim_end().to_string(),
+        });
+        // A shallow clone lists parents whose objects were never fetched
+        let parent = commit
+            .parent_ids()
+            .next()
+            .and_then(|id| id.object().ok())
+            .and_then(|object| object.try_into_commit().ok());
+        match parent {
+            Some(parent) => commit = parent,
+            None => break,
+        }
+    }
+    Ok(commits)
+}
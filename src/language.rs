@@ -0,0 +1,309 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A programming or markup language recognised from file names
+pub(crate) struct Language {
+    /// Display name, e.g. `Rust`
+    pub(crate) name: &'static str,
+    /// Language hint of Markdown code fences
+    pub(crate) fence: &'static str,
+    /// Lowercase file extensions, without the leading dot
+    pub(crate) extensions: &'static [&'static str],
+    /// Icon shown before files of this language with `--tree-icons`
+    pub(crate) icon: &'static str,
+}
+
+/// Known languages, matched by extension
+pub(crate) const LANGUAGES: &[Language] = &[
+    Language {
+        name: "Rust",
+        fence: "rust",
+        extensions: &["rs"],
+        icon: "🦀",
+    },
+    Language {
+        name: "Python",
+        fence: "python",
+        extensions: &["py", "pyi", "pyw"],
+        icon: "🐍",
+    },
+    Language {
+        name: "JavaScript",
+        fence: "javascript",
+        extensions: &["js", "mjs", "cjs", "jsx"],
+        icon: "🟨",
+    },
+    Language {
+        name: "TypeScript",
+        fence: "typescript",
+        extensions: &["ts", "mts", "cts", "tsx"],
+        icon: "🔷",
+    },
+    Language {
+        name: "Go",
+        fence: "go",
+        extensions: &["go"],
+        icon: "🐹",
+    },
+    Language {
+        name: "Java",
+        fence: "java",
+        extensions: &["java"],
+        icon: "☕",
+    },
+    Language {
+        name: "Kotlin",
+        fence: "kotlin",
+        extensions: &["kt", "kts"],
+        icon: "🟪",
+    },
+    Language {
+        name: "C",
+        fence: "c",
+        extensions: &["c", "h"],
+        icon: "🇨",
+    },
+    Language {
+        name: "C++",
+        fence: "cpp",
+        extensions: &["cpp", "cc", "cxx", "hpp", "hh", "hxx"],
+        icon: "➕",
+    },
+    Language {
+        name: "C#",
+        fence: "csharp",
+        extensions: &["cs"],
+        icon: "🟩",
+    },
+    Language {
+        name: "Ruby",
+        fence: "ruby",
+        extensions: &["rb"],
+        icon: "💎",
+    },
+    Language {
+        name: "PHP",
+        fence: "php",
+        extensions: &["php"],
+        icon: "🐘",
+    },
+    Language {
+        name: "Swift",
+        fence: "swift",
+        extensions: &["swift"],
+        icon: "🐦",
+    },
+    Language {
+        name: "Shell",
+        fence: "bash",
+        extensions: &["sh", "bash", "zsh", "fish"],
+        icon: "🐚",
+    },
+    Language {
+        name: "HTML",
+        fence: "html",
+        extensions: &["html", "htm"],
+        icon: "🌐",
+    },
+    Language {
+        name: "CSS",
+        fence: "css",
+        extensions: &["css", "scss", "sass", "less"],
+        icon: "🎨",
+    },
+    Language {
+        name: "Markdown",
+        fence: "markdown",
+        extensions: &["md", "markdown"],
+        icon: "📝",
+    },
+    Language {
+        name: "JSON",
+        fence: "json",
+        extensions: &["json"],
+        icon: "🔧",
+    },
+    Language {
+        name: "YAML",
+        fence: "yaml",
+        extensions: &["yaml", "yml"],
+        icon: "🔧",
+    },
+    Language {
+        name: "TOML",
+        fence: "toml",
+        extensions: &["toml"],
+        icon: "🔧",
+    },
+    Language {
+        name: "SQL",
+        fence: "sql",
+        extensions: &["sql"],
+        icon: "🗃️",
+    },
+];
+
+/// Icon shown before directories with `--tree-icons`
+pub(crate) const DIRECTORY_ICON: &str = "📁";
+
+/// Icon shown before files of an unknown language with `--tree-icons`
+pub(crate) const FILE_ICON: &str = "📄";
+
+/// Looks up the language of a file from its extension
+///
+/// # Arguments
+/// * `path` - Path or file name of the file
+///
+/// # Returns
+/// The language, or `None` if the extension is unknown
+///
+/// # Examples
+/// ```
+/// assert_eq!(language_for_path(Path::new("src/main.rs")).map(|l| l.icon), Some("🦀"));
+/// ```
+pub(crate) fn language_for_path(path: &Path) -> Option<&'static Language> {
+    let extension = path.extension()?.to_string_lossy().to_lowercase();
+    LANGUAGES
+        .iter()
+        .find(|language| language.extensions.contains(&extension.as_str()))
+}
+
+/// Chooses the language hint of a Markdown code fence for a file
+///
+/// # Arguments
+/// * `path` - Path or file name of the file
+///
+/// # Returns
+/// The language's fence name, the bare extension for unknown languages, or an empty string
+///
+/// # Examples
+/// ```
+/// assert_eq!(fence_language(Path::new("src/main.rs")), "rust");
+/// assert_eq!(fence_language(Path::new("notes.txt")), "txt");
+/// ```
+pub(crate) fn fence_language(path: &Path) -> String {
+    match language_for_path(path) {
+        Some(language) => language.fence.to_string(),
+        None => path
+            .extension()
+            .map(|extension| extension.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    }
+}
+
+/// Chooses the tree icon of a file or directory
+///
+/// # Arguments
+/// * `name` - File name of the entry
+/// * `is_directory` - Whether the entry is a directory
+///
+/// # Returns
+/// The icon to place before the entry name
+pub(crate) fn tree_icon(name: &str, is_directory: bool) -> &'static str {
+    if is_directory {
+        return DIRECTORY_ICON;
+    }
+    language_for_path(Path::new(name))
+        .map(|language| language.icon)
+        .unwrap_or(FILE_ICON)
+}
+
+/// File, line, byte and token totals of one language, as reported by
+/// `--language-stats-only`, `--lang-stats` and `--sloc`
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct LanguageStats {
+    /// Language name, or `Other` for unknown extensions
+    pub(crate) name: &'static str,
+    pub(crate) files: usize,
+    pub(crate) lines: usize,
+    /// Lines holding more than whitespace
+    pub(crate) non_blank_lines: usize,
+    pub(crate) bytes: u64,
+    /// Estimated tokens, 0 where they are not estimated
+    pub(crate) tokens: usize,
+}
+
+impl LanguageStats {
+    /// Totals of a single file, named after the language of its extension
+    ///
+    /// # Examples
+    /// ```
+    /// let stats = LanguageStats::file(Path::new("main.rs"), 12, 3, 1, 1);
+    /// assert_eq!((stats.name, stats.files), ("Rust", 1));
+    /// ```
+    pub(crate) fn file(
+        path: &Path,
+        bytes: u64,
+        tokens: usize,
+        lines: usize,
+        non_blank_lines: usize,
+    ) -> LanguageStats {
+        LanguageStats {
+            name: language_for_path(path).map_or("Other", |language| language.name),
+            files: 1,
+            lines,
+            non_blank_lines,
+            bytes,
+            tokens,
+        }
+    }
+}
+
+/// Adds up the totals of each language
+///
+/// # Arguments
+/// * `files` - Totals of each file, from `LanguageStats::file`
+///
+/// # Returns
+/// One entry per language, in name order
+pub(crate) fn group_by_language(
+    files: impl IntoIterator<Item = LanguageStats>,
+) -> Vec<LanguageStats> {
+    let mut totals: BTreeMap<&'static str, LanguageStats> = BTreeMap::new();
+    for file in files {
+        match totals.get_mut(file.name) {
+            Some(stats) => {
+                stats.files += file.files;
+                stats.lines += file.lines;
+                stats.non_blank_lines += file.non_blank_lines;
+                stats.bytes += file.bytes;
+                stats.tokens += file.tokens;
+            }
+            None => {
+                totals.insert(file.name, file);
+            }
+        }
+    }
+    totals.into_values().collect()
+}
+
+/// Totals the files, lines and bytes of each language
+///
+/// Files are read as bytes and lines are counted by newlines, so no text decoding or
+/// content assembly is needed. Unreadable files count towards files only.
+///
+/// # Arguments
+/// * `root_path` - The root directory
+/// * `files` - Paths relative to the root
+///
+/// # Returns
+/// One entry per language, largest byte total first
+pub(crate) fn language_stats(root_path: &Path, files: &[PathBuf]) -> Vec<LanguageStats> {
+    let mut stats = group_by_language(files.iter().map(|file| {
+        let bytes = fs::read(root_path.join(file)).unwrap_or_default();
+        // A final newline ends the last line rather than starting another
+        let lines: Vec<&[u8]> = match bytes.strip_suffix(b"\n") {
+            _ if bytes.is_empty() => Vec::new(),
+            Some(text) => text.split(|&byte| byte == b'\n').collect(),
+            None => bytes.split(|&byte| byte == b'\n').collect(),
+        };
+        let non_blank_lines = lines
+            .iter()
+            .filter(|line| !line.iter().all(u8::is_ascii_whitespace))
+            .count();
+        LanguageStats::file(file, bytes.len() as u64, 0, lines.len(), non_blank_lines)
+    }));
+    stats.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.name.cmp(b.name)));
+    stats
+}
@@ -1,24 +1,55 @@
 use anyhow::{Context, Result};
-use clap::Parser;
-use globset::{Glob, GlobSetBuilder};
+use clap::{Parser, ValueEnum};
+use globset::{GlobBuilder, GlobSetBuilder};
 use ignore::WalkBuilder;
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
 use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode, Stdio};
+use std::sync::{Arc, Mutex, OnceLock};
+
+mod compress;
+mod config;
+mod content_type;
+mod git;
+mod json;
+mod language;
+mod notice;
+mod picker;
+mod progress;
+mod redact;
+mod sort;
+mod symbol;
+mod task;
+mod transform;
+
+use content_type::ContentType;
+use json::Json;
+use sort::{RelevanceTweaks, SortMode};
+use task::Task;
 
 #[cfg(test)]
 mod tests;
 
-#[derive(Parser)]
+#[derive(Clone, Parser)]
 #[command(name = "repodump")]
 #[command(about = "Generate LLM-friendly text files from directories and git repositories")]
 #[command(version = "0.1.0")]
+#[command(args_override_self = true)]
 struct Cli {
     /// Path to the directory or git repository
     path: Option<PathBuf>,
 
-    /// Output file path
-    #[arg(short = 'o', long = "output", default_value = "repodump.txt")]
-    output: PathBuf,
+    /// Output file path, or `-` for stdout [default: repodump.txt]. Repeat to write several
+    /// formats in one run
+    #[arg(short = 'o', long = "output")]
+    output: Vec<PathBuf>,
+
+    /// Write the output to stdout, the same as `-o -`
+    #[arg(long = "stdout", conflicts_with = "output")]
+    stdout: bool,
 
     /// Only include the directory structure but not the file contents
     #[arg(short = 't', long = "tree")]
@@ -36,11 +67,30 @@ struct Cli {
     #[arg(short = 'f', long = "filter")]
     filter: Vec<String>,
 
+    /// Only include READMEs, documentation and top-level manifests in the contents, for a
+    /// cheap project overview; --filter adds patterns and --exclude removes files
+    #[arg(long = "overview")]
+    overview: bool,
+
     /// Exclude files matching any of these patterns
     #[arg(short = 'e', long = "exclude")]
     exclude: Vec<String>,
 
-    /// Include files matching any of these patterns, overriding exclusions
+    /// Exclude the exact relative paths listed one per line in this file, without glob
+    /// interpretation. Repeatable
+    #[arg(long = "exclude-paths-file", value_name = "FILE")]
+    exclude_paths_file: Vec<PathBuf>,
+
+    /// Dump exactly the relative paths listed one per line in this file, or `-` for stdin,
+    /// instead of walking the directory
+    #[arg(long = "files-from", value_name = "FILE")]
+    files_from: Option<PathBuf>,
+
+    /// Still apply --filter, --exclude and the other content filters to --files-from paths
+    #[arg(long = "files-from-filtered", requires = "files_from")]
+    files_from_filtered: bool,
+
+    /// Include files matching any of these patterns, overriding exclusions and .gitignore
     #[arg(short = 'i', long = "include")]
     include: Vec<String>,
 
@@ -52,16 +102,700 @@ struct Cli {
     #[arg(short = 'm', long = "prompt")]
     prompt: Option<String>,
 
+    /// Where the prompt goes: `top`, before the directory tree, or `bottom`
+    #[arg(
+        long = "prompt-position",
+        value_enum,
+        value_name = "POSITION",
+        default_value_t = PromptPosition::Bottom
+    )]
+    prompt_position: PromptPosition,
+
+    /// Read the prompt text from this file, or from stdin with `-`
+    #[arg(long = "prompt-file", value_name = "FILE", conflicts_with = "prompt")]
+    prompt_file: Option<PathBuf>,
+
     /// Do not output a summary to stdout
     #[arg(short = 'q', long = "quiet")]
     quiet: bool,
+
+    /// Output format for both the directory structure and the file contents [default: text].
+    /// Repeat to render several formats from the same scan
+    #[arg(long = "format", value_enum)]
+    format: Vec<OutputFormat>,
+
+    /// Output format for the directory structure, overriding --format
+    #[arg(long = "tree-format", value_enum)]
+    tree_format: Option<OutputFormat>,
+
+    /// Output format for the file contents, overriding --format
+    #[arg(long = "contents-format", value_enum)]
+    contents_format: Option<OutputFormat>,
+
+    /// Skip directories containing more than N entries, noting them in the tree
+    #[arg(long = "skip-huge-dirs", value_name = "N")]
+    skip_huge_dirs: Option<usize>,
+
+    /// Skip token estimation in the summary
+    #[arg(long = "no-summary-tokens")]
+    no_summary_tokens: bool,
+
+    /// Show symlinked files as `link -> target` in the tree and file banners
+    #[arg(long = "relativize-symlinks")]
+    relativize_symlinks: bool,
+
+    /// Leave symlinked files out of the contents, avoiding duplicates of their targets
+    #[arg(long = "skip-symlinked-files")]
+    skip_symlinked_files: bool,
+
+    /// Cap the estimated tokens contributed by each top-level directory
+    #[arg(long = "budget-per-dir", value_name = "N")]
+    budget_per_dir: Option<usize>,
+
+    /// Do not pipe stdout output through a pager
+    #[arg(long = "no-pager")]
+    no_pager: bool,
+
+    /// Include the contents of initialized git submodules
+    #[arg(long = "include-submodules")]
+    include_submodules: bool,
+
+    /// Remove a leading license comment from each file
+    #[arg(long = "strip-license-headers")]
+    strip_license_headers: bool,
+
+    /// Replace API keys, tokens, passwords and private keys in file contents with
+    /// `[REDACTED]`
+    #[arg(long = "redact")]
+    redact: bool,
+
+    /// Also redact matches of this regular expression, or of its first capture group;
+    /// may be repeated
+    #[arg(long = "redact-pattern", value_name = "REGEX", requires = "redact")]
+    redact_pattern: Vec<String>,
+
+    /// File with one marker per line identifying license headers to strip
+    #[arg(
+        long = "license-markers",
+        value_name = "FILE",
+        requires = "strip_license_headers"
+    )]
+    license_markers: Option<PathBuf>,
+
+    /// Add a nested `tree_structured` field to JSON output
+    #[arg(long = "json-include-tree-structured")]
+    json_tree_structured: bool,
+
+    /// Skip files whose first lines carry a generated-code marker
+    #[arg(long = "skip-generated")]
+    skip_generated: bool,
+
+    /// Additional regex identifying generated files, used with --skip-generated
+    #[arg(
+        long = "generated-pattern",
+        value_name = "REGEX",
+        requires = "skip_generated"
+    )]
+    generated_pattern: Vec<String>,
+
+    /// Write a JSON manifest of the included files (path, size, tokens, hash) to this path
+    #[arg(long = "manifest-json", value_name = "FILE")]
+    manifest_json: Option<PathBuf>,
+
+    /// Exclude files whose detected content type is any of these, regardless of extension
+    #[arg(
+        long = "exclude-type",
+        value_enum,
+        value_delimiter = ',',
+        value_name = "TYPES"
+    )]
+    exclude_type: Vec<ContentType>,
+
+    /// Order of files in the contents section
+    #[arg(long = "sort", value_enum, default_value_t = SortMode::Path)]
+    sort: SortMode,
+
+    /// Reverse the order chosen with --sort
+    #[arg(long = "reverse")]
+    reverse: bool,
+
+    /// Place files matching these patterns first when sorting by relevance
+    #[arg(long = "relevance-boost", value_name = "PATTERN")]
+    relevance_boost: Vec<String>,
+
+    /// Place files matching these patterns last when sorting by relevance
+    #[arg(long = "relevance-demote", value_name = "PATTERN")]
+    relevance_demote: Vec<String>,
+
+    /// Only include the changed lines (plus context) of files that differ from this git revision
+    #[arg(long = "only-changed-lines", value_name = "REF")]
+    only_changed_lines: Option<String>,
+
+    /// Lines of context kept around each change with --only-changed-lines
+    #[arg(long = "changed-context", value_name = "N", default_value_t = 3)]
+    changed_context: usize,
+
+    /// Only include files that differ between this git revision and the working tree
+    #[arg(long = "changed-since", value_name = "REF")]
+    changed_since: Option<String>,
+
+    /// Warn on stderr about files whose estimated tokens exceed N
+    #[arg(long = "warn-on-large-tokens-per-file", value_name = "N")]
+    warn_file_tokens: Option<usize>,
+
+    /// Fail when a selected file cannot be read instead of emitting a placeholder
+    #[arg(long = "strict")]
+    strict: bool,
+
+    /// Exit with code 4 when no files are selected for the contents instead of writing an
+    /// empty contents section
+    #[arg(long = "fail-on-empty")]
+    fail_on_empty: bool,
+
+    /// Label the tree root with its full absolute path instead of the directory name
+    #[arg(long = "tree-root-path")]
+    tree_root_path: bool,
+
+    /// Ignore `repodump.toml` and the defaults in the project's `.repodump/` directory
+    #[arg(long = "no-config")]
+    no_config: bool,
+
+    /// Allow project defaults to set options that run commands or write files, such as
+    /// `run`, `upload` and `output`
+    #[arg(long = "trust-config", conflicts_with = "no_config")]
+    trust_config: bool,
+
+    /// Read defaults from this config file instead of the `repodump.toml` found in the target
+    /// directory or its parents
+    #[arg(long = "config", value_name = "PATH", conflicts_with = "no_config")]
+    config: Option<PathBuf>,
+
+    /// Embed binary files up to 1 MiB as base64 instead of a placeholder
+    #[arg(long = "include-binary-base64", alias = "include-binary")]
+    include_binary_base64: bool,
+
+    /// Prefix tree entries with an icon for their type (📁 directories, 🦀 Rust, 🐍 Python, ...)
+    #[arg(long = "tree-icons")]
+    tree_icons: bool,
+
+    /// Disable tree icons, overriding `--tree-icons`. Setting `NO_COLOR` has the same effect
+    #[arg(long = "no-icons")]
+    no_icons: bool,
+
+    /// Check the filter, exclude and include patterns and exit without scanning
+    #[arg(long = "validate-patterns")]
+    validate_patterns: bool,
+
+    /// Apply the contents' exclusion rules to the directory tree without pruning it to the
+    /// final file selection
+    #[arg(long = "exclude-matching-gitignore-in-tree")]
+    tree_excludes: bool,
+
+    /// Hash store shared across runs; files whose content hash is recorded are replaced with
+    /// a note, and newly emitted hashes are appended
+    #[arg(long = "content-hash-dedup-across-runs", value_name = "STORE")]
+    hash_store: Option<PathBuf>,
+
+    /// Prepend a YAML front-matter block with the repository name, timestamp, file count
+    /// and estimated tokens to text and Markdown outputs
+    #[arg(long = "front-matter")]
+    front_matter: bool,
+
+    /// Split the contents into a source section followed by a test section
+    #[arg(long = "group-tests-separately")]
+    group_tests_separately: bool,
+
+    /// Split each output into numbered parts of at most N estimated tokens, e.g.
+    /// `repodump.001.txt`, breaking only between files
+    #[arg(
+        long = "split-tokens",
+        value_name = "N",
+        conflicts_with_all = ["group_tests_separately", "front_matter", "upload", "stdout"]
+    )]
+    split_tokens: Option<usize>,
+
+    /// Compress the output files, adding `.gz` or `.zst` to their names; outputs named so
+    /// are compressed without it
+    #[arg(
+        long = "compress",
+        value_enum,
+        value_name = "ALGORITHM",
+        conflicts_with = "stdout"
+    )]
+    compress: Option<compress::Compression>,
+
+    /// Canonicalize the target directory, resolving symlinks in its ancestry, before scanning
+    #[arg(long = "realpath", alias = "resolve-realpath")]
+    realpath: bool,
+
+    /// PUT the (first) output to this HTTP(S) URL using curl, in addition to writing it
+    #[arg(long = "upload", value_name = "URL")]
+    upload: Option<String>,
+
+    /// With `--upload`, do not also write the uploaded output locally
+    #[arg(long = "upload-only", requires = "upload")]
+    upload_only: bool,
+
+    /// Copy the (first) output to the system clipboard; it is only written to a file as well
+    /// when `--output` is given
+    #[arg(short = 'C', long = "clipboard", conflicts_with = "split_tokens")]
+    clipboard: bool,
+
+    /// Add a `git diff --stat` style summary of the files changed since this git revision
+    #[arg(long = "diff-stat", value_name = "REF")]
+    diff_stat: Option<String>,
+
+    /// Append a unified diff of the files changed since this git revision
+    #[arg(long = "diff", value_name = "REF")]
+    diff: Option<String>,
+
+    /// Also write the run summary to this file, even with --quiet (JSON for a `.json` path)
+    #[arg(long = "summary-file", value_name = "PATH")]
+    summary_file: Option<PathBuf>,
+
+    /// Show only the last N path components in file banners, e.g. `.../utils/helpers.rs`
+    #[arg(long = "max-path-depth-in-banner", value_name = "N")]
+    max_path_depth_in_banner: Option<usize>,
+
+    /// Use the built-in prompt for a common task; --prompt takes precedence
+    #[arg(long = "task", value_enum, value_name = "NAME")]
+    task: Option<Task>,
+
+    /// Show directories matching this glob as a single summary node in the tree and leave
+    /// their files out of the contents; may be repeated
+    #[arg(long = "collapse-dir", value_name = "GLOB")]
+    collapse_dir: Vec<String>,
+
+    /// Run this shell command in the root and include its output as a pseudo-file labeled
+    /// `<command: ...>`; may be repeated
+    #[arg(long = "run", value_name = "COMMAND")]
+    run: Vec<String>,
+
+    /// Build the directory structure from the files tracked in the git index instead of
+    /// walking the filesystem
+    #[arg(long = "tree-from-git")]
+    tree_from_git: bool,
+
+    /// Keep only files tracked in the git index, in both the tree and the contents
+    #[arg(long = "tracked-only")]
+    tracked_only: bool,
+
+    /// Weight the characters of lines longer than N characters by --long-line-factor when
+    /// estimating tokens, for minified and other dense content
+    #[arg(long = "long-line-threshold", value_name = "N")]
+    long_line_threshold: Option<usize>,
+
+    /// Token estimate weight of characters on lines over --long-line-threshold
+    #[arg(
+        long = "long-line-factor",
+        value_name = "FACTOR",
+        default_value_t = 1.5,
+        value_parser = parse_long_line_factor
+    )]
+    long_line_factor: f64,
+
+    /// Show sizes in the summary with KB/MB/GB units and one decimal
+    #[arg(long = "pretty-bytes")]
+    pretty_bytes: bool,
+
+    /// Show exact byte counts in the summary and the tree's collapsed directories, overriding
+    /// --pretty-bytes (e.g. from project defaults)
+    #[arg(long = "raw-bytes", overrides_with = "pretty_bytes")]
+    raw_bytes: bool,
+
+    /// Leave files whose estimated tokens exceed N out of the contents
+    #[arg(long = "max-file-tokens", value_name = "N")]
+    max_file_tokens: Option<usize>,
+
+    /// Leave files larger than this out of the contents, e.g. `500k` or `2M`
+    #[arg(long = "max-file-size", value_name = "SIZE", value_parser = parse_size)]
+    max_file_size: Option<u64>,
+
+    /// Leave files smaller than this out of the contents, e.g. `10` or `1k`
+    #[arg(long = "min-file-size", value_name = "SIZE", value_parser = parse_size)]
+    min_file_size: Option<u64>,
+
+    /// Replace file banners by prefixing every content line with `path:line: `
+    #[arg(long = "prefix-line-with-path")]
+    prefix_line_with_path: bool,
+
+    /// Prefix every line of file contents with its line number, as in `  42 | let x = 1;`
+    #[arg(long = "line-numbers", conflicts_with = "prefix_line_with_path")]
+    line_numbers: bool,
+
+    /// Fail instead of creating missing parent directories of the output files
+    #[arg(long = "no-create-dirs")]
+    no_create_dirs: bool,
+
+    /// Apply the gitignore-syntax rules of this file to the whole walk, relative to the
+    /// target directory; may be repeated
+    #[arg(long = "ignore-from", value_name = "FILE")]
+    ignore_from: Vec<PathBuf>,
+
+    /// Only print the file, line and byte totals of each language of the selected files
+    #[arg(long = "language-stats-only")]
+    language_stats_only: bool,
+
+    /// Banner placed before each file in text contents
+    #[arg(
+        long = "banner-style",
+        alias = "header-style",
+        value_enum,
+        default_value_t = BannerStyle::Equals
+    )]
+    banner_style: BannerStyle,
+
+    /// Custom banner placed before each file in text contents, overriding --banner-style;
+    /// `{path}`, `{size}`, `{tokens}` and `{lang}` are replaced and `\n` starts a new line
+    #[arg(long = "header-template", value_name = "TEMPLATE")]
+    header_template: Option<String>,
+
+    /// Threads used to read files and count their tokens (0 uses all CPU cores)
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    jobs: usize,
+
+    /// List the largest included files with their size and estimated tokens in the summary
+    #[arg(long = "stats")]
+    stats: bool,
+
+    /// Number of files listed by --stats and --report-excluded
+    #[arg(long = "stats-top", value_name = "N", default_value_t = 20)]
+    stats_top: usize,
+
+    /// Only include the definitions of this symbol, with their files' imports, from Rust,
+    /// Python, JavaScript/TypeScript and Go files
+    #[arg(
+        long = "symbol",
+        value_name = "NAME",
+        conflicts_with = "only_changed_lines"
+    )]
+    symbol: Option<String>,
+
+    /// Stop adding files once their estimated tokens would exceed N in total
+    #[arg(long = "max-tokens", value_name = "N")]
+    max_tokens: Option<usize>,
+
+    /// With --max-tokens, cut the first file that does not fit instead of skipping it
+    #[arg(long = "truncate-files", requires = "max_tokens")]
+    truncate_files: bool,
+
+    /// With --max-tokens, add files from the fewest tokens up to fit more of them
+    #[arg(long = "smallest-first", requires = "max_tokens")]
+    smallest_first: bool,
+
+    /// Rewrite text files with an extension through built-in transforms, as
+    /// EXT=TRANSFORM[,TRANSFORM...]. Repeatable
+    #[arg(long = "transform", value_name = "RULE", value_parser = transform::TransformRule::parse)]
+    transform: Vec<transform::TransformRule>,
+
+    /// Print the files that would be dumped with their estimated tokens, without reading
+    /// them into a dump or writing any output
+    #[arg(long = "dry-run")]
+    dry_run: bool,
+
+    /// Only descend this many directory levels below the target; 0 keeps only the files
+    /// directly inside it
+    #[arg(long = "max-depth", value_name = "N")]
+    max_depth: Option<usize>,
+
+    /// Match the filter, exclude, include and other glob patterns without regard to case
+    #[arg(long = "case-insensitive")]
+    case_insensitive: bool,
+
+    /// Descend into symlinked directories, skipping any symlink that loops back to one of
+    /// its ancestors
+    #[arg(long = "follow-symlinks")]
+    follow_symlinks: bool,
+
+    /// List the largest files left out by exclude globs, gitignore rules and other filters
+    /// in the summary
+    #[arg(long = "report-excluded")]
+    report_excluded: bool,
+
+    /// Open Markdown outputs with a table of contents even when they hold fewer than three
+    /// files
+    #[arg(long = "toc", conflicts_with = "no_toc")]
+    toc: bool,
+
+    /// Leave the table of contents out of Markdown outputs
+    #[arg(long = "no-toc")]
+    no_toc: bool,
+
+    /// Open the output with the current branch, HEAD commit and origin remote of the git
+    /// repository; left out when the target is not in a repository
+    #[arg(long = "git-info")]
+    git_info: bool,
+
+    /// Write `--format json` outputs on a single line instead of indented
+    #[arg(long = "compact")]
+    compact: bool,
+
+    /// Break the included files down by language in the summary, with their files, bytes
+    /// and share of the estimated tokens
+    #[arg(long = "lang-stats")]
+    lang_stats: bool,
+
+    /// After writing the outputs, keep watching the target directory and regenerate them
+    /// whenever files change
+    #[arg(long = "watch", conflicts_with_all = ["stdout", "dry_run", "language_stats_only"])]
+    watch: bool,
+
+    /// Branch or tag to check out when the path is a repository URL
+    #[arg(long, value_name = "NAME")]
+    branch: Option<String>,
+
+    /// Leave the temporary clone of a repository URL on disk instead of deleting it
+    #[arg(long)]
+    keep_clone: bool,
+
+    /// Include at most N files in the contents, in the chosen sort order
+    #[arg(long = "max-files", value_name = "N")]
+    max_files: Option<usize>,
+
+    /// Read every text file in this encoding, such as `latin1` or `utf-16le`, instead of
+    /// detecting it
+    #[arg(long, value_name = "LABEL", value_parser = content_type::parse_encoding)]
+    encoding: Option<&'static encoding_rs::Encoding>,
+
+    /// Pick the files to dump from a checklist of the filtered files before generating
+    #[arg(long, conflicts_with = "watch")]
+    interactive: bool,
+
+    /// Write each distinct file body once and replace later identical files with an
+    /// `[identical to PATH]` note
+    #[arg(long)]
+    dedup: bool,
+
+    /// Wrap each file body in a language-tagged code fence without switching to Markdown
+    /// headings
+    #[arg(long)]
+    fenced: bool,
+
+    /// Name shown for the root in the tree, the summary and JSON output instead of the
+    /// directory name
+    #[arg(
+        long = "root-name",
+        value_name = "NAME",
+        conflicts_with = "tree_root_path"
+    )]
+    root_name: Option<String>,
+
+    /// Show absolute paths in file banners instead of paths relative to the root; the tree
+    /// stays relative
+    #[arg(long = "absolute-paths", conflicts_with = "max_path_depth_in_banner")]
+    absolute_paths: bool,
+
+    /// List the lines and non-blank lines of each language in the summary
+    #[arg(long)]
+    sloc: bool,
+
+    /// Append the last N commits of the current branch, with their full messages
+    #[arg(long = "git-log", value_name = "N")]
+    git_log: Option<usize>,
+
+    /// Show the --git-log dates as relative durations such as `3 hours ago` instead of UTC
+    /// timestamps; JSON keeps the timestamps
+    #[arg(long = "relative-time", requires = "git_log")]
+    relative_time: bool,
+}
+
+/// Rendering format for a section of the output file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Plain text with `FILE:` banners
+    #[default]
+    Text,
+    /// Markdown headings with fenced code blocks
+    Markdown,
+    /// A single JSON document; only valid for `--format`
+    Json,
+    /// `<directory_structure>` and `<documents>` elements, the layout Claude prompts favor
+    Xml,
+}
+
+impl OutputFormat {
+    /// File extension conventionally used for the format
+    fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Text => "txt",
+            OutputFormat::Markdown => "md",
+            OutputFormat::Json => "json",
+            OutputFormat::Xml => "xml",
+        }
+    }
+
+    /// Infers the format from an output path's extension, defaulting to text; a compression
+    /// extension such as `.gz` is looked through
+    fn from_path(path: &Path) -> OutputFormat {
+        let (path, _) = compress::strip_extension(path);
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        match extension.as_str() {
+            "md" | "markdown" => OutputFormat::Markdown,
+            "json" => OutputFormat::Json,
+            "xml" => OutputFormat::Xml,
+            _ => OutputFormat::Text,
+        }
+    }
+}
+
+/// Output path used when no `--output` is given
+const DEFAULT_OUTPUT: &str = "repodump.txt";
+
+/// Where the prompt is placed in the output
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+enum PromptPosition {
+    /// Before everything else, ahead of the directory tree
+    Top,
+    /// After everything else
+    #[default]
+    Bottom,
+}
+
+/// Banner placed before each file in text contents
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+enum BannerStyle {
+    /// `FILE: path` between lines of `=` signs
+    #[default]
+    #[value(alias = "banner")]
+    Equals,
+    /// A `## path` heading and a fenced code block
+    Markdown,
+    /// A single `--- path ---` line
+    Minimal,
+    /// The body wrapped in `<file path="...">` and `</file>`
+    Xml,
+    /// A `// FILE: path` comment line
+    Comment,
+}
+
+impl BannerStyle {
+    /// The banner style used for contents rendered in a format
+    ///
+    /// # Arguments
+    /// * `format` - The contents format
+    /// * `text_style` - The `--banner-style` chosen for text contents
+    fn for_format(format: OutputFormat, text_style: BannerStyle) -> BannerStyle {
+        match format {
+            OutputFormat::Markdown => BannerStyle::Markdown,
+            OutputFormat::Text | OutputFormat::Json | OutputFormat::Xml => text_style,
+        }
+    }
+}
+
+/// Pairs each output path with the format it is rendered in
+///
+/// * No `--format`: a single output is text; several outputs take their format from their
+///   extension (`.md`, `.json`, `.xml`, otherwise text).
+/// * Several formats and a single output: the output's extension is replaced by each
+///   format's (`txt`, `md`, `json`, `xml`).
+/// * Otherwise outputs and formats are paired in order and their counts must match.
+///
+/// Outputs given twice and outputs that are existing directories are rejected.
+///
+/// # Arguments
+/// * `outputs` - The `--output` values, empty for the default
+/// * `formats` - The `--format` values, empty if none were given
+///
+/// # Returns
+/// The `(path, format)` pairs to render, or an error if they cannot be paired
+fn resolve_outputs(
+    outputs: &[PathBuf],
+    formats: &[OutputFormat],
+) -> Result<Vec<(PathBuf, OutputFormat)>> {
+    let default_output = [PathBuf::from(DEFAULT_OUTPUT)];
+    let outputs = if outputs.is_empty() {
+        &default_output[..]
+    } else {
+        outputs
+    };
+
+    let pairs: Vec<(PathBuf, OutputFormat)> = match (outputs, formats) {
+        ([output], []) => vec![(output.clone(), OutputFormat::Text)],
+        (outputs, []) => outputs
+            .iter()
+            .map(|output| (output.clone(), OutputFormat::from_path(output)))
+            .collect(),
+        ([output], formats) if formats.len() > 1 && !is_stdout_output(output) => formats
+            .iter()
+            .map(|format| (output.with_extension(format.extension()), *format))
+            .collect(),
+        (outputs, formats) if outputs.len() == formats.len() => outputs
+            .iter()
+            .cloned()
+            .zip(formats.iter().copied())
+            .collect(),
+        _ => anyhow::bail!(ExitError::new(
+            ExitStatus::BadArguments,
+            format!(
+                "Cannot pair {} output(s) with {} format(s); give one --output per --format",
+                outputs.len(),
+                formats.len()
+            ),
+        )),
+    };
+
+    let mut seen = BTreeSet::new();
+    for (path, format) in &pairs {
+        if !seen.insert(path) {
+            anyhow::bail!(ExitError::new(
+                ExitStatus::BadArguments,
+                format!("Output {} is given more than once", path.display()),
+            ));
+        }
+        if path.is_dir() {
+            let suggestion = path.join(DEFAULT_OUTPUT).with_extension(format.extension());
+            anyhow::bail!(ExitError::new(
+                ExitStatus::BadArguments,
+                format!(
+                    "Output {} is a directory; give a file name such as {}",
+                    path.display(),
+                    suggestion.display()
+                ),
+            ));
+        }
+    }
+    Ok(pairs)
 }
 
+/// Filter patterns selecting the files of `--overview`: READMEs and other documentation
+/// anywhere, plus the project manifests and build files in the root
+const OVERVIEW_PATTERNS: &[&str] = &[
+    "README",
+    "README.*",
+    "*/README",
+    "*/README.*",
+    "*.md",
+    "*.rst",
+    "*.adoc",
+    "docs/*",
+    "doc/*",
+    "Cargo.toml",
+    "package.json",
+    "pyproject.toml",
+    "setup.py",
+    "setup.cfg",
+    "go.mod",
+    "pom.xml",
+    "build.gradle",
+    "Makefile",
+    "Dockerfile",
+    "CMakeLists.txt",
+    "tsconfig.json",
+];
+
 /// Represents file filtering configuration
 struct FileFilter {
     filter_globs: globset::GlobSet,
     exclude_globs: globset::GlobSet,
     include_globs: globset::GlobSet,
+    include_patterns: Vec<String>,
+    /// Exact relative paths to exclude, compared without glob interpretation
+    excluded_paths: BTreeSet<PathBuf>,
+    /// Whether the globs ignore case
+    case_insensitive: bool,
 }
 
 impl FileFilter {
@@ -71,27 +805,57 @@ impl FileFilter {
     /// * `filter` - Patterns for files to include (if empty, all files pass filter)
     /// * `exclude` - Patterns for files to exclude
     /// * `include` - Patterns for files to force include
+    /// * `case_insensitive` - Whether the patterns ignore case, so `*.RS` matches `main.rs`
     ///
     /// # Examples
     /// ```
     /// let filter = FileFilter::new(
     ///     vec!["*.rs".to_string()],
     ///     vec!["target/*".to_string()],
-    ///     vec!["Cargo.toml".to_string()]
+    ///     vec!["Cargo.toml".to_string()],
+    ///     false,
     /// ).unwrap();
     /// ```
-    fn new(filter: Vec<String>, exclude: Vec<String>, include: Vec<String>) -> Result<Self> {
-        let filter_globs = build_globset(filter)?;
-        let exclude_globs = build_globset(exclude)?;
-        let include_globs = build_globset(include)?;
+    fn new(
+        filter: Vec<String>,
+        exclude: Vec<String>,
+        include: Vec<String>,
+        case_insensitive: bool,
+    ) -> Result<Self> {
+        let filter_globs = build_globset(filter, case_insensitive)?;
+        let exclude_globs = build_globset(exclude, case_insensitive)?;
+        let include_globs = build_globset(include.clone(), case_insensitive)?;
 
         Ok(FileFilter {
             filter_globs,
             exclude_globs,
             include_globs,
+            include_patterns: include,
+            excluded_paths: BTreeSet::new(),
+            case_insensitive,
         })
     }
 
+    /// Adds exact relative paths to exclude, such as those read by `--exclude-paths-file`
+    ///
+    /// # Arguments
+    /// * `paths` - Paths relative to the root; glob metacharacters in them are literal
+    fn with_excluded_paths(mut self, paths: BTreeSet<PathBuf>) -> Self {
+        self.excluded_paths = paths;
+        self
+    }
+
+    /// Determines if a file matches a force-include pattern
+    ///
+    /// # Arguments
+    /// * `path` - The file path to check
+    ///
+    /// # Returns
+    /// `true` if the file is included regardless of excludes and gitignore rules
+    fn is_force_included(&self, path: &Path) -> bool {
+        self.include_globs.is_match(path)
+    }
+
     /// Determines if a file should be included based on filtering rules
     ///
     /// # Arguments
@@ -102,20 +866,20 @@ impl FileFilter {
     ///
     /// # Examples
     /// ```
-    /// let filter = FileFilter::new(vec![], vec!["*.tmp".to_string()], vec![]).unwrap();
+    /// let filter = FileFilter::new(vec![], vec!["*.tmp".to_string()], vec![], false).unwrap();
     /// assert!(!filter.should_include("temp.tmp"));
     /// assert!(filter.should_include("main.rs"));
     /// ```
     fn should_include(&self, path: &Path) -> bool {
         // Step 1: Apply filter patterns (if any exist)
-        if self.filter_globs.len() > 0 && !self.filter_globs.is_match(&path) {
+        if !self.filter_globs.is_empty() && !self.filter_globs.is_match(path) {
             return false;
         }
 
-        // Step 2: Apply exclude patterns
-        if self.exclude_globs.is_match(&path) {
+        // Step 2: Apply exclude patterns and exact excluded paths
+        if self.exclude_globs.is_match(path) || self.excluded_paths.contains(path) {
             // Step 3: Check if include patterns override exclusion
-            return self.include_globs.is_match(&path);
+            return self.include_globs.is_match(path);
         }
 
         true
@@ -126,19 +890,54 @@ impl FileFilter {
 ///
 /// # Arguments
 /// * `patterns` - Vector of glob pattern strings
+/// * `case_insensitive` - Whether the globs ignore case
 ///
 /// # Returns
 /// A compiled GlobSet or an error if patterns are invalid
-fn build_globset(patterns: Vec<String>) -> Result<globset::GlobSet> {
+fn build_globset(patterns: Vec<String>, case_insensitive: bool) -> Result<globset::GlobSet> {
     let mut builder = GlobSetBuilder::new();
     for pattern in patterns {
-        let glob =
-            Glob::new(&pattern).with_context(|| format!("Invalid glob pattern: {}", pattern))?;
+        let glob = GlobBuilder::new(&pattern)
+            .case_insensitive(case_insensitive)
+            .build()
+            .with_context(|| {
+                ExitError::new(
+                    ExitStatus::BadArguments,
+                    format!("Invalid glob pattern: {}", pattern),
+                )
+            })?;
         builder.add(glob);
     }
     builder.build().context("Failed to build glob set")
 }
 
+/// Checks every pattern of several option groups, collecting all problems instead of
+/// stopping at the first
+///
+/// # Arguments
+/// * `groups` - Option names paired with the patterns given for them
+///
+/// # Returns
+/// One message per invalid pattern, empty if all patterns compile
+///
+/// # Examples
+/// ```
+/// let filters = vec!["*.rs".to_string(), "src/[".to_string()];
+/// let problems = validate_patterns(&[("--filter", &filters)]);
+/// assert_eq!(problems.len(), 1);
+/// ```
+fn validate_patterns(groups: &[(&str, &[String])]) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (option, patterns) in groups {
+        for pattern in patterns.iter() {
+            if let Err(error) = build_globset(vec![pattern.clone()], false) {
+                problems.push(format!("{}: {:#}", option, error));
+            }
+        }
+    }
+    problems
+}
+
 /// Determines the target directory to process
 ///
 /// # Arguments
@@ -150,7 +949,10 @@ fn resolve_target_directory(path_arg: Option<PathBuf>) -> Result<PathBuf> {
     match path_arg {
         Some(path) => {
             if !path.exists() {
-                anyhow::bail!("Directory does not exist: {}", path.display());
+                anyhow::bail!(ExitError::new(
+                    ExitStatus::PathNotFound,
+                    format!("Directory does not exist: {}", path.display()),
+                ));
             }
             Ok(path)
         }
@@ -165,31 +967,255 @@ fn resolve_target_directory(path_arg: Option<PathBuf>) -> Result<PathBuf> {
                     Ok(repo_root.to_path_buf())
                 }
                 Err(_) => {
-                    anyhow::bail!("The current directory is not a git repository. For use outside of git repositories, please provide a directory path.");
+                    anyhow::bail!(ExitError::new(ExitStatus::PathNotFound, "The current directory is not a git repository. For use outside of git repositories, please provide a directory path."));
                 }
             }
         }
     }
 }
 
+/// Shows the interactive file picker on the terminal
+///
+/// # Arguments
+/// * `files` - The files left after filtering, which all start selected
+///
+/// # Returns
+/// The files the user kept, or an error if there is no terminal, the user cancels or
+/// nothing is selected
+fn pick_interactively(files: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    if files.is_empty() {
+        anyhow::bail!(ExitError::new(
+            ExitStatus::NoFilesMatched,
+            "No files to pick from",
+        ));
+    }
+    // The terminal is opened directly, since stdin may carry a file list or prompt
+    let terminal_path = if cfg!(windows) { "CONIN$" } else { "/dev/tty" };
+    let terminal = fs::File::open(terminal_path).map_err(|_| {
+        ExitError::new(
+            ExitStatus::BadArguments,
+            "--interactive needs a terminal to read the selection from",
+        )
+    })?;
+    let picked = picker::pick_files(files, &mut io::BufReader::new(terminal), &mut io::stderr())?;
+    match picked {
+        None => anyhow::bail!(ExitError::new(ExitStatus::Failure, "Selection cancelled")),
+        Some(picked) if picked.is_empty() => anyhow::bail!(ExitError::new(
+            ExitStatus::NoFilesMatched,
+            "No files selected",
+        )),
+        Some(picked) => Ok(picked),
+    }
+}
+
+/// Shallow-clones a remote repository into a temporary directory
+///
+/// # Arguments
+/// * `url` - The repository URL given as the path
+/// * `branch` - Branch or tag to check out, from `--branch`
+/// * `keep` - Whether to leave the clone on disk afterwards, from `--keep-clone`
+///
+/// # Returns
+/// The path of the clone, and the temporary directory that removes it when dropped, or `None`
+/// if it is kept
+fn clone_remote(
+    url: &str,
+    branch: Option<&str>,
+    keep: bool,
+) -> Result<(PathBuf, Option<tempfile::TempDir>)> {
+    let clone_dir = tempfile::Builder::new()
+        .prefix("repodump-")
+        .tempdir()
+        .context("Failed to create a directory for the clone")?;
+    // Clone into a subdirectory so the dump is named after the repository
+    let clone_path = clone_dir.path().join(git::remote_repo_name(url));
+    git::shallow_clone(url, branch, &clone_path)
+        .map_err(|error| ExitError::new(ExitStatus::PathNotFound, format!("{:#}", error)))?;
+    if keep {
+        let kept_path = clone_dir.keep().join(git::remote_repo_name(url));
+        eprintln!("Kept clone at {}", kept_path.display());
+        Ok((kept_path, None))
+    } else {
+        Ok((clone_path, Some(clone_dir)))
+    }
+}
+
+/// Options controlling how the directory walk is performed
+#[derive(Default)]
+struct WalkOptions {
+    /// Whether to ignore .gitignore files
+    ignore_gitignore: bool,
+    /// Prune directories with more than this many direct children
+    skip_huge_dirs: Option<usize>,
+    /// Directories, relative to the root, that are never descended into
+    excluded_dirs: Vec<PathBuf>,
+    /// Directories matching these globs are summarized instead of descended into
+    collapse_dirs: Option<globset::GlobSet>,
+    /// Rules from `--ignore-from` files, matched relative to the root
+    extra_ignores: Option<ignore::gitignore::Gitignore>,
+    /// Deepest directory level descended into below the root; 0 keeps only the root's
+    /// direct children
+    max_depth: Option<usize>,
+    /// Whether to descend into symlinked directories
+    follow_symlinks: bool,
+    /// Counts the files found, or `None` to walk silently
+    progress: Option<Arc<progress::Progress>>,
+}
+
+/// The result of walking a directory
+#[derive(Default)]
+struct CollectedFiles {
+    /// Relative paths of files that passed the filter, sorted
+    files: Vec<PathBuf>,
+    /// Relative paths of directories pruned by `skip_huge_dirs`, with their entry counts
+    skipped_dirs: Vec<(PathBuf, usize)>,
+    /// Included files that are symlinks, mapped to their resolved targets
+    symlinks: BTreeMap<PathBuf, PathBuf>,
+    /// Included files that are symlinks, whether or not their target resolves
+    symlinked_files: BTreeSet<PathBuf>,
+    /// Relative paths of directories matched by `collapse_dirs`, with their file count and
+    /// total size in bytes
+    collapsed_dirs: Vec<(PathBuf, usize, u64)>,
+    /// Symlinked directories not descended into because they lead back to an ancestor,
+    /// relative to the root
+    symlink_cycles: Vec<PathBuf>,
+}
+
 /// Collects all files in the directory that pass the filter
 ///
+/// Force-include patterns also bring back files hidden by `.gitignore`: after the main walk,
+/// literal patterns are checked directly and the remaining patterns are searched for in the
+/// directory named by their wildcard-free prefix, with gitignore rules disabled.
+///
 /// # Arguments
 /// * `root_path` - The root directory to scan
 /// * `filter` - The file filter to apply
-/// * `ignore_gitignore` - Whether to ignore .gitignore files
+/// * `options` - Options controlling the walk
 ///
 /// # Returns
-/// A vector of file paths that should be included
+/// The file paths that should be included, plus any directories pruned during the walk
 fn collect_files(
     root_path: &Path,
     filter: &FileFilter,
-    ignore_gitignore: bool,
-) -> Result<Vec<PathBuf>> {
-    let mut builder = WalkBuilder::new(root_path);
+    options: &WalkOptions,
+) -> Result<CollectedFiles> {
+    let mut collected = walk_files(root_path, root_path, options, &|path| {
+        filter.should_include(path)
+    })?;
+    if options.ignore_gitignore || filter.include_patterns.is_empty() {
+        return Ok(collected);
+    }
+
+    let mut files: BTreeSet<PathBuf> = collected.files.drain(..).collect();
+    let mut walk_roots = BTreeSet::new();
+    for pattern in &filter.include_patterns {
+        let (prefix, is_literal) = literal_glob_prefix(pattern);
+        if filter.case_insensitive {
+            // The prefix may name the directory in another case, so search from the root
+            walk_roots.insert(PathBuf::new());
+        } else if is_literal {
+            if root_path.join(&prefix).is_file()
+                && filter.is_force_included(&prefix)
+                && filter.should_include(&prefix)
+            {
+                files.insert(prefix);
+            }
+        } else {
+            walk_roots.insert(prefix);
+        }
+    }
+
+    let unignored_options = WalkOptions {
+        ignore_gitignore: true,
+        skip_huge_dirs: options.skip_huge_dirs,
+        excluded_dirs: options.excluded_dirs.clone(),
+        collapse_dirs: options.collapse_dirs.clone(),
+        extra_ignores: None,
+        max_depth: options.max_depth,
+        follow_symlinks: options.follow_symlinks,
+        progress: options.progress.clone(),
+    };
+    for walk_root in walk_roots {
+        let start = root_path.join(&walk_root);
+        if !start.is_dir() {
+            continue;
+        }
+        // Filter patterns still apply to the files the include patterns reach
+        let forced = walk_files(root_path, &start, &unignored_options, &|path| {
+            filter.is_force_included(path) && filter.should_include(path)
+        })?;
+        files.extend(forced.files);
+        collected.symlinks.extend(forced.symlinks);
+        collected.symlinked_files.extend(forced.symlinked_files);
+        for cycle in forced.symlink_cycles {
+            if !collected.symlink_cycles.contains(&cycle) {
+                collected.symlink_cycles.push(cycle);
+            }
+        }
+    }
+
+    collected.files = files.into_iter().collect();
+    Ok(collected)
+}
+
+/// Splits a glob pattern into its leading components that contain no wildcards
+///
+/// # Arguments
+/// * `pattern` - A glob pattern relative to the root
+///
+/// # Returns
+/// The wildcard-free directory prefix, or the whole path and `true` if the pattern is a
+/// literal path
+///
+/// # Examples
+/// ```
+/// assert_eq!(literal_glob_prefix("logs/**/*.log"), (PathBuf::from("logs"), false));
+/// assert_eq!(literal_glob_prefix("important.log"), (PathBuf::from("important.log"), true));
+/// ```
+fn literal_glob_prefix(pattern: &str) -> (PathBuf, bool) {
+    let components: Vec<&str> = pattern
+        .split('/')
+        .filter(|component| !component.is_empty())
+        .collect();
+    let literal_count = components
+        .iter()
+        .take_while(|component| !component.contains(['*', '?', '[', '{', '\\']))
+        .count();
+    let prefix = components[..literal_count].iter().collect();
+    (prefix, literal_count == components.len())
+}
+
+/// Walks a directory below the root, keeping the files accepted by `keep`
+///
+/// # Arguments
+/// * `root_path` - The root directory that paths are made relative to
+/// * `start` - The directory to walk, the root itself or one of its subdirectories
+/// * `options` - Options controlling the walk
+/// * `keep` - Decides from the relative path whether a file is collected
+///
+/// # Returns
+/// The kept file paths, plus any directories pruned during the walk
+fn walk_files(
+    root_path: &Path,
+    start: &Path,
+    options: &WalkOptions,
+    keep: &dyn Fn(&Path) -> bool,
+) -> Result<CollectedFiles> {
+    let mut builder = WalkBuilder::new(start);
     builder.hidden(false); // Include hidden files by default
+    builder.follow_links(options.follow_symlinks);
+    if let Some(max_depth) = options.max_depth {
+        // The walk may start below the root, but depth is counted from the root
+        let start_depth = start
+            .strip_prefix(root_path)
+            .map_or(0, |relative| relative.components().count());
+        if start_depth > max_depth {
+            return Ok(CollectedFiles::default());
+        }
+        builder.max_depth(Some(max_depth + 1 - start_depth));
+    }
 
-    if ignore_gitignore {
+    if options.ignore_gitignore {
         builder.git_ignore(false);
         builder.git_exclude(false);
         builder.git_global(false);
@@ -198,10 +1224,88 @@ fn collect_files(
         builder.add_custom_ignore_filename(".gitignore");
     }
 
+    let skipped_dirs = Arc::new(Mutex::new(Vec::new()));
+    let collapsed_dirs = Arc::new(Mutex::new(Vec::new()));
+    if options.skip_huge_dirs.is_some()
+        || !options.excluded_dirs.is_empty()
+        || options.collapse_dirs.is_some()
+        || options.extra_ignores.is_some()
+    {
+        let skipped_dirs = Arc::clone(&skipped_dirs);
+        let collapsed_dirs = Arc::clone(&collapsed_dirs);
+        let excluded_dirs: BTreeSet<PathBuf> = options
+            .excluded_dirs
+            .iter()
+            .map(|dir| root_path.join(dir))
+            .collect();
+        let skip_huge_dirs = options.skip_huge_dirs;
+        let collapse_dirs = options.collapse_dirs.clone();
+        let extra_ignores = options.extra_ignores.clone();
+        let root = root_path.to_path_buf();
+        builder.filter_entry(move |entry| {
+            if entry.depth() == 0 {
+                return true;
+            }
+            let is_dir = entry.file_type().is_some_and(|ft| ft.is_dir());
+            if extra_ignores
+                .as_ref()
+                .is_some_and(|rules| rules.matched(entry.path(), is_dir).is_ignore())
+            {
+                return false;
+            }
+            if !is_dir {
+                return true;
+            }
+            if excluded_dirs.contains(entry.path()) {
+                return false;
+            }
+            if let (Some(globs), Ok(relative_path)) =
+                (&collapse_dirs, entry.path().strip_prefix(&root))
+            {
+                if globs.is_match(relative_path) {
+                    collapsed_dirs
+                        .lock()
+                        .unwrap()
+                        .push(relative_path.to_path_buf());
+                    return false;
+                }
+            }
+            let Some(threshold) = skip_huge_dirs else {
+                return true;
+            };
+            let child_count = match fs::read_dir(entry.path()) {
+                Ok(children) => children.count(),
+                Err(_) => return true,
+            };
+            if child_count > threshold {
+                skipped_dirs
+                    .lock()
+                    .unwrap()
+                    .push((entry.path().to_path_buf(), child_count));
+                return false;
+            }
+            true
+        });
+    }
+
     let mut files = Vec::new();
+    let mut symlinks = BTreeMap::new();
+    let mut symlinked_files = BTreeSet::new();
+    let mut symlink_cycles = Vec::new();
 
     for result in builder.build() {
-        let entry = result.context("Failed to read directory entry")?;
+        let entry = match result {
+            Ok(entry) => entry,
+            Err(error) => match symlink_loop(&error) {
+                // The walker refuses to re-enter an ancestor; note the link and move on
+                Some(link) => {
+                    let relative_link = link.strip_prefix(root_path).unwrap_or(link);
+                    symlink_cycles.push(relative_link.to_path_buf());
+                    continue;
+                }
+                None => return Err(error).context("Failed to read directory entry"),
+            },
+        };
         let path = entry.path();
 
         if path.is_file() {
@@ -209,48 +1313,328 @@ fn collect_files(
                 .strip_prefix(root_path)
                 .context("Failed to create relative path")?;
 
-            if filter.should_include(relative_path) {
+            if keep(relative_path) {
+                if entry.path_is_symlink() {
+                    if let Some(target) = resolve_symlink_target(root_path, path) {
+                        symlinks.insert(relative_path.to_path_buf(), target);
+                    }
+                    symlinked_files.insert(relative_path.to_path_buf());
+                }
                 files.push(relative_path.to_path_buf());
+                if let Some(progress) = &options.progress {
+                    progress.inc();
+                }
             }
         }
     }
 
     files.sort();
-    Ok(files)
+
+    let mut skipped_dirs = std::mem::take(&mut *skipped_dirs.lock().unwrap())
+        .into_iter()
+        .filter_map(|(path, count)| {
+            let relative_path = path.strip_prefix(root_path).ok()?.to_path_buf();
+            Some((relative_path, count))
+        })
+        .collect::<Vec<_>>();
+    skipped_dirs.sort();
+
+    let mut collapsed_dirs = std::mem::take(&mut *collapsed_dirs.lock().unwrap());
+    collapsed_dirs.sort();
+    let collapsed_dirs = collapsed_dirs
+        .into_iter()
+        .map(|dir| {
+            let (file_count, total_bytes) =
+                directory_stats(&root_path.join(&dir), options.ignore_gitignore);
+            (dir, file_count, total_bytes)
+        })
+        .collect();
+
+    Ok(CollectedFiles {
+        files,
+        skipped_dirs,
+        symlinks,
+        symlinked_files,
+        collapsed_dirs,
+        symlink_cycles,
+    })
 }
 
-/// Generates a directory tree structure as a string
+/// Finds the files a walk left out, for `--report-excluded`
+///
+/// The root is walked again with gitignore rules disabled, so files hidden by `.gitignore`,
+/// `--ignore-from` or an exclude glob are all found. Directories pruned by the walk options
+/// and version control metadata under `.git` are not reported.
 ///
 /// # Arguments
-/// * `root_path` - The root directory
-/// * `files` - List of files to include in the tree
+/// * `root_path` - The root directory that was scanned
+/// * `kept` - The files the walk collected
+/// * `options` - The options of the walk that collected `kept`
 ///
 /// # Returns
-/// A formatted directory tree string
-fn generate_directory_tree(root_path: &Path, files: &[PathBuf]) -> Result<String> {
-    let mut tree = String::new();
-    let root_name = root_path
-        .file_name()
-        .unwrap_or_else(|| std::ffi::OsStr::new("root"))
-        .to_string_lossy();
-
-    tree.push_str("Directory Structure:\n");
-    tree.push_str(&format!("{}/\n", root_name));
-
-    if files.is_empty() {
-        return Ok(tree);
+/// The excluded files with their sizes in bytes, largest first
+fn collect_excluded_files(
+    root_path: &Path,
+    kept: &[PathBuf],
+    options: &WalkOptions,
+) -> Result<Vec<(PathBuf, u64)>> {
+    let kept: BTreeSet<&PathBuf> = kept.iter().collect();
+    let unignored_options = WalkOptions {
+        ignore_gitignore: true,
+        skip_huge_dirs: options.skip_huge_dirs,
+        excluded_dirs: options.excluded_dirs.clone(),
+        collapse_dirs: options.collapse_dirs.clone(),
+        extra_ignores: None,
+        max_depth: options.max_depth,
+        follow_symlinks: options.follow_symlinks,
+        progress: None,
+    };
+    let walked = walk_files(root_path, root_path, &unignored_options, &|path| {
+        !kept.contains(&path.to_path_buf())
+            && !path
+                .components()
+                .any(|component| component.as_os_str() == ".git")
+    })?;
+    let mut excluded: Vec<(PathBuf, u64)> = walked
+        .files
+        .into_iter()
+        .map(|file| {
+            let size = fs::metadata(root_path.join(&file)).map_or(0, |metadata| metadata.len());
+            (file, size)
+        })
+        .collect();
+    excluded.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    Ok(excluded)
+}
+
+/// Finds the symlink that closed a loop in a walk error
+///
+/// # Arguments
+/// * `error` - An error reported by the walker
+///
+/// # Returns
+/// The path of the symlink leading back to an ancestor, or `None` for other errors
+fn symlink_loop(error: &ignore::Error) -> Option<&Path> {
+    match error {
+        ignore::Error::Loop { child, .. } => Some(child),
+        ignore::Error::WithPath { err, .. }
+        | ignore::Error::WithDepth { err, .. }
+        | ignore::Error::WithLineNumber { err, .. } => symlink_loop(err),
+        _ => None,
     }
+}
 
-    // Build a hierarchical structure
-    use std::collections::{BTreeMap, BTreeSet};
+/// Loads gitignore-syntax rules from files given with `--ignore-from`
+///
+/// # Arguments
+/// * `root_path` - The directory the rules are relative to, as if the files were its `.gitignore`
+/// * `paths` - The ignore files; later files take precedence
+///
+/// # Returns
+/// The combined rules, or an error if a file cannot be read or holds an invalid pattern
+fn load_ignore_files(root_path: &Path, paths: &[PathBuf]) -> Result<ignore::gitignore::Gitignore> {
+    let mut builder = ignore::gitignore::GitignoreBuilder::new(root_path);
+    for path in paths {
+        if !path.is_file() {
+            anyhow::bail!(ExitError::new(
+                ExitStatus::PathNotFound,
+                format!("Ignore file not found: {}", path.display()),
+            ));
+        }
+        if let Some(error) = builder.add(path) {
+            anyhow::bail!(ExitError::new(
+                ExitStatus::BadArguments,
+                format!("Invalid ignore file {}: {}", path.display(), error),
+            ));
+        }
+    }
+    builder.build().context("Failed to build ignore rules")
+}
+
+/// Counts the files below a directory and their total size
+///
+/// # Arguments
+/// * `dir` - The directory to measure
+/// * `ignore_gitignore` - Whether files hidden by `.gitignore` are counted too
+///
+/// # Returns
+/// The number of files and their combined size in bytes; unreadable entries are skipped
+fn directory_stats(dir: &Path, ignore_gitignore: bool) -> (usize, u64) {
+    let mut builder = WalkBuilder::new(dir);
+    builder.hidden(false);
+    if ignore_gitignore {
+        builder.git_ignore(false);
+        builder.git_exclude(false);
+        builder.git_global(false);
+    } else {
+        builder.add_custom_ignore_filename(".gitignore");
+    }
+
+    builder
+        .build()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_some_and(|ft| ft.is_file()))
+        .filter_map(|entry| entry.metadata().ok())
+        .fold((0, 0), |(count, bytes), metadata| {
+            (count + 1, bytes + metadata.len())
+        })
+}
+
+/// Resolves the real path a symlink points at
+///
+/// # Arguments
+/// * `root_path` - The root directory being scanned
+/// * `link_path` - The full path of the symlink
+///
+/// # Returns
+/// The target relative to the root if it lies inside it, the absolute target otherwise,
+/// or `None` if the link cannot be resolved
+fn resolve_symlink_target(root_path: &Path, link_path: &Path) -> Option<PathBuf> {
+    let target = fs::canonicalize(link_path).ok()?;
+    let canonical_root = fs::canonicalize(root_path).ok()?;
+    match target.strip_prefix(&canonical_root) {
+        Ok(relative_target) => Some(relative_target.to_path_buf()),
+        Err(_) => Some(target),
+    }
+}
+
+/// Formats a count with thousands separators, e.g. `4200` becomes `4,200`
+///
+/// # Examples
+/// ```
+/// assert_eq!(format_count(4200), "4,200");
+/// ```
+fn format_count(count: usize) -> String {
+    let digits = count.to_string();
+    let mut formatted = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted
+}
+
+/// Formats a byte count with binary units and one decimal, e.g. `3250585` becomes `3.1 MB`
+///
+/// # Examples
+/// ```
+/// assert_eq!(format_size(512), "512 B");
+/// assert_eq!(format_size(3250585), "3.1 MB");
+/// ```
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Parses a byte count with an optional binary unit, as given to `--max-file-size`
+///
+/// # Examples
+/// ```
+/// assert_eq!(parse_size("500k"), Ok(512000));
+/// assert_eq!(parse_size("1.5MB"), Ok(1572864));
+/// assert_eq!(parse_size("200"), Ok(200));
+/// ```
+fn parse_size(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("expected a size such as 500k or 2M, got `{}`", text))?;
+    let multiplier: u64 = match unit.trim().to_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        _ => return Err(format!("unknown size unit `{}`; use k, M or G", unit)),
+    };
+    Ok((number * multiplier as f64).round() as u64)
+}
+
+/// Parses the weight given to `--long-line-factor`
+///
+/// The penalty only ever raises an estimate, so factors below 1 are refused, as are
+/// infinities and NaN.
+///
+/// # Examples
+/// ```
+/// assert_eq!(parse_long_line_factor("2.5"), Ok(2.5));
+/// assert!(parse_long_line_factor("0.5").is_err());
+/// ```
+fn parse_long_line_factor(text: &str) -> Result<f64, String> {
+    let factor: f64 = text
+        .trim()
+        .parse()
+        .map_err(|_| format!("expected a number such as 1.5, got `{}`", text))?;
+    if !factor.is_finite() || factor < 1.0 {
+        return Err(format!(
+            "the factor must be a finite number of at least 1, got `{}`",
+            text
+        ));
+    }
+    Ok(factor)
+}
+
+/// Formats a byte count for people with `pretty` set, or exactly for scripts
+///
+/// # Examples
+/// ```
+/// assert_eq!(display_size(3250585, true), "3.1 MB");
+/// assert_eq!(display_size(3250585, false), "3250585 bytes");
+/// ```
+fn display_size(bytes: u64, pretty: bool) -> String {
+    if pretty {
+        format_size(bytes)
+    } else {
+        format!("{} bytes", bytes)
+    }
+}
+
+/// Options controlling how the directory tree is rendered
+#[derive(Default)]
+struct TreeOptions {
+    /// The format used to render the tree
+    format: OutputFormat,
+    /// Directories shown as leaf nodes followed by a note instead of being expanded
+    collapsed_dirs: BTreeMap<PathBuf, String>,
+    /// Symlinked files mapped to the real path they point at, shown as `link -> target`
+    symlink_targets: BTreeMap<PathBuf, PathBuf>,
+    /// Label the root with its absolute path instead of the bare directory name
+    root_path_label: bool,
+    /// Label the root with this name instead of the directory name, from `--root-name`
+    root_name: Option<String>,
+    /// Prefix each entry with an icon for its type
+    icons: bool,
+}
 
+/// Builds the directory hierarchy of a set of file paths
+///
+/// # Arguments
+/// * `paths` - Relative paths of the files (and collapsed directories) in the tree
+///
+/// # Returns
+/// A map from each directory's relative path (`""` for the root) to its children's paths
+fn build_directory_children<'a>(
+    paths: impl Iterator<Item = &'a PathBuf>,
+) -> BTreeMap<String, BTreeSet<String>> {
     let mut directory_children: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
-    let mut all_paths: BTreeSet<String> = BTreeSet::new();
 
     // Collect all directories and files
-    for file in files {
-        let file_path_str = file.to_string_lossy().replace('\\', "/");
-        all_paths.insert(file_path_str.clone());
+    for path in paths {
+        let file_path_str = path.to_string_lossy().replace('\\', "/");
 
         // Add all parent directories to the structure
         let mut current_path = String::new();
@@ -264,24 +1648,153 @@ fn generate_directory_tree(root_path: &Path, files: &[PathBuf]) -> Result<String
             }
             current_path.push_str(part);
 
-            if i < parts.len() - 1 {
-                // This is a directory
-                all_paths.insert(current_path.clone());
-            }
-
             // Add to parent's children
             directory_children
                 .entry(parent_path)
-                .or_insert_with(BTreeSet::new)
+                .or_default()
                 .insert(current_path.clone());
         }
     }
 
+    directory_children
+}
+
+/// Generates a nested JSON representation of the directory tree
+///
+/// Each node is an object with `name` and `is_dir` fields; directories also carry a
+/// `children` array, and collapsed directories a `note`.
+///
+/// # Arguments
+/// * `root_path` - The root directory
+/// * `files` - List of files to include in the tree
+/// * `options` - Options controlling which directories are collapsed
+///
+/// # Returns
+/// The root node of the tree
+fn generate_tree_structure(root_path: &Path, files: &[PathBuf], options: &TreeOptions) -> Json {
+    let directory_children =
+        build_directory_children(files.iter().chain(options.collapsed_dirs.keys()));
+    let collapsed_dirs: BTreeMap<String, String> = options
+        .collapsed_dirs
+        .iter()
+        .map(|(path, note)| (path.to_string_lossy().replace('\\', "/"), note.clone()))
+        .collect();
+    let root_name = options.root_name.clone().unwrap_or_else(|| {
+        root_path
+            .file_name()
+            .unwrap_or_else(|| std::ffi::OsStr::new("root"))
+            .to_string_lossy()
+            .to_string()
+    });
+
+    fn build_node(
+        directory_children: &BTreeMap<String, BTreeSet<String>>,
+        collapsed_dirs: &BTreeMap<String, String>,
+        path: &str,
+        name: String,
+    ) -> Json {
+        let collapsed_note = collapsed_dirs.get(path);
+        let is_dir =
+            path.is_empty() || directory_children.contains_key(path) || collapsed_note.is_some();
+
+        let mut fields = vec![("name", Json::String(name)), ("is_dir", Json::Bool(is_dir))];
+        if is_dir {
+            let children = directory_children
+                .get(path)
+                .into_iter()
+                .flatten()
+                .map(|child| {
+                    let child_name = child.split('/').next_back().unwrap_or(child);
+                    build_node(
+                        directory_children,
+                        collapsed_dirs,
+                        child,
+                        child_name.to_string(),
+                    )
+                })
+                .collect();
+            fields.push(("children", Json::Array(children)));
+        }
+        if let Some(note) = collapsed_note {
+            fields.push(("note", Json::String(note.clone())));
+        }
+        Json::object(fields)
+    }
+
+    build_node(&directory_children, &collapsed_dirs, "", root_name)
+}
+
+/// Generates a directory tree structure as a string
+///
+/// # Arguments
+/// * `root_path` - The root directory
+/// * `files` - List of files to include in the tree
+/// * `options` - Options controlling how the tree is rendered
+///
+/// # Returns
+/// A formatted directory tree string
+fn generate_directory_tree(
+    root_path: &Path,
+    files: &[PathBuf],
+    options: &TreeOptions,
+) -> Result<String> {
+    let mut tree = String::new();
+    let root_name = if let Some(root_name) = &options.root_name {
+        root_name.clone()
+    } else if options.root_path_label {
+        let full_path = fs::canonicalize(root_path).unwrap_or_else(|_| root_path.to_path_buf());
+        full_path
+            .to_string_lossy()
+            .trim_end_matches(['/', '\\'])
+            .to_string()
+    } else {
+        root_path
+            .file_name()
+            .unwrap_or_else(|| std::ffi::OsStr::new("root"))
+            .to_string_lossy()
+            .into_owned()
+    };
+
+    match options.format {
+        OutputFormat::Text | OutputFormat::Json => tree.push_str("Directory Structure:\n"),
+        OutputFormat::Markdown => tree.push_str("## Directory Structure\n\n```\n"),
+        OutputFormat::Xml => {}
+    }
+    if options.icons {
+        tree.push_str(&format!("{} ", language::DIRECTORY_ICON));
+    }
+    tree.push_str(&format!("{}/\n", root_name));
+
+    // Build a hierarchical structure
+    let directory_children =
+        build_directory_children(files.iter().chain(options.collapsed_dirs.keys()));
+
+    // Collapsed directories are rendered as leaves with a trailing note
+    let collapsed_dirs: BTreeMap<String, String> = options
+        .collapsed_dirs
+        .iter()
+        .map(|(path, note)| (path.to_string_lossy().replace('\\', "/"), note.clone()))
+        .collect();
+
+    // Symlinks are rendered with their target after the link name
+    let symlink_targets: BTreeMap<String, String> = options
+        .symlink_targets
+        .iter()
+        .map(|(path, target)| {
+            (
+                path.to_string_lossy().replace('\\', "/"),
+                format!(" -> {}", target.to_string_lossy()),
+            )
+        })
+        .collect();
+
     // Recursive function to print tree structure
     fn print_tree_recursive(
         tree: &mut String,
         directory_children: &BTreeMap<String, BTreeSet<String>>,
-        all_paths: &BTreeSet<String>,
+        collapsed_dirs: &BTreeMap<String, String>,
+        symlink_targets: &BTreeMap<String, String>,
+        icons: bool,
         current_dir: &str,
         prefix: &str,
     ) {
@@ -293,11 +1806,13 @@ fn generate_directory_tree(root_path: &Path, files: &[PathBuf]) -> Result<String
                 let child_name = if child.is_empty() {
                     continue;
                 } else {
-                    child.split('/').last().unwrap_or(child)
+                    child.split('/').next_back().unwrap_or(child)
                 };
 
                 // Determine if this is a directory
-                let is_directory = directory_children.contains_key(child);
+                let collapsed_note = collapsed_dirs.get(child);
+                let is_directory =
+                    directory_children.contains_key(child) || collapsed_note.is_some();
 
                 // Choose the appropriate tree character
                 let connector = if is_last_child {
@@ -306,10 +1821,19 @@ fn generate_directory_tree(root_path: &Path, files: &[PathBuf]) -> Result<String
                     "├── "
                 };
                 let child_suffix = if is_directory { "/" } else { "" };
+                let icon = if icons {
+                    format!("{} ", language::tree_icon(child_name, is_directory))
+                } else {
+                    String::new()
+                };
+                let note = match collapsed_note {
+                    Some(note) => format!(" {}", note),
+                    None => symlink_targets.get(child).cloned().unwrap_or_default(),
+                };
 
                 tree.push_str(&format!(
-                    "{}{}{}{}\n",
-                    prefix, connector, child_name, child_suffix
+                    "{}{}{}{}{}{}\n",
+                    prefix, connector, icon, child_name, child_suffix, note
                 ));
 
                 // Prepare prefix for children
@@ -320,170 +1844,4906 @@ fn generate_directory_tree(root_path: &Path, files: &[PathBuf]) -> Result<String
                 };
 
                 // Recursively print children
-                print_tree_recursive(tree, directory_children, all_paths, child, &child_prefix);
+                print_tree_recursive(
+                    tree,
+                    directory_children,
+                    collapsed_dirs,
+                    symlink_targets,
+                    icons,
+                    child,
+                    &child_prefix,
+                );
             }
         }
     }
 
     // Start recursive printing from root
-    print_tree_recursive(&mut tree, &directory_children, &all_paths, "", "");
+    print_tree_recursive(
+        &mut tree,
+        &directory_children,
+        &collapsed_dirs,
+        &symlink_targets,
+        options.icons,
+        "",
+        "",
+    );
+
+    match options.format {
+        OutputFormat::Markdown => tree.push_str("```\n"),
+        OutputFormat::Xml => {
+            tree = format!(
+                "<directory_structure>\n{}</directory_structure>\n",
+                escape_xml_text(&tree)
+            );
+        }
+        OutputFormat::Text | OutputFormat::Json => {}
+    }
 
     Ok(tree)
 }
 
-/// Generates file contents section as a string
+/// Prefixes each line of a file body with its line number, as in `  42 | let x = 1;`
+///
+/// The numbers are right-aligned to the widest one. Notice lines are left unnumbered, and a
+/// `truncated` notice advances the count past the lines it stands for, so excerpts keep the
+/// numbers of the original file.
 ///
 /// # Arguments
-/// * `root_path` - The root directory
-/// * `files` - List of files to include
+/// * `body` - The file body
 ///
 /// # Returns
-/// A formatted string containing all file contents
-fn generate_file_contents(root_path: &Path, files: &[PathBuf]) -> Result<String> {
-    let mut contents = String::new();
+/// The numbered body, ending with a newline exactly when `body` does
+///
+/// # Examples
+/// ```
+/// assert_eq!(number_lines("a\nb\n"), "1 | a\n2 | b\n");
+/// ```
+fn number_lines(body: &str) -> String {
+    static TRUNCATED: OnceLock<Regex> = OnceLock::new();
+    let truncated = TRUNCATED.get_or_init(|| {
+        Regex::new(r"^\[\[repodump:truncated lines=\d+\.\.(\d+)\b").expect("valid pattern")
+    });
 
-    for (i, file_path) in files.iter().enumerate() {
-        let full_path = root_path.join(file_path);
+    let mut next = 1;
+    let numbers: Vec<Option<usize>> = body
+        .lines()
+        .map(|line| {
+            if let Some(captures) = truncated.captures(line) {
+                next = captures[1].parse::<usize>().map_or(next, |last| last + 1);
+                None
+            } else if line.starts_with("[[repodump:") && line.ends_with("]]") {
+                None
+            } else {
+                next += 1;
+                Some(next - 1)
+            }
+        })
+        .collect();
+    let width = numbers
+        .iter()
+        .flatten()
+        .max()
+        .map_or(1, |n| n.to_string().len());
 
-        if i > 0 {
-            contents.push('\n');
+    let mut numbered = String::with_capacity(body.len() + numbers.len() * (width + 3));
+    for (line, number) in body.lines().zip(numbers) {
+        match number {
+            Some(number) => numbered.push_str(&format!("{:>width$} | {}\n", number, line)),
+            None => {
+                numbered.push_str(line);
+                numbered.push('\n');
+            }
         }
+    }
+    if !body.ends_with('\n') {
+        numbered.pop();
+    }
+    numbered
+}
 
-        contents.push_str("================================================\n");
-        contents.push_str(&format!("FILE: {}\n", file_path.to_string_lossy()));
-        contents.push_str("================================================\n");
+/// Options controlling how file contents are rendered
+#[derive(Default)]
+struct ContentOptions {
+    /// Symlinked files mapped to the real path they point at, noted in the banner
+    symlink_targets: BTreeMap<PathBuf, PathBuf>,
+    /// Working trees of included submodules, whose files' banners name the submodule
+    submodules: Vec<PathBuf>,
+    /// Markers identifying license headers to strip, or `None` to keep headers
+    license_markers: Option<Vec<String>>,
+    /// Replacement bodies for files, such as the excerpts of `--only-changed-lines`
+    body_overrides: BTreeMap<PathBuf, String>,
+    /// Fail instead of emitting a placeholder when a file cannot be read
+    strict: bool,
+    /// Embed binary files as base64 instead of a placeholder
+    binary_base64: bool,
+    /// Content hashes emitted by earlier runs, or `None` to disable cross-run deduplication
+    sent_hashes: Option<BTreeSet<String>>,
+    /// Number of trailing path components shown in banners, or `None` for full paths
+    banner_path_depth: Option<usize>,
+    /// Absolute path of the root that banner paths are joined to, or `None` for relative paths
+    absolute_root: Option<PathBuf>,
+    /// Estimator of the per-file token metrics
+    token_estimator: TokenEstimator,
+    /// Transforms applied to text files, by lowercase extension
+    transforms: BTreeMap<String, Vec<transform::Transform>>,
+    /// Threads reading files; 0 or 1 reads on the calling thread
+    jobs: usize,
+    /// Prefix each line of text bodies with its line number
+    line_numbers: bool,
+    /// Replaces secrets in text bodies, or `None` to keep them
+    redactor: Option<redact::Redactor>,
+    /// Encoding of every text file, or `None` to detect each one
+    encoding: Option<&'static encoding_rs::Encoding>,
+    /// Counts the files read, or `None` to read silently
+    progress: Option<Arc<progress::Progress>>,
+    /// Replace repeated copies of a file with a reference to the first one
+    dedup: bool,
+}
 
-        match fs::read_to_string(&full_path) {
-            Ok(file_content) => {
-                contents.push_str(&file_content);
-                if !file_content.ends_with('\n') {
-                    contents.push('\n');
-                }
-            }
-            Err(_) => {
-                contents.push_str("[Binary file or read error]\n");
-            }
+/// The files of the contents section and statistics gathered while reading them
+#[derive(Default)]
+struct LoadedContents {
+    /// Number of license headers removed by `--strip-license-headers`
+    license_headers_stripped: usize,
+    /// Number of secrets replaced by `--redact`
+    redactions: usize,
+    /// Number of files decoded from an encoding other than UTF-8
+    transcoded: usize,
+    /// Number of files replaced by a reference to an identical earlier file with `--dedup`
+    duplicates: usize,
+    /// Estimated tokens removed by `--dedup`
+    dedup_tokens_saved: usize,
+    /// Estimated tokens removed by `--strip-license-headers`
+    license_tokens_saved: usize,
+    /// Size, token and hash metrics of each rendered file, in output order
+    file_metrics: Vec<FileMetrics>,
+    /// The files as read, in output order
+    entries: Vec<FileEntry>,
+    /// Number of files replaced because their hash was already sent
+    already_sent: usize,
+    /// Hashes of files emitted in full that were not sent before
+    new_hashes: Vec<String>,
+}
+
+/// A file of the contents section, read and transformed but not yet rendered
+struct FileEntry {
+    /// Path relative to the root
+    path: PathBuf,
+    /// Path shown in the banner, with the symlink target when it is noted
+    display_path: String,
+    /// The file contents, or a placeholder for unreadable files
+    body: String,
+    /// Encoding of the body when it is not the file's own text, such as `base64`
+    encoding: Option<&'static str>,
+    /// Whether the body is the output of a `--run` command rather than a file
+    is_command: bool,
+    /// Size of the file in bytes, or of the command output
+    size_bytes: usize,
+    /// Estimated tokens of the body
+    tokens: usize,
+}
+
+impl FileEntry {
+    /// Language tag of the code fence around the body
+    fn fence_language(&self) -> String {
+        match self.encoding {
+            Some(encoding) => encoding.to_string(),
+            None if self.is_command => "text".to_string(),
+            None => language::fence_language(&self.path),
         }
     }
+}
 
-    Ok(contents)
+/// Metrics of a single file in the contents section
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FileMetrics {
+    /// Path relative to the root
+    path: PathBuf,
+    /// Size of the file on disk in bytes
+    size_bytes: usize,
+    /// Estimated tokens of the file body as rendered
+    tokens: usize,
+    /// SHA-1 hex digest of the raw file bytes, empty for binary files that were not read
+    sha1: String,
+    /// Lines of the text body, 0 for placeholders
+    lines: usize,
+    /// Lines of the text body holding more than whitespace
+    non_blank_lines: usize,
 }
 
-/// Estimates the number of LLM tokens in the text
-///
-/// # Arguments
-/// * `text` - The text to analyze
-///
-/// # Returns
-/// Estimated number of tokens (characters / 4)
+/// Counts the lines of a text, and those holding more than whitespace
 ///
 /// # Examples
 /// ```
-/// assert_eq!(estimate_tokens("Hello world"), 2);
-/// assert_eq!(estimate_tokens("This is a test"), 3);
+/// assert_eq!(count_lines("fn main() {\n\n}"), (3, 2));
 /// ```
-fn estimate_tokens(text: &str) -> usize {
-    text.chars().count() / 4
+fn count_lines(text: &str) -> (usize, usize) {
+    text.lines().fold((0, 0), |(lines, non_blank), line| {
+        (lines + 1, non_blank + usize::from(!line.trim().is_empty()))
+    })
 }
 
-/// Prints a summary of the generated file to stdout
+/// Finds the files whose estimated tokens exceed a threshold
 ///
 /// # Arguments
-/// * `root_path` - The processed directory
-/// * `structure_file_count` - Number of files in structure
-/// * `content_file_count` - Number of files with contents
-/// * `output_size` - Size of output file in bytes
-/// * `token_count` - Estimated token count
-fn print_summary(
-    root_path: &Path,
-    structure_file_count: usize,
-    content_file_count: usize,
-    output_size: usize,
-    token_count: usize,
-) {
-    let repo_name = root_path
-        .file_name()
-        .unwrap_or_else(|| std::ffi::OsStr::new("unknown"))
-        .to_string_lossy();
-
-    println!("Repository: {}", repo_name);
-    println!("Files in structure: {}", structure_file_count);
-    println!("Files in contents: {}", content_file_count);
-    println!("Output size: {} bytes", output_size);
-    println!("Estimated tokens: {}", token_count);
+/// * `metrics` - Per-file metrics gathered while generating the contents
+/// * `threshold` - Token count a file must exceed to be reported
+///
+/// # Returns
+/// The offending files, largest first
+fn large_token_files(metrics: &[FileMetrics], threshold: usize) -> Vec<&FileMetrics> {
+    let mut large: Vec<&FileMetrics> = metrics
+        .iter()
+        .filter(|file| file.tokens > threshold)
+        .collect();
+    large.sort_by(|a, b| b.tokens.cmp(&a.tokens).then_with(|| a.path.cmp(&b.path)));
+    large
 }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+/// Builds the sidecar manifest listing each file in the contents section
+///
+/// # Arguments
+/// * `repo_name` - Name of the processed directory, or the `--root-name` given for it
+/// * `metrics` - Per-file metrics gathered while generating the contents
+///
+/// # Returns
+/// A JSON object with the repository name and one entry per file
+fn generate_manifest(repo_name: &str, metrics: &[FileMetrics]) -> Json {
+    let files = metrics
+        .iter()
+        .map(|file| {
+            Json::object([
+                (
+                    "path",
+                    Json::String(file.path.to_string_lossy().to_string()),
+                ),
+                ("size_bytes", Json::Number(file.size_bytes as u64)),
+                ("tokens", Json::Number(file.tokens as u64)),
+                ("sha1", Json::String(file.sha1.clone())),
+            ])
+        })
+        .collect();
+    Json::object([
+        ("repository", Json::String(repo_name.to_string())),
+        ("files", Json::Array(files)),
+    ])
+}
 
-    // Resolve target directory
-    let target_dir = resolve_target_directory(cli.path)?;
+/// Files read by each thread per chunk in `load_file_contents` with `--jobs`
+const READ_CHUNK_FILES_PER_JOB: usize = 8;
 
-    // Create an exclude filter that always excludes .git
-    let exclude_git = vec![".git".to_string(), ".git/**".to_string()];
-    let mut all_excludes = cli.exclude.clone();
-    all_excludes.extend(exclude_git.clone());
+/// Reads the files of the contents section, ready to be rendered in any format
+///
+/// # Arguments
+/// * `root_path` - The root directory
+/// * `files` - List of files to include
+/// * `options` - Options controlling how each file is read
+///
+/// # Returns
+/// The file entries and statistics about them, with nothing rendered yet
+fn load_file_contents(
+    root_path: &Path,
+    files: &[PathBuf],
+    options: &ContentOptions,
+) -> Result<LoadedContents> {
+    let mut generated = LoadedContents::default();
+    let mut sent_hashes = options.sent_hashes.clone();
+    // The first file seen with each content hash, for `--dedup`
+    let mut first_copies: BTreeMap<String, PathBuf> = BTreeMap::new();
 
-    // Gather files for content section
-    let content_filter = FileFilter::new(cli.filter, all_excludes, cli.include.clone())?;
-    let content_files = collect_files(&target_dir, &content_filter, cli.ignore_gitignore)?;
+    let read_file = |file_path: &PathBuf| {
+        let full_path = root_path.join(file_path);
+        // Binary files are recognised from their first bytes, sparing the full read
+        let skip_binary = !options.binary_base64
+            && options.encoding.is_none()
+            && !options.body_overrides.contains_key(file_path)
+            && content_type::is_probably_binary(&full_path).unwrap_or(false);
+        let read = if skip_binary {
+            (true, Ok(Vec::new()))
+        } else {
+            (false, fs::read(&full_path))
+        };
+        if let Some(progress) = &options.progress {
+            progress.inc();
+        }
+        read
+    };
 
-    // Gather files for tree structure section
-    let tree_files = if cli.prune_tree {
-        // If pruning tree, use the same files as content section
-        content_files.clone()
+    // Files are read one at a time, or in bounded chunks in parallel with --jobs, so only a
+    // chunk's raw bytes are held at once; the rest keeps the file order
+    let chunk_size = if options.jobs <= 1 {
+        1
     } else {
-        let tree_filter = FileFilter::new(vec![], exclude_git, cli.include.clone())?;
-        collect_files(&target_dir, &tree_filter, cli.ignore_gitignore)?
+        options.jobs * READ_CHUNK_FILES_PER_JOB
     };
+    for chunk in files.chunks(chunk_size) {
+        let reads = parallel_map(chunk, options.jobs, read_file);
+        for (file_path, (skip_binary, raw)) in chunk.iter().zip(reads) {
+            let full_path = root_path.join(file_path);
+            // A file removed after it was collected, as opposed to a dangling symlink
+            let vanished = raw
+                .as_ref()
+                .is_err_and(|error| error.kind() == io::ErrorKind::NotFound)
+                && fs::symlink_metadata(&full_path).is_err();
+            if let (Err(error), true) = (&raw, options.strict) {
+                let message = if vanished {
+                    format!("File vanished during scan: {}", file_path.display())
+                } else {
+                    format!("Failed to read file {}: {}", file_path.display(), error)
+                };
+                anyhow::bail!(ExitError::new(ExitStatus::ReadErrors, message));
+            }
+            let mut encoding = None;
+            // Whether the body is the file's text, rather than a placeholder or notice
+            let mut is_text = true;
+            let mut body = match options.body_overrides.get(file_path) {
+                Some(override_body) => override_body.clone(),
+                None if skip_binary => {
+                    is_text = false;
+                    "[Binary file]\n".to_string()
+                }
+                None => match raw
+                    .as_deref()
+                    .map(|bytes| content_type::decode_text(bytes, options.encoding))
+                {
+                    Ok(Some((file_content, source_encoding))) => {
+                        if source_encoding != encoding_rs::UTF_8 {
+                            generated.transcoded += 1;
+                        }
+                        let extension = file_path
+                            .extension()
+                            .map(|extension| extension.to_string_lossy().to_lowercase())
+                            .unwrap_or_default();
+                        match options.transforms.get(&extension) {
+                            Some(transforms) => {
+                                transform::apply(&file_content, &extension, transforms)
+                            }
+                            None => file_content.into_owned(),
+                        }
+                    }
+                    Ok(None) if options.binary_base64 => {
+                        is_text = false;
+                        let bytes = raw.as_deref().unwrap_or_default();
+                        if bytes.len() > BINARY_BASE64_MAX_BYTES {
+                            notice::omitted_body(bytes.len(), "binary-too-large")
+                        } else {
+                            encoding = Some("base64");
+                            encode_base64(bytes)
+                        }
+                    }
+                    Ok(None) => {
+                        is_text = false;
+                        "[Binary file]\n".to_string()
+                    }
+                    Err(_) if vanished => {
+                        is_text = false;
+                        "[File vanished during scan]\n".to_string()
+                    }
+                    Err(error) => {
+                        is_text = false;
+                        format!("[Read error: {}]\n", error)
+                    }
+                },
+            };
+            let read_failed = raw.is_err() || skip_binary;
+            let raw = raw.unwrap_or_default();
+            let (size_bytes, sha1) = if skip_binary {
+                let size_bytes = fs::metadata(&full_path).map_or(0, |metadata| metadata.len());
+                (size_bytes as usize, String::new())
+            } else {
+                (raw.len(), sha1_smol::Sha1::from(&raw).digest().to_string())
+            };
+
+            let mut duplicate = false;
+            if options.dedup && !read_failed && !raw.is_empty() {
+                match first_copies.get(&sha1) {
+                    Some(original) => {
+                        let note = format!("[identical to {}]\n", original.to_string_lossy());
+                        generated.dedup_tokens_saved += options
+                            .token_estimator
+                            .estimate(&body)
+                            .saturating_sub(options.token_estimator.estimate(&note));
+                        generated.duplicates += 1;
+                        body = note;
+                        encoding = None;
+                        is_text = false;
+                        duplicate = true;
+                    }
+                    None => {
+                        first_copies.insert(sha1.clone(), file_path.clone());
+                    }
+                }
+            }
+
+            if let (Some(sent), false) = (sent_hashes.as_mut(), read_failed || duplicate) {
+                if sent.contains(&sha1) {
+                    body = format!("[Already sent elsewhere: {}]\n", sha1);
+                    encoding = None;
+                    is_text = false;
+                    generated.already_sent += 1;
+                } else {
+                    sent.insert(sha1.clone());
+                    generated.new_hashes.push(sha1.clone());
+                }
+            }
 
-    // Generate output content
-    let mut output_content = String::new();
+            if let (Some(markers), None) = (&options.license_markers, encoding) {
+                if let Some(stripped) = strip_license_header(&body, markers) {
+                    generated.license_headers_stripped += 1;
+                    // Shortening a long line can lift its estimate, which saves nothing
+                    generated.license_tokens_saved += options
+                        .token_estimator
+                        .estimate(&body)
+                        .saturating_sub(options.token_estimator.estimate(&stripped));
+                    body = stripped;
+                }
+            }
 
-    let structure_file_count;
-    if !cli.contents_only {
-        // Generate tree
-        let tree = generate_directory_tree(&target_dir, &tree_files)?;
-        output_content.push_str(&tree);
-        output_content.push('\n');
-        structure_file_count = tree_files.len()
-    } else {
-        structure_file_count = 0;
+            if let (Some(redactor), true) = (&options.redactor, is_text) {
+                let (redacted, count) = redactor.redact(&body);
+                body = redacted;
+                generated.redactions += count;
+            }
+
+            let (lines, non_blank_lines) = if is_text { count_lines(&body) } else { (0, 0) };
+            if options.line_numbers && is_text {
+                body = number_lines(&body);
+            }
+
+            generated.file_metrics.push(FileMetrics {
+                path: file_path.clone(),
+                size_bytes,
+                // Counted for all files at once below
+                tokens: 0,
+                sha1,
+                lines,
+                non_blank_lines,
+            });
+
+            let banner_path = match (&options.absolute_root, options.banner_path_depth) {
+                (Some(absolute_root), _) => {
+                    absolute_root.join(file_path).to_string_lossy().to_string()
+                }
+                (None, Some(depth)) => shorten_path(file_path, depth),
+                (None, None) => file_path.to_string_lossy().to_string(),
+            };
+            let banner_path = match options
+                .submodules
+                .iter()
+                .find(|submodule| file_path.starts_with(submodule))
+            {
+                Some(submodule) => format!("[submodule {}] {}", submodule.display(), banner_path),
+                None => banner_path,
+            };
+            let display_path = match options.symlink_targets.get(file_path) {
+                Some(target) => format!("{} -> {}", banner_path, target.to_string_lossy()),
+                None => banner_path,
+            };
+
+            generated.entries.push(FileEntry {
+                path: file_path.clone(),
+                display_path,
+                body,
+                encoding,
+                is_command: false,
+                size_bytes,
+                // Counted for all files at once below
+                tokens: 0,
+            });
+        }
+    }
+    if let Some(progress) = &options.progress {
+        progress.finish();
     }
 
-    let content_file_count;
-    if !cli.tree_only {
-        // Generate file contents
-        let contents = generate_file_contents(&target_dir, &content_files)?;
-        output_content.push_str(&contents);
-        content_file_count = content_files.len()
-    } else {
-        content_file_count = 0
-    };
+    let bodies: Vec<&str> = generated
+        .entries
+        .iter()
+        .map(|entry| entry.body.as_str())
+        .collect();
+    let token_counts = options.token_estimator.estimate_each(&bodies);
+    for ((metrics, entry), tokens) in generated
+        .file_metrics
+        .iter_mut()
+        .zip(generated.entries.iter_mut())
+        .zip(token_counts)
+    {
+        metrics.tokens = tokens;
+        entry.tokens = tokens;
+    }
 
-    // Add prompt if provided
-    if let Some(prompt) = cli.prompt {
-        output_content.push('\n');
-        output_content.push_str(&format!("Prompt: {}\n", prompt));
+    Ok(generated)
+}
+
+/// Keeps only the last components of a path, marking the elided prefix with `.../`
+///
+/// # Arguments
+/// * `path` - Path relative to the root
+/// * `depth` - Number of trailing components to keep; the file name is always kept
+///
+/// # Returns
+/// The shortened path with `/` separators, or the whole path if it is short enough
+///
+/// # Examples
+/// ```
+/// assert_eq!(shorten_path(Path::new("src/app/utils/helpers.rs"), 2), ".../utils/helpers.rs");
+/// assert_eq!(shorten_path(Path::new("src/main.rs"), 2), "src/main.rs");
+/// ```
+fn shorten_path(path: &Path, depth: usize) -> String {
+    let components: Vec<_> = path
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect();
+    let keep = depth.max(1);
+    if components.len() <= keep {
+        return components.join("/");
     }
+    format!(".../{}", components[components.len() - keep..].join("/"))
+}
 
-    // Write output file
-    fs::write(&cli.output, &output_content)
-        .with_context(|| format!("Failed to write output file: {}", cli.output.display()))?;
+/// Runs a shell command in the root directory and captures its output as a pseudo-file
+///
+/// The entry is labeled `<command: COMMAND>`. If the command fails, its stderr and exit
+/// status are appended to whatever it wrote to stdout.
+///
+/// # Arguments
+/// * `root_path` - The directory the command runs in
+/// * `command` - The command line, run with `sh -c` (`cmd /C` on Windows)
+///
+/// # Returns
+/// The contents entry holding the command output
+fn run_command(root_path: &Path, command: &str) -> FileEntry {
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let output = Command::new(shell)
+        .args([flag, command])
+        .current_dir(root_path)
+        .stdin(Stdio::null())
+        .output();
 
-    // Print summary unless quiet mode
-    if !cli.quiet {
-        let output_size = output_content.len();
-        let token_count = estimate_tokens(&output_content);
+    let body = match output {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).into_owned()
+        }
+        Ok(output) => {
+            let mut body = String::from_utf8_lossy(&output.stdout).into_owned();
+            if !body.is_empty() && !body.ends_with('\n') {
+                body.push('\n');
+            }
+            body.push_str(&String::from_utf8_lossy(&output.stderr));
+            if !body.is_empty() && !body.ends_with('\n') {
+                body.push('\n');
+            }
+            body.push_str(&format!("[Command failed: {}]\n", output.status));
+            body
+        }
+        Err(error) => format!("[Command could not be run: {}]\n", error),
+    };
 
-        print_summary(
-            &target_dir,
-            structure_file_count,
-            content_file_count,
-            output_size,
-            token_count,
-        );
+    let label = format!("<command: {}>", command);
+    FileEntry {
+        path: PathBuf::from(&label),
+        display_path: label,
+        size_bytes: body.len(),
+        tokens: estimate_tokens(&body),
+        body,
+        encoding: None,
+        is_command: true,
     }
+}
 
-    Ok(())
+/// Renders file entries as the text or Markdown contents section
+///
+/// # Arguments
+/// * `entries` - The files loaded by `load_file_contents`
+/// * `style` - The banner placed before each file
+/// * `fenced` - Wrap each body in a language-tagged code fence under its banner, which the
+///   Markdown style always does
+///
+/// # Returns
+/// The formatted file contents
+fn render_file_contents(entries: &[FileEntry], style: BannerStyle, fenced: bool) -> String {
+    let mut contents = String::new();
+    for (i, entry) in entries.iter().enumerate() {
+        if i > 0 {
+            contents.push('\n');
+        }
+
+        let mut body = entry.body.clone();
+        if !body.ends_with('\n') {
+            body.push('\n');
+        }
+        if fenced && style != BannerStyle::Markdown {
+            let fence = code_fence(&body);
+            body = format!("{}{}\n{}{}\n", fence, entry.fence_language(), body, fence);
+        }
+        let encoding_note = entry
+            .encoding
+            .map(|encoding| format!(" ({})", encoding))
+            .unwrap_or_default();
+
+        match style {
+            BannerStyle::Equals => {
+                contents.push_str("================================================\n");
+                contents.push_str(&format!("FILE: {}\n", entry.display_path));
+                if let Some(encoding) = entry.encoding {
+                    contents.push_str(&format!("ENCODING: {}\n", encoding));
+                }
+                contents.push_str("================================================\n");
+                contents.push_str(&body);
+            }
+            BannerStyle::Markdown => {
+                let fence = code_fence(&body);
+
+                contents.push_str(&format!("## {}\n\n", entry.display_path));
+                contents.push_str(&format!("{}{}\n", fence, entry.fence_language()));
+                contents.push_str(&body);
+                contents.push_str(&format!("{}\n", fence));
+            }
+            BannerStyle::Minimal => {
+                contents.push_str(&format!(
+                    "--- {}{} ---\n",
+                    entry.display_path, encoding_note
+                ));
+                contents.push_str(&body);
+            }
+            BannerStyle::Xml => {
+                let encoding_attribute = entry
+                    .encoding
+                    .map(|encoding| format!(" encoding=\"{}\"", encoding))
+                    .unwrap_or_default();
+                contents.push_str(&format!(
+                    "<file path=\"{}\"{}>\n",
+                    escape_xml_attribute(&entry.display_path),
+                    encoding_attribute
+                ));
+                contents.push_str(&escape_xml_text(&body));
+                contents.push_str("</file>\n");
+            }
+            BannerStyle::Comment => {
+                contents.push_str(&format!(
+                    "// FILE: {}{}\n",
+                    entry.display_path, encoding_note
+                ));
+                contents.push_str(&body);
+            }
+        }
+    }
+    contents
+}
+
+/// Renders file entries as text contents with a `--header-template` banner
+///
+/// # Arguments
+/// * `entries` - The files loaded by `load_file_contents`
+/// * `template` - The banner, in which `{path}`, `{size}`, `{tokens}` and `{lang}` stand for
+///   the displayed path, the size in bytes, the estimated tokens and the code fence
+///   language, and `\n` for a line break
+///
+/// # Returns
+/// The formatted file contents
+///
+/// # Examples
+/// ```
+/// let contents = render_templated_contents(&entries, "# {path} ({tokens} tokens)");
+/// assert!(contents.starts_with("# src/main.rs (3 tokens)\nfn main() {}\n"));
+/// ```
+fn render_templated_contents(entries: &[FileEntry], template: &str) -> String {
+    let template = template.replace("\\n", "\n");
+    let mut contents = String::new();
+    for (i, entry) in entries.iter().enumerate() {
+        if i > 0 {
+            contents.push('\n');
+        }
+        let language = entry.fence_language();
+        // Substitute in one pass so a path containing `{size}` is never expanded again
+        let mut header = String::new();
+        let mut rest = template.as_str();
+        while let Some(start) = rest.find('{') {
+            header.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let end = rest.find('}').map_or(0, |end| end + 1);
+            let value = match &rest[..end] {
+                "{path}" => entry.display_path.clone(),
+                "{size}" => entry.size_bytes.to_string(),
+                "{tokens}" => entry.tokens.to_string(),
+                "{lang}" => language.clone(),
+                _ => {
+                    header.push('{');
+                    rest = &rest[1..];
+                    continue;
+                }
+            };
+            header.push_str(&value);
+            rest = &rest[end..];
+        }
+        header.push_str(rest);
+        contents.push_str(&header);
+        if !header.ends_with('\n') {
+            contents.push('\n');
+        }
+        contents.push_str(&entry.body);
+        if !entry.body.ends_with('\n') {
+            contents.push('\n');
+        }
+    }
+    contents
+}
+
+/// Chooses a Markdown code fence that the text cannot close early
+///
+/// # Arguments
+/// * `text` - The text to place inside the fence
+///
+/// # Returns
+/// Three backticks, or one more than the longest run of backticks in the text
+///
+/// # Examples
+/// ```
+/// assert_eq!(code_fence("fn main() {}"), "```");
+/// assert_eq!(code_fence("```rust\nfn main() {}\n```"), "````");
+/// ```
+fn code_fence(text: &str) -> String {
+    let longest_run = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    "`".repeat((longest_run + 1).max(3))
+}
+
+/// Escapes text for use as the content of an XML element
+fn escape_xml_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Escapes text for use inside a double-quoted XML attribute
+fn escape_xml_attribute(text: &str) -> String {
+    escape_xml_text(text).replace('"', "&quot;")
+}
+
+/// Renders file entries as a `<documents>` element, one numbered `<document>` per file
+///
+/// Paths and contents are XML-escaped, so the structure survives any file contents.
+///
+/// # Arguments
+/// * `entries` - The files to render, in output order
+/// * `first_index` - Index of the first document, above 1 when the files continue an
+///   earlier part of a split output
+///
+/// # Returns
+/// The documents element
+///
+/// # Examples
+/// ```
+/// // <documents>
+/// // <document index="1">
+/// // <source>src/main.rs</source>
+/// // <document_contents>
+/// // fn main() {}
+/// // </document_contents>
+/// // </document>
+/// // </documents>
+/// ```
+fn render_xml_documents(entries: &[FileEntry], first_index: usize) -> String {
+    let mut documents = String::from("<documents>\n");
+    for (i, entry) in (first_index..).zip(entries) {
+        let mut body = escape_xml_text(&entry.body);
+        if !body.ends_with('\n') {
+            body.push('\n');
+        }
+        let encoding_attribute = entry
+            .encoding
+            .map(|encoding| format!(" encoding=\"{}\"", encoding))
+            .unwrap_or_default();
+        documents.push_str(&format!("<document index=\"{}\">\n", i));
+        documents.push_str(&format!(
+            "<source>{}</source>\n",
+            escape_xml_text(&entry.display_path)
+        ));
+        documents.push_str(&format!("<document_contents{}>\n", encoding_attribute));
+        documents.push_str(&body);
+        documents.push_str("</document_contents>\n</document>\n");
+    }
+    documents.push_str("</documents>\n");
+    documents
+}
+
+/// Files below which the Markdown table of contents is left out unless `--toc` is given
+const TOC_MIN_FILES: usize = 3;
+
+/// Turns a Markdown heading into the anchor GitHub generates for it
+///
+/// Letters and digits are lowercased, spaces become `-`, `-` and `_` are kept and all other
+/// characters are dropped.
+///
+/// # Examples
+/// ```
+/// assert_eq!(markdown_anchor("src/main.rs"), "srcmainrs");
+/// assert_eq!(markdown_anchor("My File_v2.md"), "my-file_v2md");
+/// ```
+fn markdown_anchor(heading: &str) -> String {
+    heading
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            '-' | '_' => Some(c),
+            c if c.is_alphanumeric() => Some(c),
+            _ => None,
+        })
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Renders a Markdown table of contents linking to each file's heading
+///
+/// Files are listed in dump order under bullets for their directories, indented by depth.
+/// Repeated anchors get `-1`, `-2` and so on appended, as GitHub does for repeated headings.
+///
+/// # Arguments
+/// * `entries` - The files, rendered with `## path` headings
+///
+/// # Returns
+/// A `## Table of Contents` section
+///
+/// # Examples
+/// ```
+/// // ## Table of Contents
+/// //
+/// // - [README.md](#readmemd)
+/// // - src/
+/// //   - [main.rs](#srcmainrs)
+/// ```
+fn render_markdown_toc(entries: &[FileEntry]) -> String {
+    let mut toc = String::from("## Table of Contents\n\n");
+    let mut seen: BTreeMap<String, usize> = BTreeMap::new();
+    let mut open_dirs: Vec<String> = Vec::new();
+    for entry in entries {
+        let anchor = markdown_anchor(&entry.display_path);
+        let count = seen.entry(anchor.clone()).or_insert(0);
+        let anchor = if *count == 0 {
+            anchor
+        } else {
+            format!("{}-{}", anchor, count)
+        };
+        *count += 1;
+
+        let (dirs, name) = if entry.is_command {
+            (Vec::new(), entry.display_path.clone())
+        } else {
+            let path = entry.path.to_string_lossy().replace('\\', "/");
+            let mut parts: Vec<String> = path.split('/').map(str::to_string).collect();
+            let name = parts.pop().unwrap_or_default();
+            (parts, name)
+        };
+        let shared = open_dirs
+            .iter()
+            .zip(&dirs)
+            .take_while(|(open, dir)| open == dir)
+            .count();
+        open_dirs.truncate(shared);
+        for dir in &dirs[shared..] {
+            toc.push_str(&format!("{}- {}/\n", "  ".repeat(open_dirs.len()), dir));
+            open_dirs.push(dir.clone());
+        }
+        toc.push_str(&format!(
+            "{}- [{}](#{})\n",
+            "  ".repeat(open_dirs.len()),
+            name.replace('[', "\\[").replace(']', "\\]"),
+            anchor
+        ));
+    }
+    toc
+}
+
+/// Renders file entries without banners, prefixing every line with its location
+///
+/// Each line becomes `path:line: text`, like `grep -n` output over several files, so every
+/// line of the dump can be searched and addressed on its own.
+///
+/// # Arguments
+/// * `entries` - The files loaded by `load_file_contents`
+/// * `format` - Markdown wraps the lines in a single code fence, XML in one element, and
+///   JSON writes them as an array of `{path, line, text}` objects
+///
+/// # Returns
+/// The prefixed lines
+///
+/// # Examples
+/// ```
+/// // A file `src/main.rs` containing "fn main() {\n}\n" renders as:
+/// // src/main.rs:1: fn main() {
+/// // src/main.rs:2: }
+/// ```
+fn render_prefixed_lines(entries: &[FileEntry], format: OutputFormat) -> String {
+    let mut lines = String::new();
+    for entry in entries {
+        let path = entry.path.to_string_lossy().replace('\\', "/");
+        for (number, line) in entry.body.lines().enumerate() {
+            lines.push_str(&format!("{}:{}: {}\n", path, number + 1, line));
+        }
+    }
+
+    match format {
+        OutputFormat::Text => lines,
+        OutputFormat::Markdown => {
+            let fence = code_fence(&lines);
+            format!("{}\n{}{}\n", fence, lines, fence)
+        }
+        OutputFormat::Xml => format!(
+            "<document_contents>\n{}</document_contents>\n",
+            escape_xml_text(&lines)
+        ),
+        OutputFormat::Json => Json::Array(prefixed_lines_json(entries)).to_pretty_string() + "\n",
+    }
+}
+
+/// Converts file entries into the `lines` array used by JSON with `--prefix-line-with-path`
+///
+/// # Arguments
+/// * `entries` - The files loaded by `load_file_contents`
+///
+/// # Returns
+/// One `{path, line, text}` object per line of every file, numbered from 1
+fn prefixed_lines_json(entries: &[FileEntry]) -> Vec<Json> {
+    entries
+        .iter()
+        .flat_map(|entry| {
+            let path = entry.path.to_string_lossy().replace('\\', "/");
+            entry.body.lines().enumerate().map(move |(number, line)| {
+                Json::object([
+                    ("path", Json::String(path.clone())),
+                    ("line", Json::Number(number as u64 + 1)),
+                    ("text", Json::String(line.to_string())),
+                ])
+            })
+        })
+        .collect()
+}
+
+/// Splits a rendered output into parts that each stay under a token budget
+///
+/// Parts break only between files: the directory tree and other sections before the contents
+/// open the first part, the sections after them close the last one, and a file larger than
+/// the budget gets a part of its own. Each part starts with a `Part X of Y` line.
+///
+/// # Arguments
+/// * `head` - The rendered sections before the contents
+/// * `entries` - The files of the contents section
+/// * `tail` - The rendered sections after the contents
+/// * `budget` - Maximum estimated tokens of each part
+/// * `render` - Renders a run of files as the contents section, given the position of the
+///   first of them in `entries`
+/// * `estimator` - Estimator of the tokens of each piece
+/// * `format` - The output format, which decides how the part line is written
+///
+/// # Returns
+/// The parts, in order
+fn split_output(
+    head: &str,
+    entries: &[FileEntry],
+    tail: &str,
+    budget: usize,
+    render: &dyn Fn(&[FileEntry], usize) -> String,
+    estimator: &TokenEstimator,
+    format: OutputFormat,
+) -> Vec<String> {
+    let header_tokens = estimator.estimate(&part_header(999, 999, format));
+    let rendered: Vec<String> = entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| render(std::slice::from_ref(entry), i) + "\n")
+        .collect();
+    let texts: Vec<&str> = rendered.iter().map(String::as_str).collect();
+    let entry_tokens = estimator.estimate_each(&texts);
+
+    // Each part is its file range and whether it holds the head and tail
+    let mut parts: Vec<(bool, std::ops::Range<usize>, bool)> = Vec::new();
+    let mut start = 0;
+    let mut has_head = !head.is_empty();
+    let mut tokens = header_tokens + estimator.estimate(head);
+    for (i, entry_tokens) in entry_tokens.iter().enumerate() {
+        if tokens + entry_tokens > budget && (has_head || start < i) {
+            parts.push((has_head, start..i, false));
+            start = i;
+            has_head = false;
+            tokens = header_tokens;
+        }
+        tokens += entry_tokens;
+    }
+    let tail_tokens = estimator.estimate(tail);
+    if tokens + tail_tokens > budget && (has_head || start < entries.len()) && !tail.is_empty() {
+        parts.push((has_head, start..entries.len(), false));
+        start = entries.len();
+        has_head = false;
+    }
+    parts.push((has_head, start..entries.len(), true));
+
+    let total = parts.len();
+    parts
+        .into_iter()
+        .enumerate()
+        .map(|(i, (has_head, range, has_tail))| {
+            let mut part = part_header(i + 1, total, format);
+            if has_head {
+                part.push_str(head);
+            }
+            if !range.is_empty() {
+                part.push_str(&render(&entries[range.clone()], range.start));
+            }
+            if has_tail {
+                part.push_str(tail);
+            }
+            part
+        })
+        .collect()
+}
+
+/// Formats the line opening each part of a `--split-tokens` output
+fn part_header(part: usize, total: usize, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Markdown | OutputFormat::Xml => {
+            format!("<!-- Part {} of {} -->\n\n", part, total)
+        }
+        OutputFormat::Text | OutputFormat::Json => format!("Part {} of {}\n\n", part, total),
+    }
+}
+
+/// Numbers an output path for a part of a `--split-tokens` output
+///
+/// # Examples
+/// ```
+/// assert_eq!(numbered_output_path(Path::new("out/repodump.txt"), 2), PathBuf::from("out/repodump.002.txt"));
+/// ```
+fn numbered_output_path(path: &Path, part: usize) -> PathBuf {
+    // The number goes before a compression extension, as in `repodump.002.txt.gz`
+    if let (path, Some(compression)) = compress::strip_extension(path) {
+        let mut name = numbered_output_path(&path, part).into_os_string();
+        name.push(format!(".{}", compression.extension()));
+        return PathBuf::from(name);
+    }
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match path.extension() {
+        Some(extension) => format!("{}.{:03}.{}", stem, part, extension.to_string_lossy()),
+        None => format!("{}.{:03}", stem, part),
+    };
+    path.with_file_name(name)
+}
+
+/// Renders a titled group of files, such as the sources or tests of `--group-tests-separately`
+///
+/// # Arguments
+/// * `title` - Heading of the group
+/// * `body` - The rendered files of the group
+/// * `format` - The format used to render the heading
+///
+/// # Returns
+/// The heading followed by the rendered files
+fn render_file_group(title: &str, body: &str, format: OutputFormat) -> String {
+    let heading = match format {
+        OutputFormat::Markdown => format!("# {}\n\n", title),
+        OutputFormat::Xml => format!("<!-- {} -->\n", title),
+        OutputFormat::Text | OutputFormat::Json => format!("{}:\n\n", title),
+    };
+    heading + body
+}
+
+/// Renders the `--diff-stat` section in the style of `git diff --stat`
+///
+/// # Arguments
+/// * `stats` - The changed files
+/// * `rev` - The revision the files were compared against
+/// * `format` - The output format of the section
+///
+/// # Returns
+/// One line per changed file with its change count and a `+`/`-` bar, followed by a totals
+/// line, or a single line noting that nothing changed
+///
+/// # Examples
+/// ```
+/// let stats = vec![DiffStat { path: PathBuf::from("src/main.rs"), insertions: 2, deletions: 1 }];
+/// assert!(render_diff_stat(&stats, "HEAD", OutputFormat::Text).contains(" src/main.rs | 3 ++-\n"));
+/// ```
+fn render_diff_stat(stats: &[git::DiffStat], rev: &str, format: OutputFormat) -> String {
+    const MAX_BAR_WIDTH: usize = 40;
+
+    let mut body = String::new();
+    if stats.is_empty() {
+        body.push_str(&format!("No changes since {}\n", rev));
+    } else {
+        let paths: Vec<String> = stats
+            .iter()
+            .map(|stat| stat.path.to_string_lossy().replace('\\', "/"))
+            .collect();
+        let path_width = paths.iter().map(|path| path.len()).max().unwrap_or(0);
+        let max_changes = stats
+            .iter()
+            .map(|stat| stat.insertions + stat.deletions)
+            .max()
+            .unwrap_or(0);
+        let count_width = max_changes.to_string().len();
+
+        for (stat, path) in stats.iter().zip(&paths) {
+            let changes = stat.insertions + stat.deletions;
+            // Scale the bar down only when the largest change would not fit
+            let (plus, minus) = if max_changes > MAX_BAR_WIDTH {
+                let scale = |n: usize| (n * MAX_BAR_WIDTH).div_ceil(max_changes);
+                (scale(stat.insertions), scale(stat.deletions))
+            } else {
+                (stat.insertions, stat.deletions)
+            };
+            body.push_str(&format!(
+                " {:path_width$} | {:>count_width$} {}{}\n",
+                path,
+                changes,
+                "+".repeat(plus),
+                "-".repeat(minus),
+            ));
+        }
+
+        let insertions: usize = stats.iter().map(|stat| stat.insertions).sum();
+        let deletions: usize = stats.iter().map(|stat| stat.deletions).sum();
+        body.push_str(&format!(
+            " {} file{} changed, {} insertion{}(+), {} deletion{}(-)\n",
+            stats.len(),
+            if stats.len() == 1 { "" } else { "s" },
+            insertions,
+            if insertions == 1 { "" } else { "s" },
+            deletions,
+            if deletions == 1 { "" } else { "s" },
+        ));
+    }
+
+    match format {
+        OutputFormat::Markdown => format!("## Diff Stat (since {})\n\n```\n{}```\n", rev, body),
+        OutputFormat::Xml => format!(
+            "<diff_stat since=\"{}\">\n{}</diff_stat>\n",
+            escape_xml_attribute(rev),
+            escape_xml_text(&body)
+        ),
+        OutputFormat::Text | OutputFormat::Json => format!("Diff Stat (since {}):\n{}", rev, body),
+    }
+}
+
+/// Renders the `--diff` section: a totals line, then the unified diff of each changed file
+///
+/// # Arguments
+/// * `diffs` - The changed files
+/// * `rev` - The revision the files were compared against
+/// * `format` - The output format of the section
+///
+/// # Returns
+/// The section, with a `--- a/path`/`+++ b/path` header per file, or a single line noting
+/// that nothing changed
+fn render_diff(diffs: &[git::FileDiff], rev: &str, format: OutputFormat) -> String {
+    let insertions: usize = diffs.iter().map(|diff| diff.insertions).sum();
+    let deletions: usize = diffs.iter().map(|diff| diff.deletions).sum();
+    let totals = if diffs.is_empty() {
+        format!("No changes since {}\n", rev)
+    } else {
+        format!(
+            "{} file{} changed, {} insertion{}(+), {} deletion{}(-)\n",
+            diffs.len(),
+            if diffs.len() == 1 { "" } else { "s" },
+            insertions,
+            if insertions == 1 { "" } else { "s" },
+            deletions,
+            if deletions == 1 { "" } else { "s" },
+        )
+    };
+
+    let mut section = match format {
+        OutputFormat::Markdown => format!("## Diff (since {})\n\n{}", rev, totals),
+        OutputFormat::Xml => format!(
+            "<diff since=\"{}\">\n<summary>{}</summary>\n",
+            escape_xml_attribute(rev),
+            escape_xml_text(totals.trim_end())
+        ),
+        OutputFormat::Text | OutputFormat::Json => format!("Diff (since {}):\n{}", rev, totals),
+    };
+    for diff in diffs {
+        let path = diff.path.to_string_lossy().replace('\\', "/");
+        let patch = match &diff.hunks {
+            Some(hunks) => {
+                // Added and deleted files are compared with nothing, as git shows them
+                let old = if hunks.starts_with("@@ -0,0 ") {
+                    "/dev/null".to_string()
+                } else {
+                    format!("a/{}", path)
+                };
+                let new = if hunks.contains(" +0,0 @@") {
+                    "/dev/null".to_string()
+                } else {
+                    format!("b/{}", path)
+                };
+                format!("--- {}\n+++ {}\n{}", old, new, hunks)
+            }
+            None => format!("Binary files a/{} and b/{} differ\n", path, path),
+        };
+        let counts = format!("+{} -{}", diff.insertions, diff.deletions);
+        section.push_str(&match format {
+            OutputFormat::Markdown => {
+                let fence = code_fence(&patch);
+                format!(
+                    "\n### {} ({})\n\n{}diff\n{}{}\n",
+                    path, counts, fence, patch, fence
+                )
+            }
+            OutputFormat::Xml => format!(
+                "<file path=\"{}\" insertions=\"{}\" deletions=\"{}\">\n{}</file>\n",
+                escape_xml_attribute(&path),
+                diff.insertions,
+                diff.deletions,
+                escape_xml_text(&patch)
+            ),
+            OutputFormat::Text | OutputFormat::Json => format!(
+                "\n================================================\nDIFF: {} ({})\n================================================\n{}",
+                path, counts, patch
+            ),
+        });
+    }
+    if format == OutputFormat::Xml {
+        section.push_str("</diff>\n");
+    }
+    section
+}
+
+/// Converts the changed files of `--diff` into a JSON object
+///
+/// # Arguments
+/// * `diffs` - The changed files
+/// * `rev` - The revision the files were compared against
+///
+/// # Returns
+/// `{since, files}` with one `{path, insertions, deletions, patch}` object per file; binary
+/// files have `binary: true` instead of a patch
+fn diff_json(diffs: &[git::FileDiff], rev: &str) -> Json {
+    let files = diffs
+        .iter()
+        .map(|diff| {
+            let mut fields = vec![
+                (
+                    "path",
+                    Json::String(diff.path.to_string_lossy().to_string()),
+                ),
+                ("insertions", Json::Number(diff.insertions as u64)),
+                ("deletions", Json::Number(diff.deletions as u64)),
+            ];
+            match &diff.hunks {
+                Some(hunks) => fields.push(("patch", Json::String(hunks.clone()))),
+                None => fields.push(("binary", Json::Bool(true))),
+            }
+            Json::object(fields)
+        })
+        .collect();
+    Json::object([
+        ("since", Json::String(rev.to_string())),
+        ("files", Json::Array(files)),
+    ])
+}
+
+/// Renders the `--git-info` block describing the state of the repository
+///
+/// # Arguments
+/// * `info` - The branch, commit and remote of the repository
+/// * `format` - The output format of the block
+///
+/// # Returns
+/// A `Branch`, `Commit` and `Remote` line each; the remote line is left out when there is no
+/// `origin` remote
+///
+/// # Examples
+/// ```
+/// // Git Info:
+/// // Branch: main
+/// // Commit: 4f2a9c1e0b7d... Fix the parser
+/// // Remote: https://github.com/user/repo.git
+/// ```
+fn render_git_info(info: &git::RepoInfo, format: OutputFormat) -> String {
+    let branch = info.branch.as_deref().unwrap_or("(detached HEAD)");
+    let commit = match (&info.commit, &info.summary) {
+        (Some(sha), Some(summary)) => format!("{} {}", sha, summary),
+        (Some(sha), None) => sha.clone(),
+        (None, _) => "(no commits)".to_string(),
+    };
+    let mut lines = vec![("Branch", branch.to_string()), ("Commit", commit)];
+    if let Some(remote) = &info.remote {
+        lines.push(("Remote", remote.clone()));
+    }
+
+    match format {
+        OutputFormat::Markdown => {
+            let mut block = String::from("## Git Info\n\n");
+            for (label, value) in lines {
+                block.push_str(&format!("- {}: `{}`\n", label, value));
+            }
+            block
+        }
+        OutputFormat::Xml => {
+            let mut block = String::from("<git_info>\n");
+            for (label, value) in lines {
+                let tag = label.to_lowercase();
+                block.push_str(&format!("<{}>{}</{}>\n", tag, escape_xml_text(&value), tag));
+            }
+            block.push_str("</git_info>\n");
+            block
+        }
+        OutputFormat::Text | OutputFormat::Json => {
+            let mut block = String::from("Git Info:\n");
+            for (label, value) in lines {
+                block.push_str(&format!("{}: {}\n", label, value));
+            }
+            block
+        }
+    }
+}
+
+/// Formats a commit time, in seconds since the Unix epoch, as an ISO 8601 UTC timestamp
+fn commit_date(time: i64) -> String {
+    let seconds = u64::try_from(time).unwrap_or(0);
+    format_utc_timestamp(std::time::UNIX_EPOCH + std::time::Duration::from_secs(seconds))
+}
+
+/// Formats how long before `now` a commit was made, as in `3 hours ago`
+///
+/// Times are in seconds since the Unix epoch. Durations are rounded down to the largest
+/// whole unit; months count as 30 days and years as 365.
+///
+/// # Examples
+/// ```
+/// assert_eq!(relative_time(0, 7_200), "2 hours ago");
+/// assert_eq!(relative_time(0, 86_400), "1 day ago");
+/// assert_eq!(relative_time(100, 130), "just now");
+/// ```
+fn relative_time(time: i64, now: i64) -> String {
+    const UNITS: [(i64, &str); 5] = [
+        (365 * 86_400, "year"),
+        (30 * 86_400, "month"),
+        (86_400, "day"),
+        (3_600, "hour"),
+        (60, "minute"),
+    ];
+    // A commit dated after `now`, from clock skew, is treated as just made
+    let elapsed = now.saturating_sub(time);
+    for (seconds, unit) in UNITS {
+        let count = elapsed / seconds;
+        if count > 0 {
+            let plural = if count == 1 { "" } else { "s" };
+            return format!("{} {}{} ago", count, unit, plural);
+        }
+    }
+    "just now".to_string()
+}
+
+/// Renders the `--git-log` section
+///
+/// # Arguments
+/// * `commits` - The commits, newest first
+/// * `format` - The output format of the section
+/// * `now` - With `--relative-time`, the current time in seconds since the Unix epoch, which
+///   the dates are given relative to; `None` shows UTC timestamps
+///
+/// # Returns
+/// The section with each commit's hash, author, date and indented message as `git log`
+/// shows them; Markdown wraps them in a code block and XML uses a `<commit>` element each
+fn render_git_log(commits: &[git::LogEntry], format: OutputFormat, now: Option<i64>) -> String {
+    let date = |commit: &git::LogEntry| match now {
+        Some(now) => relative_time(commit.time, now),
+        None => commit_date(commit.time),
+    };
+    if format == OutputFormat::Xml {
+        let mut block = String::from("<git_log>\n");
+        for commit in commits {
+            block.push_str(&format!(
+                "<commit id=\"{}\" author=\"{}\" date=\"{}\">\n{}\n</commit>\n",
+                commit.id,
+                escape_xml_attribute(&commit.author),
+                date(commit),
+                escape_xml_text(&commit.message)
+            ));
+        }
+        block.push_str("</git_log>\n");
+        return block;
+    }
+
+    let mut log = String::new();
+    for (i, commit) in commits.iter().enumerate() {
+        if i > 0 {
+            log.push('\n');
+        }
+        log.push_str(&format!(
+            "commit {}\nAuthor: {}\nDate: {}\n\n",
+            commit.id,
+            commit.author,
+            date(commit)
+        ));
+        for line in commit.message.lines() {
+            if line.trim().is_empty() {
+                log.push('\n');
+            } else {
+                log.push_str(&format!("    {}\n", line));
+            }
+        }
+    }
+    let heading = format!(
+        "Git Log (last {} commit{})",
+        commits.len(),
+        if commits.len() == 1 { "" } else { "s" }
+    );
+    match format {
+        OutputFormat::Markdown => {
+            let fence = code_fence(&log);
+            format!("## {}\n\n{}\n{}{}\n", heading, fence, log, fence)
+        }
+        _ => format!("{}:\n{}", heading, log),
+    }
+}
+
+/// Converts the `--git-log` section into JSON
+///
+/// # Returns
+/// An array of `{id, author, date, message}` objects, newest first
+fn git_log_json(commits: &[git::LogEntry]) -> Json {
+    Json::Array(
+        commits
+            .iter()
+            .map(|commit| {
+                Json::object([
+                    ("id", Json::String(commit.id.clone())),
+                    ("author", Json::String(commit.author.clone())),
+                    ("date", Json::String(commit_date(commit.time))),
+                    ("message", Json::String(commit.message.clone())),
+                ])
+            })
+            .collect(),
+    )
+}
+
+/// Converts the `--git-info` block into JSON
+///
+/// # Returns
+/// A `{branch, commit, message, remote}` object; fields that are unknown are left out
+fn git_info_json(info: &git::RepoInfo) -> Json {
+    let fields = [
+        ("branch", &info.branch),
+        ("commit", &info.commit),
+        ("message", &info.summary),
+        ("remote", &info.remote),
+    ];
+    Json::object(
+        fields
+            .into_iter()
+            .filter_map(|(name, value)| Some((name, Json::String(value.clone()?)))),
+    )
+}
+
+/// Converts the changed files of `--diff-stat` into a JSON array
+///
+/// # Arguments
+/// * `stats` - The changed files
+///
+/// # Returns
+/// One `{path, insertions, deletions}` object per file
+fn diff_stat_json(stats: &[git::DiffStat]) -> Json {
+    Json::Array(
+        stats
+            .iter()
+            .map(|stat| {
+                Json::object([
+                    (
+                        "path",
+                        Json::String(stat.path.to_string_lossy().to_string()),
+                    ),
+                    ("insertions", Json::Number(stat.insertions as u64)),
+                    ("deletions", Json::Number(stat.deletions as u64)),
+                ])
+            })
+            .collect(),
+    )
+}
+
+/// Lays out the rows of a language table, the first column left-aligned and the others
+/// right-aligned, with two spaces between columns
+///
+/// # Arguments
+/// * `rows` - The header row followed by the data rows
+/// * `indent` - Text placed before each row
+///
+/// # Returns
+/// One line per row
+fn render_language_table(rows: &[Vec<String>], indent: &str) -> String {
+    let columns = rows.first().map_or(0, Vec::len);
+    let widths: Vec<usize> = (0..columns)
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+        .collect();
+    let mut table = String::new();
+    for row in rows {
+        table.push_str(indent);
+        for (column, (cell, width)) in row.iter().zip(&widths).enumerate() {
+            if column == 0 {
+                table.push_str(&format!("{:<width$}", cell));
+            } else {
+                table.push_str(&format!("  {:>width$}", cell));
+            }
+        }
+        table.push('\n');
+    }
+    table
+}
+
+/// Renders the table of `--language-stats-only`
+///
+/// # Arguments
+/// * `stats` - Totals of each language, in display order
+///
+/// # Returns
+/// A table with one row per language followed by a total row
+fn render_language_stats(stats: &[language::LanguageStats]) -> String {
+    let total_files: usize = stats.iter().map(|language| language.files).sum();
+    let total_lines: usize = stats.iter().map(|language| language.lines).sum();
+    let total_bytes: u64 = stats.iter().map(|language| language.bytes).sum();
+
+    let mut rows = vec![vec![
+        "Language".to_string(),
+        "Files".to_string(),
+        "Lines".to_string(),
+        "Bytes".to_string(),
+    ]];
+    for language in stats {
+        rows.push(vec![
+            language.name.to_string(),
+            format_count(language.files),
+            format_count(language.lines),
+            format_count(language.bytes as usize),
+        ]);
+    }
+    rows.push(vec![
+        "Total".to_string(),
+        format_count(total_files),
+        format_count(total_lines),
+        format_count(total_bytes as usize),
+    ]);
+    render_language_table(&rows, "")
+}
+
+/// Renders the file list of `--dry-run`
+///
+/// # Arguments
+/// * `files` - Each file of the contents with its estimated tokens, in dump order
+/// * `tree_files` - Number of files in the tree structure, or `None` when it is not shown
+///
+/// # Returns
+/// One line per file followed by the totals
+///
+/// # Examples
+/// ```
+/// let report = render_dry_run(&[(PathBuf::from("src/main.rs"), 1200)], Some(3));
+/// assert_eq!(report, "1,200  src/main.rs\n\n1 file in contents, 1,200 estimated tokens\n3 files in tree\n");
+/// ```
+fn render_dry_run(files: &[(PathBuf, usize)], tree_files: Option<usize>) -> String {
+    let counts: Vec<String> = files
+        .iter()
+        .map(|(_, tokens)| format_count(*tokens))
+        .collect();
+    let width = counts.iter().map(String::len).max().unwrap_or(0);
+    let mut report: String = files
+        .iter()
+        .zip(&counts)
+        .map(|((path, _), count)| format!("{:>width$}  {}\n", count, path.display()))
+        .collect();
+    if !files.is_empty() {
+        report.push('\n');
+    }
+    let total: usize = files.iter().map(|(_, tokens)| tokens).sum();
+    let plural = |count: usize| if count == 1 { "file" } else { "files" };
+    report.push_str(&format!(
+        "{} {} in contents, {} estimated tokens\n",
+        format_count(files.len()),
+        plural(files.len()),
+        format_count(total)
+    ));
+    if let Some(count) = tree_files {
+        report.push_str(&format!(
+            "{} {} in tree\n",
+            format_count(count),
+            plural(count)
+        ));
+    }
+    report
+}
+
+/// Converts the totals of `--language-stats-only` into JSON
+///
+/// # Arguments
+/// * `stats` - Totals of each language, in display order
+///
+/// # Returns
+/// An object with a `languages` array of `{language, files, lines, bytes}` objects
+fn language_stats_json(stats: &[language::LanguageStats]) -> Json {
+    let languages = stats
+        .iter()
+        .map(|language| {
+            Json::object([
+                ("language", Json::String(language.name.to_string())),
+                ("files", Json::Number(language.files as u64)),
+                ("lines", Json::Number(language.lines as u64)),
+                ("bytes", Json::Number(language.bytes)),
+            ])
+        })
+        .collect();
+    Json::object([("languages", Json::Array(languages))])
+}
+
+/// Converts file entries into the `files` array of the JSON document
+///
+/// # Arguments
+/// * `entries` - The files loaded by `load_file_contents`
+///
+/// # Returns
+/// One `{path, language, size_bytes, token_estimate, contents}` object per file, plus
+/// `encoding` for embedded binaries; `language` is left out when it is unknown
+fn file_contents_json(entries: &[FileEntry]) -> Vec<Json> {
+    entries
+        .iter()
+        .map(|entry| {
+            let mut fields = vec![(
+                "path",
+                Json::String(entry.path.to_string_lossy().to_string()),
+            )];
+            let language = language::language_for_path(&entry.path).filter(|_| !entry.is_command);
+            if let Some(language) = language {
+                fields.push(("language", Json::String(language.name.to_string())));
+            }
+            fields.push(("size_bytes", Json::Number(entry.size_bytes as u64)));
+            fields.push(("token_estimate", Json::Number(entry.tokens as u64)));
+            fields.push(("contents", Json::String(entry.body.clone())));
+            if let Some(encoding) = entry.encoding {
+                fields.push(("encoding", Json::String(encoding.to_string())));
+            }
+            Json::object(fields)
+        })
+        .collect()
+}
+
+/// Loads the content hashes recorded by earlier runs
+///
+/// # Arguments
+/// * `path` - The hash store, one hash per line; a missing file is an empty store
+///
+/// # Returns
+/// The set of recorded hashes
+fn load_hash_store(path: &Path) -> Result<BTreeSet<String>> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect()),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(BTreeSet::new()),
+        Err(error) => {
+            Err(error).with_context(|| format!("Failed to read hash store: {}", path.display()))
+        }
+    }
+}
+
+/// Appends newly emitted content hashes to the hash store, creating it if needed
+///
+/// # Arguments
+/// * `path` - The hash store
+/// * `hashes` - Hashes to record
+fn append_hash_store(path: &Path, hashes: &[String]) -> Result<()> {
+    let lines: String = hashes.iter().map(|hash| format!("{}\n", hash)).collect();
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut store| store.write_all(lines.as_bytes()))
+        .with_context(|| {
+            ExitError::new(
+                ExitStatus::WriteFailed,
+                format!("Failed to update hash store: {}", path.display()),
+            )
+        })
+}
+
+/// Largest binary file embedded by `--include-binary-base64`
+const BINARY_BASE64_MAX_BYTES: usize = 1024 * 1024;
+
+/// Characters of the standard base64 alphabet
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes bytes as padded base64, wrapped at 76 characters per line
+///
+/// # Arguments
+/// * `bytes` - The data to encode
+///
+/// # Returns
+/// The encoded text, ending with a newline
+///
+/// # Examples
+/// ```
+/// assert_eq!(encode_base64(b"repodump"), "cmVwb2R1bXA=\n");
+/// ```
+fn encode_base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len() * 4 / 3 + 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| {
+            group | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64_ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    let mut wrapped = String::with_capacity(encoded.len() + encoded.len() / 76 + 1);
+    for line in encoded.as_bytes().chunks(76) {
+        wrapped.push_str(std::str::from_utf8(line).unwrap_or_default());
+        wrapped.push('\n');
+    }
+    wrapped
+}
+
+/// Markers that identify a leading comment as a license header when no marker file is given
+const DEFAULT_LICENSE_MARKERS: &[&str] = &[
+    "copyright",
+    "license",
+    "licence",
+    "spdx-license-identifier",
+    "all rights reserved",
+];
+
+/// Loads license header markers from a file with one marker per line
+///
+/// # Arguments
+/// * `path` - The marker file, or `None` to use the built-in markers
+///
+/// # Returns
+/// The list of markers, matched case-insensitively against leading comments
+fn load_license_markers(path: Option<&Path>) -> Result<Vec<String>> {
+    match path {
+        Some(path) => {
+            let markers = fs::read_to_string(path).with_context(|| {
+                format!("Failed to read license marker file: {}", path.display())
+            })?;
+            Ok(markers
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect())
+        }
+        None => Ok(DEFAULT_LICENSE_MARKERS
+            .iter()
+            .map(|marker| marker.to_string())
+            .collect()),
+    }
+}
+
+/// Resolves the paths listed for `--files-from` against the root
+///
+/// Each non-empty line is one path, relative to the root or absolute inside it. A leading
+/// `./` is dropped and `\` is read as a separator. Listed paths that do not exist, such as
+/// files deleted in a diff, are skipped with a warning.
+///
+/// # Arguments
+/// * `root_path` - The root directory being scanned
+/// * `listing` - The newline-separated paths
+///
+/// # Returns
+/// The listed files, sorted and deduplicated, or an error if a path lies outside the root
+///
+/// # Examples
+/// ```
+/// let collected = collect_listed_files(Path::new("."), "./src/main.rs\nsrc/main.rs\n")?;
+/// assert_eq!(collected.files, vec![PathBuf::from("src/main.rs")]);
+/// ```
+fn collect_listed_files(root_path: &Path, listing: &str) -> Result<CollectedFiles> {
+    let canonical_root = fs::canonicalize(root_path)
+        .with_context(|| format!("Failed to resolve root: {}", root_path.display()))?;
+    let mut files = BTreeSet::new();
+    let mut collected = CollectedFiles::default();
+    for line in listing.lines().map(str::trim_end) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line = line.replace('\\', "/");
+        let listed = PathBuf::from(line.strip_prefix("./").unwrap_or(&line));
+        let full_path = root_path.join(&listed);
+        let Ok(metadata) = fs::metadata(&full_path) else {
+            eprintln!("Warning: skipping missing file from --files-from: {}", line);
+            continue;
+        };
+        // The parent is resolved rather than the file, so symlinked files keep their name
+        let inside_root = full_path
+            .parent()
+            .and_then(|parent| fs::canonicalize(parent).ok())
+            .zip(full_path.file_name())
+            .and_then(|(parent, name)| {
+                parent
+                    .join(name)
+                    .strip_prefix(&canonical_root)
+                    .ok()
+                    .map(Path::to_path_buf)
+            });
+        let Some(relative_path) = inside_root.filter(|path| !path.as_os_str().is_empty()) else {
+            anyhow::bail!(ExitError::new(
+                ExitStatus::BadArguments,
+                format!("Path from --files-from is outside the root: {}", line),
+            ));
+        };
+        if !metadata.is_file() {
+            eprintln!("Warning: skipping non-file from --files-from: {}", line);
+            continue;
+        }
+        if fs::symlink_metadata(&full_path).is_ok_and(|metadata| metadata.is_symlink()) {
+            if let Some(target) = resolve_symlink_target(root_path, &full_path) {
+                collected.symlinks.insert(relative_path.clone(), target);
+            }
+            collected.symlinked_files.insert(relative_path.clone());
+        }
+        files.insert(relative_path);
+    }
+    collected.files = files.into_iter().collect();
+    Ok(collected)
+}
+
+/// Reads the exact paths listed in `--exclude-paths-file` files
+///
+/// Each non-empty line is one path relative to the root, taken literally: `*`, `?`, `[` and
+/// `{` have no special meaning. A leading `./` is dropped and `\` is read as a separator.
+///
+/// # Arguments
+/// * `paths` - The files listing paths to exclude
+///
+/// # Returns
+/// The listed paths, or an error if a file cannot be read
+fn load_excluded_paths(paths: &[PathBuf]) -> Result<BTreeSet<PathBuf>> {
+    let mut excluded = BTreeSet::new();
+    for path in paths {
+        let listing = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read exclude paths file: {}", path.display()))?;
+        excluded.extend(
+            listing
+                .lines()
+                .map(|line| line.trim_end_matches('\r'))
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| {
+                    let line = line.replace('\\', "/");
+                    PathBuf::from(line.strip_prefix("./").unwrap_or(&line))
+                }),
+        );
+    }
+    Ok(excluded)
+}
+
+/// Removes a leading comment block from a file if it looks like a license header
+///
+/// Only the first comment in the file is considered, after an optional shebang line and
+/// blank lines. Block comments (`/* */`, `<!-- -->`) and runs of line comments (`//`, `#`,
+/// `--`, `;`) are recognised.
+///
+/// # Arguments
+/// * `content` - The file content
+/// * `markers` - Case-insensitive markers, any of which identifies a license header
+///
+/// # Returns
+/// The content without the header, or `None` if no license header was found
+///
+/// # Examples
+/// ```
+/// let markers = vec!["copyright".to_string()];
+/// let stripped = strip_license_header("// Copyright 2024\nfn main() {}\n", &markers);
+/// assert_eq!(stripped.as_deref(), Some("fn main() {}\n"));
+/// ```
+fn strip_license_header(content: &str, markers: &[String]) -> Option<String> {
+    // Keep a shebang line in place
+    let shebang_len = if content.starts_with("#!") {
+        content.find('\n').map(|i| i + 1).unwrap_or(content.len())
+    } else {
+        0
+    };
+    let (shebang, rest) = content.split_at(shebang_len);
+    let rest = rest.trim_start();
+
+    let header_len = if let Some(block) = rest.strip_prefix("/*") {
+        block.find("*/")? + 4
+    } else if let Some(block) = rest.strip_prefix("<!--") {
+        block.find("-->")? + 7
+    } else {
+        let comment_prefix = ["//", "#", "--", ";"]
+            .into_iter()
+            .find(|prefix| rest.starts_with(prefix))?;
+        rest.split_inclusive('\n')
+            .take_while(|line| line.trim_start().starts_with(comment_prefix))
+            .map(str::len)
+            .sum()
+    };
+
+    let (header, remainder) = rest.split_at(header_len);
+    let header = header.to_lowercase();
+    if !markers
+        .iter()
+        .any(|marker| header.contains(&marker.to_lowercase()))
+    {
+        return None;
+    }
+
+    Some(format!(
+        "{}{}",
+        shebang,
+        remainder.trim_start_matches(['\r', '\n'])
+    ))
+}
+
+/// Patterns identifying generated files when `--skip-generated` is used
+const DEFAULT_GENERATED_PATTERNS: &[&str] = &[
+    r"(?i)code generated .* do not edit",
+    r"(?i)generated by the protocol buffer compiler",
+    r"@generated",
+    r"(?i)auto-?generated.*do not (edit|modify)",
+    r"(?i)this file (was|is) (automatically |auto-?)generated",
+];
+
+/// Number of bytes at the start of a file scanned for generated-code markers
+const GENERATED_MARKER_SCAN_BYTES: u64 = 1024;
+
+/// Compiles the generated-code marker patterns
+///
+/// # Arguments
+/// * `extra_patterns` - User supplied regexes added to the built-in patterns
+///
+/// # Returns
+/// The compiled patterns or an error naming the first invalid one
+fn build_generated_patterns(extra_patterns: &[String]) -> Result<Vec<Regex>> {
+    DEFAULT_GENERATED_PATTERNS
+        .iter()
+        .copied()
+        .chain(extra_patterns.iter().map(String::as_str))
+        .map(|pattern| {
+            Regex::new(pattern).with_context(|| {
+                ExitError::new(
+                    ExitStatus::BadArguments,
+                    format!("Invalid generated-code pattern: {}", pattern),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Determines if a file looks generated by scanning its head for marker comments
+///
+/// # Arguments
+/// * `path` - Full path of the file
+/// * `patterns` - Compiled generated-code marker patterns
+///
+/// # Returns
+/// `true` if any pattern matches the first bytes of the file
+fn is_generated_file(path: &Path, patterns: &[Regex]) -> bool {
+    let Ok(file) = fs::File::open(path) else {
+        return false;
+    };
+    let mut head = Vec::new();
+    if file
+        .take(GENERATED_MARKER_SCAN_BYTES)
+        .read_to_end(&mut head)
+        .is_err()
+    {
+        return false;
+    }
+    let head = String::from_utf8_lossy(&head);
+    patterns.iter().any(|pattern| pattern.is_match(&head))
+}
+
+/// Formats a point in time as an ISO 8601 UTC timestamp with second precision
+///
+/// # Arguments
+/// * `time` - The time to format
+///
+/// # Returns
+/// A timestamp such as `2024-05-01T12:30:00Z`
+fn format_utc_timestamp(time: std::time::SystemTime) -> String {
+    let seconds = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let (days, second_of_day) = (seconds / 86_400, seconds % 86_400);
+
+    // Convert days since the epoch to a civil date (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        second_of_day / 3_600,
+        second_of_day % 3_600 / 60,
+        second_of_day % 60
+    )
+}
+
+/// Generates the YAML front-matter block placed before the dump with `--front-matter`
+///
+/// # Arguments
+/// * `repository` - Name of the dumped repository
+/// * `generated_at` - Timestamp of the run
+/// * `file_count` - Number of files in the contents section
+/// * `token_count` - Estimated tokens of the dump without the front matter
+///
+/// # Returns
+/// The block, delimited by `---` lines
+///
+/// # Examples
+/// ```
+/// let block = generate_front_matter("repodump", "2024-05-01T12:30:00Z", 3, 120);
+/// assert!(block.starts_with("---\nrepo: \"repodump\"\n"));
+/// ```
+fn generate_front_matter(
+    repository: &str,
+    generated_at: &str,
+    file_count: usize,
+    token_count: usize,
+) -> String {
+    // JSON strings are valid YAML scalars, which takes care of quoting
+    format!(
+        "---\nrepo: {}\ngenerated: {}\nfiles: {}\ntokens: {}\n---\n\n",
+        Json::String(repository.to_string()).to_pretty_string(),
+        generated_at,
+        file_count,
+        token_count
+    )
+}
+
+/// Estimates the number of LLM tokens in the text
+///
+/// # Arguments
+/// * `text` - The text to analyze
+///
+/// # Returns
+/// Estimated number of tokens (characters / 4)
+///
+/// # Examples
+/// ```
+/// assert_eq!(estimate_tokens("Hello world"), 2);
+/// assert_eq!(estimate_tokens("This is a test"), 3);
+/// ```
+fn estimate_tokens(text: &str) -> usize {
+    text.chars().count() / 4
+}
+
+/// Tuning of the token estimate for dense content
+///
+/// Minified code and other very long lines tokenize worse than prose, so characters on
+/// lines longer than `long_line_threshold` are weighted by `long_line_factor` before the
+/// characters / 4 heuristic is applied. The default applies no penalty.
+#[derive(Clone, Copy, Debug, Default)]
+struct TokenEstimator {
+    /// Lines with more characters than this are penalized, or `None` to disable the penalty
+    long_line_threshold: Option<usize>,
+    /// Weight of each character on a long line
+    long_line_factor: f64,
+    /// Threads used by `estimate_each`; 0 or 1 counts on the calling thread
+    jobs: usize,
+}
+
+impl TokenEstimator {
+    /// Estimates the number of LLM tokens in the text, penalizing long lines
+    ///
+    /// # Arguments
+    /// * `text` - The text to analyze
+    ///
+    /// # Returns
+    /// Estimated number of tokens
+    ///
+    /// # Examples
+    /// ```
+    /// let estimator = TokenEstimator {
+    ///     long_line_threshold: Some(10),
+    ///     long_line_factor: 2.0,
+    ///     ..Default::default()
+    /// };
+    /// assert_eq!(estimator.estimate("short\n"), 1);
+    /// assert_eq!(estimator.estimate("a_very_long_line\n"), 8);
+    /// ```
+    fn estimate(&self, text: &str) -> usize {
+        let Some(threshold) = self.long_line_threshold else {
+            return estimate_tokens(text);
+        };
+        let (mut short_chars, mut long_chars) = (0, 0);
+        for line in text.split_inclusive('\n') {
+            let chars = line.chars().count();
+            if line.trim_end_matches(['\n', '\r']).chars().count() > threshold {
+                long_chars += chars;
+            } else {
+                short_chars += chars;
+            }
+        }
+        (short_chars + (long_chars as f64 * self.long_line_factor).round() as usize) / 4
+    }
+
+    /// Estimates the tokens of each text, spreading the texts over `jobs` threads
+    ///
+    /// Each text is counted on its own, so the result is the same for any number of jobs.
+    ///
+    /// # Arguments
+    /// * `texts` - The texts to analyze
+    ///
+    /// # Returns
+    /// Estimated number of tokens of each text, in the same order
+    fn estimate_each(&self, texts: &[&str]) -> Vec<usize> {
+        parallel_map(texts, self.jobs, |text| self.estimate(text))
+    }
+}
+
+/// Maps items on up to `jobs` threads, keeping the results in the order of the items
+///
+/// The items are split into one contiguous chunk per thread. With `jobs` of 0 or 1, or a
+/// single item, everything runs on the calling thread.
+///
+/// # Arguments
+/// * `items` - The items to map
+/// * `jobs` - Maximum number of threads
+/// * `map` - The function applied to each item
+///
+/// # Returns
+/// The mapped items, in the same order
+fn parallel_map<T, R>(items: &[T], jobs: usize, map: impl Fn(&T) -> R + Sync) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+{
+    if jobs <= 1 || items.len() <= 1 {
+        return items.iter().map(map).collect();
+    }
+    let chunk_size = items.len().div_ceil(jobs);
+    let map = &map;
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = items
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(map).collect::<Vec<_>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("worker thread panicked"))
+            .collect()
+    })
+}
+
+/// Token usage of a single top-level directory under `--budget-per-dir`
+#[derive(Debug, PartialEq, Eq)]
+struct DirTokenUsage {
+    /// The top-level directory name, or `.` for files in the root
+    dir: String,
+    /// Estimated tokens of the files kept from this directory
+    tokens: usize,
+    /// Number of files kept from this directory
+    files_kept: usize,
+    /// Number of files dropped because the directory was over budget
+    files_dropped: usize,
+}
+
+/// Caps the estimated tokens contributed by each top-level directory
+///
+/// Files are considered in the given order; a file is dropped if adding it would push its
+/// top-level directory over the budget. Files count as rendered, so transcoded and embedded
+/// binary files, transforms and banners all weigh in. Command outputs are always kept.
+///
+/// # Arguments
+/// * `entries` - The files loaded by `load_file_contents`, in output order
+/// * `budget` - Maximum estimated tokens per top-level directory
+/// * `entry_tokens` - Estimates the tokens of one file as rendered in the output
+///
+/// # Returns
+/// The usage of each directory; the entries keep only the files that fit
+fn apply_dir_budget(
+    entries: &mut Vec<FileEntry>,
+    budget: usize,
+    entry_tokens: &dyn Fn(&FileEntry) -> usize,
+) -> Vec<DirTokenUsage> {
+    let mut usage: BTreeMap<String, DirTokenUsage> = BTreeMap::new();
+
+    entries.retain(|entry| {
+        if entry.is_command {
+            return true;
+        }
+        let file_path = &entry.path;
+        let dir = if file_path.components().count() > 1 {
+            file_path
+                .components()
+                .next()
+                .map(|component| component.as_os_str().to_string_lossy().to_string())
+                .unwrap_or_default()
+        } else {
+            ".".to_string()
+        };
+        let tokens = entry_tokens(entry);
+
+        let dir_usage = usage.entry(dir.clone()).or_insert(DirTokenUsage {
+            dir,
+            tokens: 0,
+            files_kept: 0,
+            files_dropped: 0,
+        });
+        if dir_usage.tokens + tokens > budget {
+            dir_usage.files_dropped += 1;
+            false
+        } else {
+            dir_usage.tokens += tokens;
+            dir_usage.files_kept += 1;
+            true
+        }
+    });
+
+    usage.into_values().collect()
+}
+
+/// Files left out or cut short by `--max-tokens`
+#[derive(Debug, Default, PartialEq, Eq)]
+struct TokenBudgetReport {
+    /// Files dropped because they did not fit, in output order
+    skipped: Vec<PathBuf>,
+    /// A file cut to fit the remaining budget, with the estimated tokens removed
+    truncated: Option<(PathBuf, usize)>,
+}
+
+/// Keeps the files that fit within a total token budget for the whole output
+///
+/// The budget covers the output as rendered: banners and the sections around the contents,
+/// such as the tree, prompt and git details, count along with the file bodies. Files are
+/// added in order until the next one would exceed the budget; it and all later files are
+/// skipped. With `truncate`, the first file that does not fit is instead cut at a line
+/// boundary to the remaining budget, ending with a `truncated` notice. With
+/// `smallest_first`, files are added from the fewest tokens up, which fits more of them,
+/// while the kept files stay in their original order.
+///
+/// # Arguments
+/// * `entries` - The files in output order, as they will be rendered; skipped files are
+///   removed and a truncated file's body is cut
+/// * `budget` - Maximum estimated tokens of the whole output
+/// * `document_tokens` - Estimates the tokens of the output holding a run of files
+/// * `entry_tokens` - Estimates the tokens a file adds to the output, banner included
+/// * `estimator` - Estimator used to cut a file to the remaining budget
+/// * `truncate` - Cut the first file that does not fit instead of skipping it
+/// * `smallest_first` - Consider files from the fewest tokens up
+///
+/// # Returns
+/// The files skipped or truncated
+fn apply_token_budget(
+    entries: &mut Vec<FileEntry>,
+    budget: usize,
+    document_tokens: &mut dyn FnMut(&[FileEntry]) -> Result<usize>,
+    entry_tokens: &dyn Fn(&FileEntry) -> usize,
+    estimator: &TokenEstimator,
+    truncate: bool,
+    smallest_first: bool,
+) -> Result<TokenBudgetReport> {
+    let available = budget.saturating_sub(document_tokens(&[])?);
+    let tokens: Vec<usize> = entries.iter().map(entry_tokens).collect();
+    let mut order: Vec<usize> = (0..entries.len()).collect();
+    if smallest_first {
+        order.sort_by_key(|&i| tokens[i]);
+    }
+
+    let mut used = 0;
+    // Indices of the kept files in the order they were added
+    let mut added = Vec::new();
+    let mut report = TokenBudgetReport::default();
+    for i in order {
+        if used + tokens[i] <= available {
+            used += tokens[i];
+            added.push(i);
+            continue;
+        }
+        if truncate {
+            let entry = &mut entries[i];
+            let body = std::mem::take(&mut entry.body);
+            let mut body_budget = (available - used).saturating_sub(entry_tokens(entry));
+            // The rendered file can estimate a little above its banner and body apart, so
+            // cut further until it fits
+            while let Some((truncated, tokens_cut)) =
+                truncate_to_tokens(&body, body_budget, estimator)
+            {
+                entry.body = truncated;
+                if used + entry_tokens(entry) <= available {
+                    entry.tokens = estimator.estimate(&entry.body);
+                    report.truncated = Some((entry.path.clone(), tokens_cut));
+                    added.push(i);
+                    break;
+                }
+                if body_budget == 0 {
+                    break;
+                }
+                body_budget -= 1;
+            }
+            if report.truncated.is_none() {
+                entry.body = body;
+            }
+        }
+        break;
+    }
+
+    let mut kept_indices: Vec<usize> = added.clone();
+    kept_indices.sort_unstable();
+    let mut skipped = Vec::new();
+    for (i, entry) in std::mem::take(entries).into_iter().enumerate() {
+        if kept_indices.binary_search(&i).is_ok() {
+            entries.push(entry);
+        } else {
+            skipped.push((i, entry.path));
+        }
+    }
+    // Estimates of the pieces can add up to less than the estimate of the whole, so drop
+    // the last added files until the output itself fits
+    while !entries.is_empty() && document_tokens(entries)? > budget {
+        let last = added.pop().expect("every kept file was added");
+        let position = kept_indices
+            .binary_search(&last)
+            .expect("every added file is kept");
+        kept_indices.remove(position);
+        let entry = entries.remove(position);
+        if report
+            .truncated
+            .as_ref()
+            .is_some_and(|(path, _)| *path == entry.path)
+        {
+            report.truncated = None;
+        }
+        skipped.push((last, entry.path));
+    }
+    skipped.sort();
+    report.skipped = skipped.into_iter().map(|(_, path)| path).collect();
+    Ok(report)
+}
+
+/// Cuts text at a line boundary so its estimated tokens fit a budget
+///
+/// # Arguments
+/// * `text` - The file body
+/// * `budget` - Maximum estimated tokens of the kept lines and the notice
+/// * `estimator` - Estimator of the tokens
+///
+/// # Returns
+/// The leading lines that fit, followed by a `truncated` notice with the line range and
+/// tokens removed, and the number of tokens removed; `None` if not even the first line fits
+fn truncate_to_tokens(
+    text: &str,
+    budget: usize,
+    estimator: &TokenEstimator,
+) -> Option<(String, usize)> {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let total_tokens = estimator.estimate(text);
+    let notice = |first: usize, tokens_cut: usize| {
+        notice::format_notice(
+            "truncated",
+            &[
+                ("lines", format!("{}..{}", first, lines.len())),
+                ("tokens", tokens_cut.to_string()),
+                ("reason", "token-budget".to_string()),
+            ],
+        )
+    };
+    // The notice is counted at its longest, with the largest numbers it can hold
+    let budget = budget.saturating_sub(estimator.estimate(&notice(lines.len(), total_tokens)));
+    let prefix_tokens = |count: usize| estimator.estimate(&lines[..count].concat());
+    // Binary search for the most lines that fit
+    let (mut low, mut high) = (0, lines.len());
+    while low < high {
+        let middle = (low + high).div_ceil(2);
+        if prefix_tokens(middle) <= budget {
+            low = middle;
+        } else {
+            high = middle - 1;
+        }
+    }
+    if low == 0 {
+        return None;
+    }
+
+    let tokens_cut = total_tokens.saturating_sub(prefix_tokens(low));
+    let mut body = lines[..low].concat();
+    if !body.ends_with('\n') {
+        body.push('\n');
+    }
+    body.push_str(&notice(low + 1, tokens_cut));
+    Some((body, tokens_cut))
+}
+
+/// What was written for one output file, as reported in the summary
+struct OutputReport {
+    path: PathBuf,
+    format: OutputFormat,
+    /// Size of the rendered output in bytes, before any compression
+    size_bytes: usize,
+    /// Size of the file written with `--compress`
+    compressed_bytes: Option<usize>,
+    /// Estimated tokens, or `None` when `--no-summary-tokens` skips them
+    tokens: Option<usize>,
+}
+
+/// Figures reported at the end of a run, on stdout and with `--summary-file`
+struct RunSummary<'a> {
+    /// The processed directory
+    root_path: &'a Path,
+    /// Name shown for the directory instead of its own, from `--root-name`
+    root_name: Option<&'a str>,
+    /// Number of files in structure
+    structure_file_count: usize,
+    /// Number of files with contents
+    content_file_count: usize,
+    /// Each output, in the order written
+    outputs: &'a [OutputReport],
+    /// The `--upload` URL and the HTTP status it answered with
+    upload: Option<(&'a str, u16)>,
+    /// The command that copied the output with `--clipboard`
+    clipboard: Option<&'a str>,
+    /// The `--budget-per-dir` budget and the usage of each top-level directory
+    dir_budget: Option<(usize, &'a [DirTokenUsage])>,
+    /// The `--max-tokens` budget and the files it skipped or truncated
+    token_budget: Option<(usize, &'a TokenBudgetReport)>,
+    /// The `--changed-since` revision, the number of files changed since it and the files
+    /// deleted since it
+    changed_since: Option<(&'a str, usize, &'a [PathBuf])>,
+    /// Files dropped by `--skip-generated`
+    generated_skipped: Option<usize>,
+    /// Files dropped by `--max-file-tokens`
+    token_limit_skipped: Option<usize>,
+    /// Files dropped by `--max-file-size` and `--min-file-size`, with their sizes
+    size_skipped: Option<&'a [(PathBuf, u64)]>,
+    /// Files left out by `--max-files`
+    max_files_omitted: Option<usize>,
+    /// Files replaced by a reference with `--content-hash-dedup-across-runs`
+    already_sent: Option<usize>,
+    /// License headers stripped and the estimated tokens saved
+    license_headers: Option<(usize, usize)>,
+    /// Files replaced by a reference with `--dedup` and the estimated tokens saved
+    duplicates: Option<(usize, usize)>,
+    /// Secrets replaced by `--redact`
+    redactions: Option<usize>,
+    /// Files decoded from an encoding other than UTF-8
+    transcoded: Option<usize>,
+    /// Show sizes with KB/MB/GB units in the text summary
+    pretty_bytes: bool,
+    /// Per-file metrics and the number of largest files to list, with `--stats`
+    file_stats: Option<(&'a [FileMetrics], usize)>,
+    /// Per-language totals of the included files, with `--lang-stats`
+    languages: Option<&'a [language::LanguageStats]>,
+    /// Lines and non-blank lines of the included text files, when contents were read
+    lines: Option<(usize, usize)>,
+    /// Per-language totals of the included files, with `--sloc`
+    sloc: Option<&'a [language::LanguageStats]>,
+    /// Files left out of the walk, largest first, and the number to list, with
+    /// `--report-excluded`
+    excluded_files: Option<(&'a [(PathBuf, u64)], usize)>,
+}
+
+impl RunSummary<'_> {
+    /// Name of the processed directory
+    fn repository_name(&self) -> String {
+        if let Some(root_name) = self.root_name {
+            return root_name.to_string();
+        }
+        self.root_path
+            .file_name()
+            .unwrap_or_else(|| std::ffi::OsStr::new("unknown"))
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// Prints a summary of the generated file
+///
+/// # Arguments
+/// * `out` - Where to print the summary (stdout, or stderr when the dump goes to stdout)
+/// * `summary` - The figures to report; the size and tokens shown are those of the first output
+fn print_summary(out: &mut dyn Write, summary: &RunSummary) -> Result<()> {
+    let first = &summary.outputs[0];
+
+    writeln!(out, "Repository: {}", summary.repository_name())?;
+    writeln!(out, "Files in structure: {}", summary.structure_file_count)?;
+    writeln!(out, "Files in contents: {}", summary.content_file_count)?;
+    writeln!(
+        out,
+        "Output size: {}",
+        output_size_text(first, summary.pretty_bytes)
+    )?;
+    match first.tokens {
+        Some(token_count) => writeln!(out, "Estimated tokens: {}", token_count)?,
+        None => writeln!(out, "Estimated tokens: (skipped)")?,
+    }
+    if let Some((lines, non_blank_lines)) = summary.lines {
+        writeln!(
+            out,
+            "Lines: {} ({} non-blank)",
+            format_count(lines),
+            format_count(non_blank_lines)
+        )?;
+    }
+
+    if summary.outputs.len() > 1 {
+        print_outputs_summary(out, summary.outputs, summary.pretty_bytes)?;
+    }
+    if let Some((url, status)) = summary.upload {
+        writeln!(out, "Uploaded to {}: HTTP {}", url, status)?;
+    }
+    if let Some(command) = summary.clipboard {
+        writeln!(out, "Copied to clipboard ({})", command)?;
+    }
+    if let Some((budget, usage)) = summary.dir_budget {
+        print_dir_budget_summary(out, budget, usage)?;
+    }
+    if let Some((budget, report)) = summary.token_budget {
+        writeln!(
+            out,
+            "Token budget {}: {} files skipped, {} truncated",
+            budget,
+            report.skipped.len(),
+            usize::from(report.truncated.is_some())
+        )?;
+        if let Some((path, tokens_cut)) = &report.truncated {
+            writeln!(
+                out,
+                "  Truncated: {} ({} tokens cut)",
+                path.display(),
+                tokens_cut
+            )?;
+        }
+        for path in &report.skipped {
+            writeln!(out, "  Skipped: {}", path.display())?;
+        }
+    }
+    if let Some((rev, changed, deleted)) = summary.changed_since {
+        writeln!(
+            out,
+            "Changed since {}: {} files, {} deleted",
+            rev,
+            changed,
+            deleted.len()
+        )?;
+        for path in deleted {
+            writeln!(out, "  Deleted: {}", path.display())?;
+        }
+    }
+    if let Some(skipped) = summary.generated_skipped {
+        writeln!(out, "Generated files skipped: {}", skipped)?;
+    }
+    if let Some(skipped) = summary.token_limit_skipped {
+        writeln!(out, "Files over the token limit skipped: {}", skipped)?;
+    }
+    if let Some(skipped) = summary.size_skipped {
+        writeln!(
+            out,
+            "Files outside the size limits skipped: {}",
+            skipped.len()
+        )?;
+        for (path, size) in skipped {
+            writeln!(
+                out,
+                "  Skipped: {} ({})",
+                path.display(),
+                display_size(*size, summary.pretty_bytes)
+            )?;
+        }
+    }
+    if let Some(omitted) = summary.max_files_omitted {
+        writeln!(out, "Files omitted by --max-files: {}", omitted)?;
+    }
+    if let Some(already_sent) = summary.already_sent {
+        writeln!(out, "Files already sent: {}", already_sent)?;
+    }
+    if let Some((stripped, tokens_saved)) = summary.license_headers {
+        writeln!(
+            out,
+            "License headers stripped: {} (saved {} tokens)",
+            stripped, tokens_saved
+        )?;
+    }
+    if let Some((duplicates, tokens_saved)) = summary.duplicates {
+        writeln!(
+            out,
+            "Duplicate files referenced: {} (saved {} tokens)",
+            duplicates, tokens_saved
+        )?;
+    }
+    if let Some(redactions) = summary.redactions {
+        writeln!(out, "Secrets redacted: {}", redactions)?;
+    }
+    if let Some(transcoded) = summary.transcoded {
+        writeln!(out, "Files transcoded to UTF-8: {}", transcoded)?;
+    }
+    if let Some((metrics, top)) = summary.file_stats {
+        write!(
+            out,
+            "{}",
+            render_file_stats(metrics, top, summary.pretty_bytes)
+        )?;
+    }
+    if let Some(languages) = summary.languages {
+        write!(
+            out,
+            "{}",
+            render_language_tokens(languages, summary.pretty_bytes)
+        )?;
+    }
+    if let Some(languages) = summary.sloc {
+        write!(out, "{}", render_language_lines(languages))?;
+    }
+    if let Some((excluded, top)) = summary.excluded_files {
+        write!(
+            out,
+            "{}",
+            render_excluded_files(excluded, top, summary.pretty_bytes)
+        )?;
+    }
+    Ok(())
+}
+
+/// Converts a run summary into JSON for `--summary-file`
+///
+/// # Arguments
+/// * `summary` - The figures to report
+///
+/// # Returns
+/// An object with the same figures as `print_summary`; figures that do not apply to the run,
+/// such as skipped token estimates, are left out
+fn summary_json(summary: &RunSummary) -> Json {
+    let outputs = summary
+        .outputs
+        .iter()
+        .map(|output| {
+            let format_name = output
+                .format
+                .to_possible_value()
+                .map(|value| value.get_name().to_string())
+                .unwrap_or_default();
+            let mut fields = vec![
+                (
+                    "path",
+                    Json::String(output.path.to_string_lossy().to_string()),
+                ),
+                ("format", Json::String(format_name)),
+                ("size_bytes", Json::Number(output.size_bytes as u64)),
+            ];
+            if let Some(compressed) = output.compressed_bytes {
+                fields.push(("compressed_size_bytes", Json::Number(compressed as u64)));
+            }
+            if let Some(tokens) = output.tokens {
+                fields.push(("tokens", Json::Number(tokens as u64)));
+            }
+            Json::object(fields)
+        })
+        .collect();
+
+    let mut fields = vec![
+        ("repository", Json::String(summary.repository_name())),
+        (
+            "structure_files",
+            Json::Number(summary.structure_file_count as u64),
+        ),
+        (
+            "content_files",
+            Json::Number(summary.content_file_count as u64),
+        ),
+        ("outputs", Json::Array(outputs)),
+    ];
+    if let Some((lines, non_blank_lines)) = summary.lines {
+        fields.push(("lines", Json::Number(lines as u64)));
+        fields.push(("non_blank_lines", Json::Number(non_blank_lines as u64)));
+    }
+    if let Some((url, status)) = summary.upload {
+        fields.push((
+            "upload",
+            Json::object([
+                ("url", Json::String(url.to_string())),
+                ("status", Json::Number(status.into())),
+            ]),
+        ));
+    }
+    if let Some(command) = summary.clipboard {
+        fields.push(("clipboard", Json::String(command.to_string())));
+    }
+    if let Some((budget, usage)) = summary.dir_budget {
+        let dirs = usage
+            .iter()
+            .map(|dir_usage| {
+                Json::object([
+                    ("dir", Json::String(dir_usage.dir.clone())),
+                    ("tokens", Json::Number(dir_usage.tokens as u64)),
+                    ("files_kept", Json::Number(dir_usage.files_kept as u64)),
+                    (
+                        "files_dropped",
+                        Json::Number(dir_usage.files_dropped as u64),
+                    ),
+                ])
+            })
+            .collect();
+        fields.push((
+            "dir_budget",
+            Json::object([
+                ("budget", Json::Number(budget as u64)),
+                ("dirs", Json::Array(dirs)),
+            ]),
+        ));
+    }
+    if let Some((budget, report)) = summary.token_budget {
+        let skipped = report
+            .skipped
+            .iter()
+            .map(|path| Json::String(path.to_string_lossy().to_string()))
+            .collect();
+        let mut budget_fields = vec![
+            ("budget", Json::Number(budget as u64)),
+            ("skipped", Json::Array(skipped)),
+        ];
+        if let Some((path, tokens_cut)) = &report.truncated {
+            budget_fields.push((
+                "truncated",
+                Json::object([
+                    ("path", Json::String(path.to_string_lossy().to_string())),
+                    ("tokens_cut", Json::Number(*tokens_cut as u64)),
+                ]),
+            ));
+        }
+        fields.push(("token_budget", Json::object(budget_fields)));
+    }
+    if let Some((rev, changed, deleted)) = summary.changed_since {
+        let deleted = deleted
+            .iter()
+            .map(|path| Json::String(path.to_string_lossy().to_string()))
+            .collect();
+        fields.push((
+            "changed_since",
+            Json::object([
+                ("rev", Json::String(rev.to_string())),
+                ("changed", Json::Number(changed as u64)),
+                ("deleted", Json::Array(deleted)),
+            ]),
+        ));
+    }
+    if let Some(skipped) = summary.generated_skipped {
+        fields.push(("generated_skipped", Json::Number(skipped as u64)));
+    }
+    if let Some(skipped) = summary.token_limit_skipped {
+        fields.push(("token_limit_skipped", Json::Number(skipped as u64)));
+    }
+    if let Some(skipped) = summary.size_skipped {
+        let files = skipped
+            .iter()
+            .map(|(path, size)| {
+                Json::object([
+                    ("path", Json::String(path.to_string_lossy().to_string())),
+                    ("size_bytes", Json::Number(*size)),
+                ])
+            })
+            .collect();
+        fields.push(("size_skipped", Json::Array(files)));
+    }
+    if let Some(omitted) = summary.max_files_omitted {
+        fields.push(("max_files_omitted", Json::Number(omitted as u64)));
+    }
+    if let Some(already_sent) = summary.already_sent {
+        fields.push(("already_sent", Json::Number(already_sent as u64)));
+    }
+    if let Some((stripped, tokens_saved)) = summary.license_headers {
+        fields.push(("license_headers_stripped", Json::Number(stripped as u64)));
+        fields.push(("license_tokens_saved", Json::Number(tokens_saved as u64)));
+    }
+    if let Some((duplicates, tokens_saved)) = summary.duplicates {
+        fields.push(("duplicate_files", Json::Number(duplicates as u64)));
+        fields.push(("dedup_tokens_saved", Json::Number(tokens_saved as u64)));
+    }
+    if let Some(redactions) = summary.redactions {
+        fields.push(("redactions", Json::Number(redactions as u64)));
+    }
+    if let Some(transcoded) = summary.transcoded {
+        fields.push(("transcoded", Json::Number(transcoded as u64)));
+    }
+    if let Some((metrics, top)) = summary.file_stats {
+        let files = largest_files(metrics, top)
+            .into_iter()
+            .map(|file| {
+                Json::object([
+                    (
+                        "path",
+                        Json::String(file.path.to_string_lossy().to_string()),
+                    ),
+                    ("size_bytes", Json::Number(file.size_bytes as u64)),
+                    ("tokens", Json::Number(file.tokens as u64)),
+                ])
+            })
+            .collect();
+        fields.push(("largest_files", Json::Array(files)));
+    }
+    if let Some(languages) = summary.languages {
+        let languages = languages
+            .iter()
+            .map(|language| {
+                Json::object([
+                    ("name", Json::String(language.name.to_string())),
+                    ("files", Json::Number(language.files as u64)),
+                    ("size_bytes", Json::Number(language.bytes)),
+                    ("tokens", Json::Number(language.tokens as u64)),
+                ])
+            })
+            .collect();
+        fields.push(("languages", Json::Array(languages)));
+    }
+    if let Some(languages) = summary.sloc {
+        let languages = languages
+            .iter()
+            .map(|language| {
+                Json::object([
+                    ("name", Json::String(language.name.to_string())),
+                    ("files", Json::Number(language.files as u64)),
+                    ("lines", Json::Number(language.lines as u64)),
+                    (
+                        "non_blank_lines",
+                        Json::Number(language.non_blank_lines as u64),
+                    ),
+                ])
+            })
+            .collect();
+        fields.push(("sloc", Json::Array(languages)));
+    }
+    if let Some((excluded, top)) = summary.excluded_files {
+        let files = excluded
+            .iter()
+            .take(top)
+            .map(|(path, size)| {
+                Json::object([
+                    ("path", Json::String(path.to_string_lossy().to_string())),
+                    ("size_bytes", Json::Number(*size)),
+                ])
+            })
+            .collect();
+        fields.push(("excluded_files", Json::Number(excluded.len() as u64)));
+        fields.push(("largest_excluded_files", Json::Array(files)));
+    }
+    Json::object(fields)
+}
+
+/// Picks the files with the most estimated tokens, breaking ties by size and then path
+///
+/// # Arguments
+/// * `metrics` - Per-file metrics gathered while reading the contents
+/// * `top` - Maximum number of files to return
+///
+/// # Returns
+/// Up to `top` files, largest first
+fn largest_files(metrics: &[FileMetrics], top: usize) -> Vec<&FileMetrics> {
+    let mut largest: Vec<&FileMetrics> = metrics.iter().collect();
+    largest.sort_by(|a, b| {
+        b.tokens
+            .cmp(&a.tokens)
+            .then_with(|| b.size_bytes.cmp(&a.size_bytes))
+            .then_with(|| a.path.cmp(&b.path))
+    });
+    largest.truncate(top);
+    largest
+}
+
+/// Groups the included files by language, detected from their extension
+///
+/// # Arguments
+/// * `metrics` - Per-file metrics gathered while reading the contents
+///
+/// # Returns
+/// One entry per language, most tokens first
+fn language_totals(metrics: &[FileMetrics]) -> Vec<language::LanguageStats> {
+    let mut languages = language::group_by_language(metrics.iter().map(|file| {
+        language::LanguageStats::file(
+            &file.path,
+            file.size_bytes as u64,
+            file.tokens,
+            file.lines,
+            file.non_blank_lines,
+        )
+    }));
+    languages.sort_by(|a, b| b.tokens.cmp(&a.tokens).then(a.name.cmp(b.name)));
+    languages
+}
+
+/// Renders the per-language table of `--lang-stats`
+///
+/// # Arguments
+/// * `languages` - Totals of each language, most tokens first
+/// * `pretty_bytes` - Show sizes with KB/MB/GB units instead of exact byte counts
+///
+/// # Returns
+/// A heading and one aligned row per language with its share of the estimated tokens
+///
+/// # Examples
+/// ```
+/// // Languages by estimated tokens:
+/// //   Language    Files   Bytes  Tokens  Share
+/// //   TypeScript     12  48,000  12,000  60.0%
+/// //   Rust            3  32,000   8,000  40.0%
+/// ```
+fn render_language_tokens(languages: &[language::LanguageStats], pretty_bytes: bool) -> String {
+    let total_tokens: usize = languages.iter().map(|language| language.tokens).sum();
+    let mut rows = vec![vec![
+        "Language".to_string(),
+        "Files".to_string(),
+        "Bytes".to_string(),
+        "Tokens".to_string(),
+        "Share".to_string(),
+    ]];
+    for language in languages {
+        let share = if total_tokens == 0 {
+            0.0
+        } else {
+            language.tokens as f64 * 100.0 / total_tokens as f64
+        };
+        rows.push(vec![
+            language.name.to_string(),
+            format_count(language.files),
+            if pretty_bytes {
+                format_size(language.bytes)
+            } else {
+                format_count(language.bytes as usize)
+            },
+            format_count(language.tokens),
+            format!("{:.1}%", share),
+        ]);
+    }
+    String::from("Languages by estimated tokens:\n") + &render_language_table(&rows, "  ")
+}
+
+/// Renders the per-language table of `--sloc`
+///
+/// # Arguments
+/// * `languages` - Totals of each language
+///
+/// # Returns
+/// A heading, one aligned row per language with the most non-blank lines first, and a total
+/// row
+///
+/// # Examples
+/// ```
+/// // Lines of code by language:
+/// //   Language  Files  Lines  Non-blank
+/// //   Rust          3  1,200      1,050
+/// //   Total         3  1,200      1,050
+/// ```
+fn render_language_lines(languages: &[language::LanguageStats]) -> String {
+    let mut languages: Vec<&language::LanguageStats> = languages.iter().collect();
+    languages.sort_by(|a, b| {
+        b.non_blank_lines
+            .cmp(&a.non_blank_lines)
+            .then(a.name.cmp(b.name))
+    });
+    let total = |column: fn(&language::LanguageStats) -> usize| {
+        format_count(languages.iter().map(|language| column(language)).sum())
+    };
+    let mut rows = vec![vec![
+        "Language".to_string(),
+        "Files".to_string(),
+        "Lines".to_string(),
+        "Non-blank".to_string(),
+    ]];
+    for language in &languages {
+        rows.push(vec![
+            language.name.to_string(),
+            format_count(language.files),
+            format_count(language.lines),
+            format_count(language.non_blank_lines),
+        ]);
+    }
+    rows.push(vec![
+        "Total".to_string(),
+        total(|language| language.files),
+        total(|language| language.lines),
+        total(|language| language.non_blank_lines),
+    ]);
+    String::from("Lines of code by language:\n") + &render_language_table(&rows, "  ")
+}
+
+/// Renders the per-file table of `--stats`
+///
+/// # Arguments
+/// * `metrics` - Per-file metrics gathered while reading the contents
+/// * `top` - Maximum number of files to list
+/// * `pretty_bytes` - Show sizes with KB/MB/GB units instead of exact byte counts
+///
+/// # Returns
+/// A heading and one aligned `tokens  bytes  path` row per file, largest first
+///
+/// # Examples
+/// ```
+/// // Largest files (top 2 of 12):
+/// //   Tokens   Bytes  Path
+/// //   12,345  49,380  src/main.rs
+/// //      250   1,004  src/lib.rs
+/// ```
+fn render_file_stats(metrics: &[FileMetrics], top: usize, pretty_bytes: bool) -> String {
+    let largest = largest_files(metrics, top);
+    let mut rows = vec![(
+        "Tokens".to_string(),
+        "Bytes".to_string(),
+        "Path".to_string(),
+    )];
+    for file in &largest {
+        let size = if pretty_bytes {
+            format_size(file.size_bytes as u64)
+        } else {
+            format_count(file.size_bytes)
+        };
+        rows.push((
+            format_count(file.tokens),
+            size,
+            file.path.to_string_lossy().replace('\\', "/"),
+        ));
+    }
+
+    let tokens_width = rows.iter().map(|row| row.0.len()).max().unwrap_or(0);
+    let bytes_width = rows.iter().map(|row| row.1.len()).max().unwrap_or(0);
+    let mut table = format!(
+        "Largest files (top {} of {}):\n",
+        largest.len(),
+        metrics.len()
+    );
+    for (tokens, bytes, path) in rows {
+        table.push_str(&format!(
+            "  {:>tokens_width$}  {:>bytes_width$}  {}\n",
+            tokens, bytes, path
+        ));
+    }
+    table
+}
+
+/// Renders the largest excluded files of `--report-excluded`
+///
+/// # Arguments
+/// * `excluded` - Files left out of the walk with their sizes, largest first
+/// * `top` - Maximum number of files to list
+/// * `pretty_bytes` - Show sizes with KB/MB/GB units instead of exact byte counts
+///
+/// # Returns
+/// A heading and one aligned `bytes  path` row per file
+///
+/// # Examples
+/// ```
+/// // Largest excluded files (top 2 of 40):
+/// //   1,048,576  assets/video.mp4
+/// //      52,300  Cargo.lock
+/// ```
+fn render_excluded_files(excluded: &[(PathBuf, u64)], top: usize, pretty_bytes: bool) -> String {
+    let listed = &excluded[..top.min(excluded.len())];
+    let sizes: Vec<String> = listed
+        .iter()
+        .map(|(_, size)| {
+            if pretty_bytes {
+                format_size(*size)
+            } else {
+                format_count(*size as usize)
+            }
+        })
+        .collect();
+    let width = sizes.iter().map(String::len).max().unwrap_or(0);
+    let mut table = format!(
+        "Largest excluded files (top {} of {}):\n",
+        listed.len(),
+        excluded.len()
+    );
+    for ((path, _), size) in listed.iter().zip(sizes) {
+        table.push_str(&format!(
+            "  {:>width$}  {}\n",
+            size,
+            path.to_string_lossy().replace('\\', "/")
+        ));
+    }
+    table
+}
+
+/// Prints the per-directory token usage of `--budget-per-dir`
+///
+/// # Arguments
+/// * `out` - Where to print the summary
+/// * `budget` - The per-directory token budget
+/// * `usage` - Token usage of each top-level directory
+fn print_dir_budget_summary(
+    out: &mut dyn Write,
+    budget: usize,
+    usage: &[DirTokenUsage],
+) -> Result<()> {
+    writeln!(out, "Tokens per directory (budget {}):", budget)?;
+    for dir_usage in usage {
+        writeln!(
+            out,
+            "  {}: {} tokens ({} files, {} dropped)",
+            dir_usage.dir, dir_usage.tokens, dir_usage.files_kept, dir_usage.files_dropped
+        )?;
+    }
+    Ok(())
+}
+
+/// Prints the size of each output written when rendering several formats
+///
+/// # Arguments
+/// * `out` - Where to write the summary
+/// * `outputs` - The outputs written
+/// * `pretty_bytes` - Show sizes with KB/MB/GB units instead of exact byte counts
+fn print_outputs_summary(
+    out: &mut dyn Write,
+    outputs: &[OutputReport],
+    pretty_bytes: bool,
+) -> Result<()> {
+    writeln!(out, "Outputs:")?;
+    for output in outputs {
+        let tokens = match output.tokens {
+            Some(tokens) => tokens.to_string(),
+            None => "(skipped)".to_string(),
+        };
+        let format_name = output
+            .format
+            .to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default();
+        writeln!(
+            out,
+            "  {} ({}): {}, {} estimated tokens",
+            output.path.display(),
+            format_name,
+            output_size_text(output, pretty_bytes),
+            tokens
+        )?;
+    }
+    Ok(())
+}
+
+/// Formats the size of an output, followed by its compressed size if it was compressed
+///
+/// # Examples
+/// ```
+/// // An output of 2048 bytes gzipped to 512 bytes
+/// assert_eq!(output_size_text(&output, false), "2048 bytes (512 bytes compressed)");
+/// ```
+fn output_size_text(output: &OutputReport, pretty_bytes: bool) -> String {
+    let size = display_size(output.size_bytes as u64, pretty_bytes);
+    match output.compressed_bytes {
+        Some(compressed) => format!(
+            "{} ({} compressed)",
+            size,
+            display_size(compressed as u64, pretty_bytes)
+        ),
+        None => size,
+    }
+}
+
+/// Writes a generated file, first creating its parent directories if allowed
+///
+/// # Arguments
+/// * `path` - Where to write
+/// * `contents` - The file contents
+/// * `kind` - What the file is, such as `output` or `manifest`, for error messages
+/// * `create_dirs` - Create missing parent directories instead of failing
+///
+/// # Returns
+/// An error naming the missing directory when it does not exist and may not be created
+fn write_file(
+    path: &Path,
+    contents: impl AsRef<[u8]>,
+    kind: &str,
+    create_dirs: bool,
+) -> Result<()> {
+    let write_error = |message: String| ExitError::new(ExitStatus::WriteFailed, message);
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        if create_dirs {
+            fs::create_dir_all(parent).with_context(|| {
+                write_error(format!(
+                    "Failed to create directory for {} file: {}",
+                    kind,
+                    parent.display()
+                ))
+            })?;
+        } else if !parent.is_dir() {
+            anyhow::bail!(write_error(format!(
+                "Cannot write {} file {}: directory {} does not exist",
+                kind,
+                path.display(),
+                parent.display()
+            )));
+        }
+    }
+    fs::write(path, contents)
+        .with_context(|| write_error(format!("Failed to write {} file: {}", kind, path.display())))
+}
+
+/// Returns true if the output path means "write to stdout"
+fn is_stdout_output(output: &Path) -> bool {
+    output.as_os_str() == "-"
+}
+
+/// Returns the pager command to use when writing to an interactive terminal
+///
+/// Paging is disabled by `--no-pager`, by setting `REPODUMP_NO_PAGER`, or when stdout is
+/// not a TTY. The pager is taken from `$PAGER`, falling back to `less`.
+///
+/// # Arguments
+/// * `no_pager` - Whether `--no-pager` was passed
+///
+/// # Returns
+/// The pager command split into program and arguments, or `None` if paging is disabled
+fn pager_command(no_pager: bool) -> Option<Vec<String>> {
+    if no_pager || std::env::var_os("REPODUMP_NO_PAGER").is_some() || !io::stdout().is_terminal() {
+        return None;
+    }
+    let pager = std::env::var("PAGER").unwrap_or_else(|_| "less".to_string());
+    let command: Vec<String> = pager.split_whitespace().map(str::to_string).collect();
+    if command.is_empty() {
+        None
+    } else {
+        Some(command)
+    }
+}
+
+/// Writes the generated content to stdout, through a pager if one is configured
+///
+/// # Arguments
+/// * `content` - The generated output
+/// * `pager` - Optional pager command from `pager_command`
+fn write_to_stdout(content: &str, pager: Option<Vec<String>>) -> Result<()> {
+    if let Some(pager) = pager {
+        let child = Command::new(&pager[0])
+            .args(&pager[1..])
+            .stdin(Stdio::piped())
+            .spawn();
+        // Fall back to plain stdout if the pager cannot be started
+        if let Ok(mut child) = child {
+            if let Some(mut stdin) = child.stdin.take() {
+                // The user quitting the pager early closes the pipe, which is not an error
+                let _ = stdin.write_all(content.as_bytes());
+            }
+            child.wait().context("Failed to wait for pager")?;
+            return Ok(());
+        }
+    }
+
+    let mut stdout = io::stdout().lock();
+    stdout
+        .write_all(content.as_bytes())
+        .and_then(|_| stdout.flush())
+        .context(ExitError::new(
+            ExitStatus::WriteFailed,
+            "Failed to write output to stdout",
+        ))
+}
+
+/// Uploads the generated content to a URL with an HTTP PUT
+///
+/// The request is made by `curl`, which must be on the `PATH`. The content is piped to its
+/// stdin rather than written to a temporary file. S3-compatible stores can be targeted with
+/// a presigned URL.
+///
+/// # Arguments
+/// * `url` - The HTTP(S) endpoint
+/// * `content` - The generated output
+///
+/// # Returns
+/// The HTTP status code of the response, or an error if the request failed or the status
+/// is not a success
+fn upload_output(url: &str, content: &str) -> Result<u16> {
+    let mut child = Command::new("curl")
+        .args(upload_args(url))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run curl, which --upload requires")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(content.as_bytes())
+            .context("Failed to stream output to curl")?;
+    }
+    let result = child
+        .wait_with_output()
+        .context("Failed to wait for curl")?;
+
+    let upload_error = |message: String| ExitError::new(ExitStatus::WriteFailed, message);
+    if !result.status.success() {
+        anyhow::bail!(upload_error(format!(
+            "Upload to {} failed: {}",
+            url,
+            String::from_utf8_lossy(&result.stderr).trim()
+        )));
+    }
+    let status: u16 = String::from_utf8_lossy(&result.stdout)
+        .trim()
+        .parse()
+        .with_context(|| upload_error(format!("Upload to {} returned no HTTP status", url)))?;
+    if !(200..300).contains(&status) {
+        anyhow::bail!(upload_error(format!(
+            "Upload to {} failed with HTTP status {}",
+            url, status
+        )));
+    }
+    Ok(status)
+}
+
+/// Builds the `curl` arguments for an upload
+///
+/// The URL is passed with `--url`, so one starting with `-` is never read as an option.
+///
+/// # Examples
+/// ```
+/// let args = upload_args("https://example.com/dump.txt");
+/// assert_eq!(args[args.len() - 2..], ["--url", "https://example.com/dump.txt"]);
+/// ```
+fn upload_args(url: &str) -> Vec<&str> {
+    let null_device = if cfg!(windows) { "NUL" } else { "/dev/null" };
+    vec![
+        "--silent",
+        "--show-error",
+        "--request",
+        "PUT",
+        "--data-binary",
+        "@-",
+        "--output",
+        null_device,
+        "--write-out",
+        "%{http_code}",
+        "--url",
+        url,
+    ]
+}
+
+/// Copies the generated content to the system clipboard
+///
+/// The copy is made by the platform's clipboard command: `pbcopy` on macOS, `clip` on
+/// Windows, and `wl-copy`, `xclip` or `xsel` on Linux and other Unix systems, whichever is
+/// installed first, with `wl-copy` only tried under Wayland.
+///
+/// # Arguments
+/// * `content` - The generated output
+///
+/// # Returns
+/// The name of the command that made the copy, or an error if none is installed or it failed
+fn copy_to_clipboard(content: &str) -> Result<&'static str> {
+    let candidates: Vec<(&'static str, &[&str])> = if cfg!(target_os = "macos") {
+        vec![("pbcopy", &[])]
+    } else if cfg!(windows) {
+        vec![("clip", &[])]
+    } else {
+        let mut candidates: Vec<(&'static str, &[&str])> = Vec::new();
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            candidates.push(("wl-copy", &[]));
+        }
+        candidates.push(("xclip", &["-selection", "clipboard"]));
+        candidates.push(("xsel", &["--clipboard", "--input"]));
+        candidates
+    };
+
+    let clipboard_error = |message: String| ExitError::new(ExitStatus::WriteFailed, message);
+    for (program, args) in &candidates {
+        // xclip and xsel keep running to serve the selection, so their output is not waited on
+        let mut child = match Command::new(program)
+            .args(*args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+            Err(error) => {
+                return Err(error).context(clipboard_error(format!("Failed to run {}", program)))
+            }
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(content.as_bytes()).with_context(|| {
+                clipboard_error(format!("Failed to stream output to {}", program))
+            })?;
+        }
+        let status = child
+            .wait()
+            .with_context(|| clipboard_error(format!("Failed to wait for {}", program)))?;
+        if !status.success() {
+            anyhow::bail!(clipboard_error(format!(
+                "Copying to the clipboard with {} failed: {}",
+                program, status
+            )));
+        }
+        return Ok(program);
+    }
+    let names: Vec<&str> = candidates.iter().map(|(program, _)| *program).collect();
+    anyhow::bail!(clipboard_error(format!(
+        "No clipboard command found; --clipboard requires one of: {}",
+        names.join(", ")
+    )))
+}
+
+/// Process exit codes, documented in the README so scripts can branch on the cause
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExitStatus {
+    /// Any error without a more specific category
+    Failure = 1,
+    /// Invalid arguments or patterns (clap also uses 2 for parse errors)
+    BadArguments = 2,
+    /// The target path does not exist or no repository could be found
+    PathNotFound = 3,
+    /// No files were selected for the contents section
+    NoFilesMatched = 4,
+    /// Files could not be read and `--strict` was given
+    ReadErrors = 5,
+    /// The output could not be written
+    WriteFailed = 6,
+}
+
+/// An error message tagged with the exit status it should produce
+///
+/// Attach it with `anyhow::Context` or return it with `bail!`; `main` finds it anywhere in
+/// the error chain.
+#[derive(Debug)]
+struct ExitError {
+    status: ExitStatus,
+    message: String,
+}
+
+impl ExitError {
+    fn new(status: ExitStatus, message: impl Into<String>) -> Self {
+        ExitError {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ExitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ExitError {}
+
+/// Determines the exit status for an error
+///
+/// # Arguments
+/// * `error` - The error returned by `run`
+///
+/// # Returns
+/// The status of the outermost `ExitError` in the chain, or `ExitStatus::Failure`
+fn exit_status_for(error: &anyhow::Error) -> ExitStatus {
+    error
+        .downcast_ref::<ExitError>()
+        .map(|exit_error| exit_error.status)
+        .unwrap_or(ExitStatus::Failure)
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {:?}", error);
+            ExitCode::from(exit_status_for(&error) as u8)
+        }
+    }
+}
+
+fn run() -> Result<()> {
+    let cli = Cli::parse();
+
+    let remote_url = cli
+        .path
+        .as_deref()
+        .and_then(Path::to_str)
+        .filter(|path| git::is_remote_url(path))
+        .map(str::to_string);
+    if remote_url.is_none() && (cli.branch.is_some() || cli.keep_clone) {
+        anyhow::bail!(ExitError::new(
+            ExitStatus::BadArguments,
+            "--branch and --keep-clone need a repository URL as the path",
+        ));
+    }
+    if remote_url.is_some() && cli.watch {
+        anyhow::bail!(ExitError::new(
+            ExitStatus::BadArguments,
+            "--watch cannot be used with a repository URL",
+        ));
+    }
+
+    // Resolve target directory; a clone lives until the dump is written unless it is kept
+    let mut _clone_dir = None;
+    let mut target_dir = match &remote_url {
+        Some(url) => {
+            let (clone_path, clone_dir) = clone_remote(url, cli.branch.as_deref(), cli.keep_clone)?;
+            _clone_dir = clone_dir;
+            clone_path
+        }
+        None => resolve_target_directory(cli.path.clone())?,
+    };
+    if cli.realpath {
+        target_dir = fs::canonicalize(&target_dir)
+            .with_context(|| format!("Failed to canonicalize path: {}", target_dir.display()))?;
+    }
+
+    // Defaults from the project's .repodump/ directory go before the user's own arguments.
+    // A cloned repository's settings are not trusted, since they can run commands.
+    let cli = if cli.no_config || remote_url.is_some() {
+        cli
+    } else {
+        // Formats and outputs pair up by position, so the command line replaces them
+        let mut replaced = Vec::new();
+        if !cli.format.is_empty() {
+            replaced.push("format");
+        }
+        if !cli.output.is_empty() || cli.stdout {
+            replaced.push("output");
+        }
+        // A task or prompt file chosen on the command line beats a prompt stored with the project
+        if cli.task.is_some() || cli.prompt_file.is_some() {
+            replaced.push("prompt");
+        }
+        let config_file = match &cli.config {
+            Some(path) => Some(path.clone()),
+            None => config::find_config_file(&target_dir),
+        };
+        let defaults = config::load_project_defaults(
+            &target_dir,
+            config_file.as_deref(),
+            &replaced,
+            cli.trust_config,
+        )
+        .map_err(|error| ExitError::new(ExitStatus::BadArguments, format!("{:#}", error)))?;
+        if defaults.is_empty() {
+            cli
+        } else {
+            let mut args = std::env::args_os();
+            let program = args.next().unwrap_or_else(|| "repodump".into());
+            Cli::try_parse_from(std::iter::once(program).chain(defaults).chain(args)).map_err(
+                |error| {
+                    // Keep only clap's one-line description, without its usage block
+                    let rendered = error.render().to_string();
+                    let description = rendered.lines().next().unwrap_or_default();
+                    ExitError::new(
+                        ExitStatus::BadArguments,
+                        format!(
+                            "Invalid setting in project defaults: {}",
+                            description.trim_start_matches("error: ")
+                        ),
+                    )
+                },
+            )?
+        }
+    };
+
+    if cli.validate_patterns {
+        let groups: [(&str, &[String]); 3] = [
+            ("--filter", &cli.filter),
+            ("--exclude", &cli.exclude),
+            ("--include", &cli.include),
+        ];
+        let problems = validate_patterns(&groups);
+        for problem in &problems {
+            eprintln!("{}", problem);
+        }
+        if !problems.is_empty() {
+            anyhow::bail!(ExitError::new(
+                ExitStatus::BadArguments,
+                format!("{} invalid pattern(s)", problems.len()),
+            ));
+        }
+        if !cli.quiet {
+            let count: usize = groups.iter().map(|(_, patterns)| patterns.len()).sum();
+            println!("All {} patterns are valid", count);
+        }
+        return Ok(());
+    }
+
+    let reads_stdin = [&cli.files_from, &cli.prompt_file]
+        .into_iter()
+        .flatten()
+        .any(|source| source.as_os_str() == "-");
+    if cli.watch && reads_stdin {
+        anyhow::bail!(ExitError::new(
+            ExitStatus::BadArguments,
+            "--watch cannot be combined with reading from stdin",
+        ));
+    }
+
+    let report = generate(&cli, target_dir.clone())?;
+    if cli.watch {
+        watch(&cli, &target_dir, &report.output_paths)?;
+    }
+    Ok(())
+}
+
+/// What one run of `generate` produced, for the lines printed by `--watch`
+#[derive(Default)]
+struct GenerateReport {
+    /// Number of files with contents
+    content_file_count: usize,
+    /// Estimated tokens of the first output, unless skipped
+    tokens: Option<usize>,
+    /// Paths of the files written
+    output_paths: Vec<PathBuf>,
+}
+
+/// Collects, filters and renders the files and writes every output
+///
+/// # Arguments
+/// * `cli` - The parsed arguments, with the project defaults applied
+/// * `target_dir` - The resolved target directory
+///
+/// # Returns
+/// The figures of the run; runs that only print a report, such as `--dry-run`, report nothing
+fn generate(cli: &Cli, target_dir: PathBuf) -> Result<GenerateReport> {
+    // Create an exclude filter that always excludes .git
+    let mut exclude_git = vec![".git".to_string(), ".git/**".to_string()];
+
+    // With --include-submodules, each submodule's files are noted in their banners and
+    // uninitialized submodules are shown as single tree nodes
+    let mut excluded_dirs = Vec::new();
+    let mut submodule_notes = BTreeMap::new();
+    let mut submodules = Vec::new();
+    if cli.include_submodules {
+        let found = git::find_submodules(&target_dir).unwrap_or_else(|error| {
+            eprintln!("Warning: skipping submodules: {:#}", error);
+            Vec::new()
+        });
+        for submodule in found {
+            if submodule.initialized {
+                // The submodule's .git file only points at the parent's git directory
+                let git_file = submodule.path.join(".git").to_string_lossy().to_string();
+                exclude_git.push(format!("{}/**", git_file));
+                exclude_git.push(git_file);
+                submodules.push(submodule.path);
+                continue;
+            }
+            eprintln!(
+                "Warning: skipping uninitialized submodule: {}",
+                submodule.path.display()
+            );
+            submodule_notes.insert(
+                submodule.path.clone(),
+                "[submodule, not initialized]".to_string(),
+            );
+            excluded_dirs.push(submodule.path);
+        }
+    }
+
+    let mut all_excludes = cli.exclude.clone();
+    all_excludes.extend(exclude_git.clone());
+    if !cli.no_config {
+        // The project defaults configure the dump rather than being part of it
+        all_excludes.push(format!("{}/**", config::CONFIG_DIR));
+        all_excludes.push(config::CONFIG_FILE.to_string());
+    }
+
+    // Progress goes to an interactive stderr, so large repositories do not look stuck
+    let show_progress = !cli.quiet && io::stderr().is_terminal();
+    let walk_options = WalkOptions {
+        progress: show_progress
+            .then(|| Arc::new(progress::Progress::new("Collecting files", None))),
+        max_depth: cli.max_depth,
+        follow_symlinks: cli.follow_symlinks,
+        ignore_gitignore: cli.ignore_gitignore,
+        skip_huge_dirs: cli.skip_huge_dirs,
+        excluded_dirs,
+        extra_ignores: if cli.ignore_from.is_empty() {
+            None
+        } else {
+            Some(load_ignore_files(&target_dir, &cli.ignore_from)?)
+        },
+        collapse_dirs: if cli.collapse_dir.is_empty() {
+            None
+        } else {
+            // `vendor/` names the same directory as `vendor`
+            let patterns = cli
+                .collapse_dir
+                .iter()
+                .map(|pattern| pattern.trim_end_matches('/').to_string())
+                .collect();
+            Some(build_globset(patterns, cli.case_insensitive)?)
+        },
+    };
+
+    // Gather files for content section
+    // The tree normally only drops always-excluded paths; optionally it shares the content excludes
+    let tree_excludes = if cli.tree_excludes {
+        all_excludes.clone()
+    } else {
+        exclude_git
+    };
+    // The overview preset's patterns join any --filter patterns, which add files to it
+    let mut filter_patterns = cli.filter.clone();
+    if cli.overview {
+        filter_patterns.extend(OVERVIEW_PATTERNS.iter().map(|pattern| pattern.to_string()));
+    }
+    let excluded_paths = load_excluded_paths(&cli.exclude_paths_file)?;
+    let tree_excluded_paths = if cli.tree_excludes {
+        excluded_paths.clone()
+    } else {
+        BTreeSet::new()
+    };
+    let content_filter = FileFilter::new(
+        filter_patterns,
+        all_excludes,
+        cli.include.clone(),
+        cli.case_insensitive,
+    )?
+    .with_excluded_paths(excluded_paths);
+    // Checked before the file list is read, so stdin is never consumed by a doomed run
+    let from_stdin = |source: &Option<PathBuf>| {
+        source
+            .as_ref()
+            .is_some_and(|source| source.as_os_str() == "-")
+    };
+    if from_stdin(&cli.prompt_file) && from_stdin(&cli.files_from) {
+        anyhow::bail!(ExitError::new(
+            ExitStatus::BadArguments,
+            "--prompt-file and --files-from cannot both read from stdin",
+        ));
+    }
+    let mut content_collection = match &cli.files_from {
+        Some(source) => {
+            let listing = if source.as_os_str() == "-" {
+                io::read_to_string(io::stdin()).context("Failed to read file list from stdin")?
+            } else {
+                fs::read_to_string(source)
+                    .with_context(|| format!("Failed to read file list: {}", source.display()))?
+            };
+            let mut listed = collect_listed_files(&target_dir, &listing)?;
+            if cli.files_from_filtered {
+                listed
+                    .files
+                    .retain(|file| content_filter.should_include(file));
+            }
+            listed
+        }
+        None => collect_files(&target_dir, &content_filter, &walk_options)?,
+    };
+    // Only diagnostic: the report walk does not change what is dumped
+    let excluded_files = if cli.report_excluded {
+        Some(collect_excluded_files(
+            &target_dir,
+            &content_collection.files,
+            &walk_options,
+        )?)
+    } else {
+        None
+    };
+    for link in &content_collection.symlink_cycles {
+        eprintln!(
+            "Warning: skipping symlink that loops back to an ancestor: {}",
+            link.display()
+        );
+    }
+    if cli.skip_symlinked_files {
+        let symlinked_files = std::mem::take(&mut content_collection.symlinked_files);
+        content_collection
+            .files
+            .retain(|file| !symlinked_files.contains(file));
+        content_collection.symlinks.clear();
+    }
+    // Untracked files are dropped even when no ignore rule covers them
+    let tracked_files: Option<BTreeSet<PathBuf>> = if cli.tracked_only {
+        let tracked = git::tracked_files(&target_dir).with_context(|| {
+            ExitError::new(
+                ExitStatus::PathNotFound,
+                "--tracked-only requires the target to be inside a git repository",
+            )
+        })?;
+        Some(tracked.into_iter().collect())
+    } else {
+        None
+    };
+    if let Some(tracked) = &tracked_files {
+        content_collection
+            .files
+            .retain(|file| tracked.contains(file));
+    }
+    // Files deleted since --changed-since are reported, having nothing to dump
+    let mut deleted_since = Vec::new();
+    let mut changed_since_count = 0;
+    if let Some(rev) = &cli.changed_since {
+        let changed = git::changed_files(&target_dir, rev, &content_collection.files)?;
+        changed_since_count = changed.changed.len();
+        content_collection.files = changed.changed;
+        deleted_since = changed.deleted;
+        deleted_since.retain(|file| content_filter.should_include(file));
+    }
+    let mut content_files = content_collection.files.clone();
+
+    // Drop files outside the size limits before anything reads them
+    let mut size_skipped = Vec::new();
+    if cli.max_file_size.is_some() || cli.min_file_size.is_some() {
+        content_files.retain(|file| {
+            let Ok(metadata) = fs::metadata(target_dir.join(file)) else {
+                return true;
+            };
+            let size = metadata.len();
+            let fits = cli.max_file_size.is_none_or(|max| size <= max)
+                && cli.min_file_size.is_none_or(|min| size >= min);
+            if !fits {
+                size_skipped.push((file.clone(), size));
+            }
+            fits
+        });
+    }
+
+    // Drop generated files from the contents
+    let mut generated_skipped = 0;
+    if cli.skip_generated {
+        let patterns = build_generated_patterns(&cli.generated_pattern)?;
+        let before = content_files.len();
+        content_files.retain(|file| !is_generated_file(&target_dir.join(file), &patterns));
+        generated_skipped = before - content_files.len();
+    }
+
+    // Drop files by sniffed content type
+    if !cli.exclude_type.is_empty() {
+        content_files.retain(|file| {
+            content_type::sniff_file(&target_dir.join(file))
+                .is_none_or(|content_type| !cli.exclude_type.contains(&content_type))
+        });
+    }
+
+    // Drop files that alone would take too much of the token budget
+    let token_estimator = TokenEstimator {
+        long_line_threshold: cli.long_line_threshold,
+        long_line_factor: cli.long_line_factor,
+        jobs: match cli.jobs {
+            0 => std::thread::available_parallelism().map_or(1, usize::from),
+            jobs => jobs,
+        },
+    };
+    let mut token_limit_skipped = 0;
+    if let Some(max_tokens) = cli.max_file_tokens {
+        let before = content_files.len();
+        let texts: Vec<Option<String>> = content_files
+            .iter()
+            .map(|file| fs::read_to_string(target_dir.join(file)).ok())
+            .collect();
+        let readable: Vec<&str> = texts.iter().flatten().map(String::as_str).collect();
+        let mut token_counts = token_estimator.estimate_each(&readable).into_iter();
+        let mut texts = texts.iter();
+        content_files.retain(|_| match texts.next() {
+            Some(Some(_)) => token_counts
+                .next()
+                .is_none_or(|tokens| tokens <= max_tokens),
+            _ => true,
+        });
+        token_limit_skipped = before - content_files.len();
+    }
+
+    // Report the language breakdown of the selected files instead of dumping them
+    if cli.language_stats_only {
+        let stats = language::language_stats(&target_dir, &content_files);
+        let report = if cli.format.contains(&OutputFormat::Json) {
+            language_stats_json(&stats).to_pretty_string() + "\n"
+        } else {
+            render_language_stats(&stats)
+        };
+        print!("{}", report);
+        return Ok(GenerateReport::default());
+    }
+
+    // Summarize what changed since a revision, over the same files as the contents
+    let diff_stats = match &cli.diff_stat {
+        Some(rev) => Some(git::diff_stat(&target_dir, rev, &content_files, &|file| {
+            content_filter.should_include(file)
+        })?),
+        None => None,
+    };
+    let file_diffs = match &cli.diff {
+        Some(rev) => Some(git::file_diffs(
+            &target_dir,
+            rev,
+            &content_files,
+            &|file| content_filter.should_include(file),
+        )?),
+        None => None,
+    };
+
+    // Reduce changed files to their changed hunks and drop unchanged files
+    let mut body_overrides = BTreeMap::new();
+    if let Some(rev) = &cli.only_changed_lines {
+        let reader = git::RevisionReader::open(&target_dir, rev)?;
+        let mut changed_files = Vec::new();
+        for file in content_files {
+            let Ok(current) = fs::read_to_string(target_dir.join(&file)) else {
+                changed_files.push(file);
+                continue;
+            };
+            let Some(base) = reader.read(&file)? else {
+                // New files are included whole
+                changed_files.push(file);
+                continue;
+            };
+            let base = String::from_utf8_lossy(&base);
+            if let Some(excerpt) = git::changed_excerpt(&base, &current, cli.changed_context) {
+                body_overrides.insert(file.clone(), excerpt);
+                changed_files.push(file);
+            }
+        }
+        content_files = changed_files;
+    }
+
+    // Reduce files to the definitions of a symbol and drop files without one
+    if let Some(name) = &cli.symbol {
+        content_files.retain(|file| {
+            let Ok(source) = fs::read_to_string(target_dir.join(file)) else {
+                return false;
+            };
+            match symbol::extract_symbol(file, &source, name) {
+                Some(excerpt) => {
+                    body_overrides.insert(file.clone(), excerpt);
+                    true
+                }
+                None => false,
+            }
+        });
+        if content_files.is_empty() {
+            anyhow::bail!(ExitError::new(
+                ExitStatus::NoFilesMatched,
+                format!(
+                    "No definition of {} found in {}",
+                    name,
+                    target_dir.display()
+                ),
+            ));
+        }
+    }
+
+    // Let the user narrow the candidates by hand
+    let mut unpicked = BTreeSet::new();
+    if cli.interactive {
+        let picked = pick_interactively(&content_files)?;
+        unpicked = content_files
+            .iter()
+            .filter(|file| !picked.contains(file))
+            .cloned()
+            .collect();
+        content_files = picked;
+    }
+
+    // Order the contents before any budget is applied
+    let relevance_tweaks = RelevanceTweaks {
+        boost: Some(build_globset(
+            cli.relevance_boost.clone(),
+            cli.case_insensitive,
+        )?),
+        demote: Some(build_globset(
+            cli.relevance_demote.clone(),
+            cli.case_insensitive,
+        )?),
+    };
+    let commit_times = if cli.sort == SortMode::GitRecency {
+        git::last_commit_times(&target_dir, &content_files)
+            .context("--sort git-recency requires the target to be inside a git repository")?
+    } else {
+        BTreeMap::new()
+    };
+    sort::sort_files(
+        &target_dir,
+        &mut content_files,
+        cli.sort,
+        &relevance_tweaks,
+        &commit_times,
+    );
+    if cli.reverse {
+        content_files.reverse();
+    }
+    // Keep the first files in the chosen order
+    let max_files_omitted: BTreeSet<PathBuf> = match cli.max_files {
+        Some(max_files) if content_files.len() > max_files => {
+            content_files.split_off(max_files).into_iter().collect()
+        }
+        _ => BTreeSet::new(),
+    };
+
+    // With grouping, the sources come first and the tests after them
+    if cli.group_tests_separately {
+        sort::partition_tests(&mut content_files);
+    }
+    let content_symlinks = if cli.relativize_symlinks {
+        content_collection.symlinks.clone()
+    } else {
+        BTreeMap::new()
+    };
+
+    // Gather files for tree structure section
+    let tree_collection = if cli.tree_from_git {
+        // The index decides the structure; filters still apply to it
+        let tracked = git::tracked_files(&target_dir)
+            .context("--tree-from-git requires the target to be inside a git repository")?;
+        let tree_filter = if cli.prune_tree {
+            &content_filter
+        } else {
+            &FileFilter::new(
+                vec![],
+                tree_excludes,
+                cli.include.clone(),
+                cli.case_insensitive,
+            )?
+            .with_excluded_paths(tree_excluded_paths)
+        };
+        CollectedFiles {
+            files: tracked
+                .into_iter()
+                .filter(|file| tree_filter.should_include(file))
+                .filter(|file| {
+                    cli.max_depth
+                        .is_none_or(|depth| file.components().count() <= depth + 1)
+                })
+                .collect(),
+            ..Default::default()
+        }
+    } else if cli.prune_tree {
+        // If pruning tree, use the same files as content section
+        content_collection
+    } else {
+        let tree_filter = FileFilter::new(
+            vec![],
+            tree_excludes,
+            cli.include.clone(),
+            cli.case_insensitive,
+        )?
+        .with_excluded_paths(tree_excluded_paths);
+        collect_files(&target_dir, &tree_filter, &walk_options)?
+    };
+    let mut tree_files = tree_collection.files;
+    tree_files.retain(|file| !unpicked.contains(file));
+    if let Some(progress) = &walk_options.progress {
+        progress.finish();
+    }
+    if cli.prune_tree {
+        tree_files.retain(|file| !max_files_omitted.contains(file));
+    }
+    if let Some(tracked) = &tracked_files {
+        tree_files.retain(|file| tracked.contains(file));
+    }
+
+    // Pair each output with its format; the scan below is rendered once per output
+    let output_args = if cli.stdout {
+        vec![PathBuf::from("-")]
+    } else {
+        cli.output.clone()
+    };
+    let mut outputs = resolve_outputs(&output_args, &cli.format)?;
+    if let Some(compression) = cli.compress {
+        for (path, _) in outputs.iter_mut() {
+            if is_stdout_output(path) {
+                anyhow::bail!(ExitError::new(
+                    ExitStatus::BadArguments,
+                    "--compress cannot be combined with stdout output",
+                ));
+            }
+            if compress::Compression::from_path(path) != Some(compression) {
+                let mut name = path.as_os_str().to_owned();
+                name.push(format!(".{}", compression.extension()));
+                *path = PathBuf::from(name);
+            }
+        }
+    }
+    if cli.split_tokens.is_some()
+        && outputs
+            .iter()
+            .any(|(path, format)| *format == OutputFormat::Json || is_stdout_output(path))
+    {
+        anyhow::bail!(ExitError::new(
+            ExitStatus::BadArguments,
+            "--split-tokens cannot be combined with JSON or stdout outputs",
+        ));
+    }
+    if (cli.tree_format.is_some() || cli.contents_format.is_some())
+        && outputs
+            .iter()
+            .all(|(_, format)| *format == OutputFormat::Json)
+    {
+        anyhow::bail!(ExitError::new(
+            ExitStatus::BadArguments,
+            "--tree-format and --contents-format cannot be combined with --format json",
+        ));
+    }
+    if cli.tree_format == Some(OutputFormat::Json)
+        || cli.contents_format == Some(OutputFormat::Json)
+    {
+        anyhow::bail!(ExitError::new(
+            ExitStatus::BadArguments,
+            "JSON output is only available for the whole document with --format json",
+        ));
+    }
+    let repository_name = cli.root_name.clone().unwrap_or_else(|| {
+        target_dir
+            .file_name()
+            .unwrap_or_else(|| std::ffi::OsStr::new("unknown"))
+            .to_string_lossy()
+            .to_string()
+    });
+
+    let mut tree_options = if !cli.contents_only {
+        Some(TreeOptions {
+            format: OutputFormat::Text,
+            collapsed_dirs: tree_collection
+                .skipped_dirs
+                .iter()
+                .map(|(path, count)| {
+                    let note = format!("[skipped huge dir: {} files]", format_count(*count));
+                    (path.clone(), note)
+                })
+                .chain(
+                    tree_collection
+                        .collapsed_dirs
+                        .iter()
+                        .map(|(path, count, bytes)| {
+                            // Readable by default, exact with --raw-bytes
+                            let note = format!(
+                                "[collapsed: {} files, {}]",
+                                format_count(*count),
+                                display_size(*bytes, !cli.raw_bytes)
+                            );
+                            (path.clone(), note)
+                        }),
+                )
+                .chain(submodule_notes)
+                .collect(),
+            symlink_targets: if cli.relativize_symlinks {
+                tree_collection.symlinks
+            } else {
+                BTreeMap::new()
+            },
+            root_path_label: cli.tree_root_path,
+            root_name: cli.root_name.clone(),
+            icons: cli.tree_icons
+                && !cli.no_icons
+                && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()),
+        })
+    } else {
+        None
+    };
+    let structure_file_count = if tree_options.is_some() {
+        tree_files.len()
+    } else {
+        0
+    };
+
+    // Read the file contents once for every output
+    let redactor = if cli.redact {
+        Some(redact::Redactor::new(&cli.redact_pattern)?)
+    } else {
+        None
+    };
+    let mut contents = if !cli.tree_only {
+        if cli.fail_on_empty && content_files.is_empty() {
+            anyhow::bail!(ExitError::new(
+                ExitStatus::NoFilesMatched,
+                format!("No files matched in {}", target_dir.display()),
+            ));
+        }
+
+        let content_options = ContentOptions {
+            symlink_targets: content_symlinks,
+            submodules,
+            license_markers: if cli.strip_license_headers {
+                Some(load_license_markers(cli.license_markers.as_deref())?)
+            } else {
+                None
+            },
+            body_overrides,
+            strict: cli.strict,
+            binary_base64: cli.include_binary_base64,
+            sent_hashes: match &cli.hash_store {
+                Some(path) => Some(load_hash_store(path)?),
+                None => None,
+            },
+            banner_path_depth: cli.max_path_depth_in_banner,
+            absolute_root: if cli.absolute_paths {
+                Some(fs::canonicalize(&target_dir).with_context(|| {
+                    format!("Failed to canonicalize path: {}", target_dir.display())
+                })?)
+            } else {
+                None
+            },
+            token_estimator,
+            jobs: token_estimator.jobs,
+            line_numbers: cli.line_numbers,
+            redactor: redactor.clone(),
+            encoding: cli.encoding,
+            dedup: cli.dedup,
+            progress: show_progress.then(|| {
+                Arc::new(progress::Progress::new(
+                    "Reading files",
+                    Some(content_files.len()),
+                ))
+            }),
+            // A later rule for the same extension replaces an earlier one
+            transforms: cli
+                .transform
+                .iter()
+                .map(|rule| (rule.extension.clone(), rule.transforms.clone()))
+                .collect(),
+        };
+        Some(load_file_contents(
+            &target_dir,
+            &content_files,
+            &content_options,
+        )?)
+    } else {
+        None
+    };
+    // Command outputs go before the files; with grouping they count as sources. A dry run
+    // only lists files, so it runs no commands
+    if let (Some(contents), false) = (contents.as_mut(), cli.dry_run) {
+        let commands: Vec<FileEntry> = cli
+            .run
+            .iter()
+            .map(|command| {
+                let mut entry = run_command(&target_dir, command);
+                if let Some(redactor) = &redactor {
+                    let (redacted, count) = redactor.redact(&entry.body);
+                    entry.body = redacted;
+                    contents.redactions += count;
+                }
+                entry.tokens = token_estimator.estimate(&entry.body);
+                entry
+            })
+            .collect();
+        contents.entries.splice(0..0, commands);
+    }
+
+    // A prompt file, or stdin for `-`, stands in for --prompt
+    let prompt_from_file = match &cli.prompt_file {
+        Some(source) if source.as_os_str() == "-" => {
+            let text =
+                io::read_to_string(io::stdin()).context("Failed to read prompt from stdin")?;
+            Some(text.trim_end().to_string())
+        }
+        Some(source) => {
+            let text = fs::read_to_string(source).with_context(|| {
+                ExitError::new(
+                    ExitStatus::PathNotFound,
+                    format!("Failed to read prompt file: {}", source.display()),
+                )
+            })?;
+            Some(text.trim_end().to_string())
+        }
+        None => None,
+    };
+
+    // Render and write each output
+    // An explicit prompt overrides the built-in prompt of a task
+    let prompt = cli
+        .prompt
+        .clone()
+        .or(prompt_from_file)
+        .or_else(|| cli.task.map(|task| task.prompt().to_string()));
+
+    let generated_at = format_utc_timestamp(std::time::SystemTime::now());
+    // Relative dates are measured from when the run started
+    let relative_now = cli.relative_time.then(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() as i64)
+    });
+    // Outside a git repository the block is simply left out
+    let git_log = match cli.git_log {
+        Some(count) => Some(
+            git::recent_commits(&target_dir, count)
+                .context("--git-log requires the target to be inside a git repository")?,
+        ),
+        None => None,
+    };
+    let repo_info = if cli.git_info {
+        git::repo_info(&target_dir)
+    } else {
+        None
+    };
+    // Renders the contents section of an output in its contents format, given the position
+    // of the first file among all of them so that split parts keep numbering the files
+    let render_contents = |entries: &[FileEntry], first: usize, contents_format: OutputFormat| {
+        if cli.prefix_line_with_path {
+            render_prefixed_lines(entries, contents_format)
+        } else if contents_format == OutputFormat::Xml {
+            render_xml_documents(entries, first + 1)
+        } else if let (Some(template), false) = (
+            &cli.header_template,
+            contents_format == OutputFormat::Markdown,
+        ) {
+            render_templated_contents(entries, template)
+        } else {
+            render_file_contents(
+                entries,
+                BannerStyle::for_format(contents_format, cli.banner_style),
+                cli.fenced,
+            )
+        }
+    };
+    // Renders a whole output holding the given files, returning it with the byte range of
+    // its contents section
+    let mut render_document = |format: OutputFormat,
+                               entries: Option<&[FileEntry]>|
+     -> Result<(String, usize, usize)> {
+        // JSON is a whole-document format, so it cannot be mixed per section
+        let json_output = format == OutputFormat::Json;
+        let content_file_count = entries.map_or(0, |entries| {
+            entries.iter().filter(|entry| !entry.is_command).count()
+        });
+        // With grouping, the sources, commands included, come before the tests
+        let source_count = cli.group_tests_separately.then(|| {
+            entries.map_or(0, |entries| {
+                entries
+                    .iter()
+                    .filter(|entry| entry.is_command || !sort::is_test_path(&entry.path))
+                    .count()
+            })
+        });
+        let mut output_content = String::new();
+        let mut json_fields = vec![("repository", Json::String(repository_name.clone()))];
+        if json_output {
+            json_fields.push((
+                "metadata",
+                Json::object([
+                    ("generated_at", Json::String(generated_at.clone())),
+                    ("file_count", Json::Number(content_file_count as u64)),
+                ]),
+            ));
+        }
+
+        // A prompt at the top comes before even the repository state
+        if let (Some(prompt), PromptPosition::Top) = (&prompt, cli.prompt_position) {
+            if json_output {
+                json_fields.push(("prompt", Json::String(prompt.clone())));
+            } else {
+                output_content.push_str(&format!("Prompt: {}\n\n", prompt));
+            }
+        }
+
+        // The repository state opens the document, ahead of the table of contents and tree
+        if let Some(info) = &repo_info {
+            if json_output {
+                json_fields.push(("git", git_info_json(info)));
+            } else {
+                output_content.push_str(&render_git_info(info, format));
+                output_content.push('\n');
+            }
+        }
+
+        // Links only work within one file, so split outputs go without a table of contents
+        if let (Some(entries), OutputFormat::Markdown) =
+            (entries, cli.contents_format.unwrap_or(format))
+        {
+            if !json_output
+                && !cli.no_toc
+                && !cli.prefix_line_with_path
+                && cli.split_tokens.is_none()
+                && (cli.toc || entries.len() >= TOC_MIN_FILES)
+            {
+                output_content.push_str(&render_markdown_toc(entries));
+                output_content.push('\n');
+            }
+        }
+
+        if let Some(tree_options) = tree_options.as_mut() {
+            tree_options.format = if json_output {
+                format
+            } else {
+                cli.tree_format.unwrap_or(format)
+            };
+            let tree = generate_directory_tree(&target_dir, &tree_files, tree_options)?;
+            if json_output {
+                json_fields.push(("tree", Json::String(tree)));
+                if cli.json_tree_structured {
+                    let structure = generate_tree_structure(&target_dir, &tree_files, tree_options);
+                    json_fields.push(("tree_structured", structure));
+                }
+            } else {
+                output_content.push_str(&tree);
+                output_content.push('\n');
+            }
+        }
+
+        if let (Some(stats), Some(rev)) = (&diff_stats, &cli.diff_stat) {
+            if json_output {
+                json_fields.push(("diff_stat", diff_stat_json(stats)));
+            } else {
+                output_content.push_str(&render_diff_stat(stats, rev, format));
+                output_content.push('\n');
+            }
+        }
+
+        let contents_start = output_content.len();
+        let contents_format = cli.contents_format.unwrap_or(format);
+        if let Some(entries) = entries {
+            match (json_output, source_count) {
+                // Prefixed lines replace the files, so every line can be addressed on its own
+                (true, _) if cli.prefix_line_with_path => {
+                    json_fields.push(("lines", Json::Array(prefixed_lines_json(entries))));
+                }
+                (true, None) => {
+                    let files = file_contents_json(entries);
+                    json_fields.push(("files", Json::Array(files)));
+                }
+                (true, Some(source_count)) => {
+                    let mut files = file_contents_json(entries);
+                    for (i, file) in files.iter_mut().enumerate() {
+                        let group = if i < source_count { "source" } else { "tests" };
+                        if let Json::Object(fields) = file {
+                            fields.push(("group".to_string(), Json::String(group.to_string())));
+                        }
+                    }
+                    json_fields.push(("files", Json::Array(files)));
+                }
+                (false, None) => {
+                    output_content.push_str(&render_contents(entries, 0, contents_format));
+                }
+                (false, Some(source_count)) => {
+                    let (sources, tests) = entries.split_at(source_count);
+                    let groups = [("Source Files", sources), ("Test Files", tests)];
+                    for (i, (title, entries)) in groups
+                        .iter()
+                        .filter(|(_, entries)| !entries.is_empty())
+                        .enumerate()
+                    {
+                        if i > 0 {
+                            output_content.push('\n');
+                        }
+                        output_content.push_str(&render_file_group(
+                            title,
+                            &render_contents(entries, 0, contents_format),
+                            contents_format,
+                        ));
+                    }
+                }
+            }
+        }
+
+        let contents_end = output_content.len();
+
+        if let (Some(diffs), Some(rev)) = (&file_diffs, &cli.diff) {
+            if json_output {
+                json_fields.push(("diff", diff_json(diffs, rev)));
+            } else {
+                output_content.push('\n');
+                output_content.push_str(&render_diff(diffs, rev, format));
+            }
+        }
+
+        if let Some(commits) = &git_log {
+            if json_output {
+                json_fields.push(("git_log", git_log_json(commits)));
+            } else {
+                output_content.push('\n');
+                output_content.push_str(&render_git_log(commits, format, relative_now));
+            }
+        }
+
+        // Add prompt if provided
+        if let (Some(prompt), PromptPosition::Bottom) = (&prompt, cli.prompt_position) {
+            if json_output {
+                json_fields.push(("prompt", Json::String(prompt.clone())));
+            } else {
+                output_content.push('\n');
+                output_content.push_str(&format!("Prompt: {}\n", prompt));
+            }
+        }
+
+        if json_output {
+            let document = Json::object(json_fields);
+            output_content = if cli.compact {
+                document.to_compact_string()
+            } else {
+                document.to_pretty_string()
+            };
+            output_content.push('\n');
+        } else if cli.front_matter {
+            let front_matter = generate_front_matter(
+                &repository_name,
+                &generated_at,
+                content_file_count,
+                token_estimator.estimate(&output_content),
+            );
+            output_content.insert_str(0, &front_matter);
+        }
+        Ok((output_content, contents_start, contents_end))
+    };
+
+    // Balance the contents across top-level directories, then fit them into the total token
+    // budget, both measured on the first output as rendered
+    let mut dir_budget_usage = Vec::new();
+    let mut token_budget_report = TokenBudgetReport::default();
+    if let (Some(contents), Some(&(_, format))) = (contents.as_mut(), outputs.first()) {
+        let contents_format = cli.contents_format.unwrap_or(format);
+        let entry_tokens = |entry: &FileEntry| {
+            let entry = std::slice::from_ref(entry);
+            if format == OutputFormat::Json {
+                let files = Json::Array(file_contents_json(entry));
+                token_estimator.estimate(&files.to_pretty_string())
+            } else {
+                token_estimator.estimate(&render_contents(entry, 0, contents_format))
+            }
+        };
+        if let Some(budget) = cli.budget_per_dir {
+            dir_budget_usage = apply_dir_budget(&mut contents.entries, budget, &entry_tokens);
+        }
+        if let Some(budget) = cli.max_tokens {
+            token_budget_report = apply_token_budget(
+                &mut contents.entries,
+                budget,
+                &mut |entries| {
+                    let (document, _, _) = render_document(format, Some(entries))?;
+                    Ok(token_estimator.estimate(&document))
+                },
+                &entry_tokens,
+                &token_estimator,
+                cli.truncate_files,
+                cli.smallest_first,
+            )?;
+        }
+
+        // Dropped files leave the metrics and are not recorded as sent
+        let kept: BTreeSet<&PathBuf> = contents.entries.iter().map(|entry| &entry.path).collect();
+        contents
+            .file_metrics
+            .retain(|file| kept.contains(&file.path));
+        content_files.retain(|file| kept.contains(file));
+        let kept_hashes: BTreeSet<&String> = contents
+            .file_metrics
+            .iter()
+            .map(|file| &file.sha1)
+            .collect();
+        contents
+            .new_hashes
+            .retain(|hash| kept_hashes.contains(hash));
+    }
+    let content_file_count = if contents.is_some() {
+        content_files.len()
+    } else {
+        0
+    };
+
+    // List the selection instead of writing a dump
+    if cli.dry_run {
+        let files: Vec<(PathBuf, usize)> = contents
+            .iter()
+            .flat_map(|contents| &contents.entries)
+            .filter(|entry| !entry.is_command)
+            .map(|entry| (entry.path.clone(), entry.tokens))
+            .collect();
+        let tree_count = (!cli.contents_only).then_some(tree_files.len());
+        print!("{}", render_dry_run(&files, tree_count));
+        return Ok(GenerateReport::default());
+    }
+
+    let mut written_outputs = Vec::new();
+    let mut upload_status = None;
+    let mut clipboard_command = None;
+    for (output_path, format) in &outputs {
+        let json_output = *format == OutputFormat::Json;
+        let entries = contents
+            .as_ref()
+            .map(|contents| contents.entries.as_slice());
+        let (output_content, contents_start, contents_end) = render_document(*format, entries)?;
+        let contents_format = cli.contents_format.unwrap_or(*format);
+
+        // The first output can also be uploaded, optionally instead of being written
+        let uploaded = written_outputs.is_empty() && cli.upload.is_some();
+        if let (Some(url), true) = (&cli.upload, uploaded) {
+            upload_status = Some(upload_output(url, &output_content)?);
+        }
+        // Without an explicit --output, the clipboard takes the place of the default file
+        let copied = written_outputs.is_empty() && cli.clipboard;
+        if copied {
+            clipboard_command = Some(copy_to_clipboard(&output_content)?);
+        }
+        let clipboard_only = copied && cli.output.is_empty() && !cli.stdout;
+
+        // With --split-tokens the output is written as numbered parts instead
+        let parts = match cli.split_tokens {
+            Some(budget) if !json_output => split_output(
+                &output_content[..contents_start],
+                entries.unwrap_or_default(),
+                &output_content[contents_end..],
+                budget,
+                &|entries, first| render_contents(entries, first, contents_format),
+                &token_estimator,
+                contents_format,
+            )
+            .into_iter()
+            .enumerate()
+            .map(|(i, part)| (numbered_output_path(output_path, i + 1), part))
+            .collect(),
+            _ => vec![(output_path.clone(), output_content)],
+        };
+
+        for (path, content) in parts {
+            // Write output file, or stdout for `-o -`
+            let mut compressed_size = None;
+            if (uploaded && cli.upload_only) || clipboard_only {
+                // Nothing to write locally
+            } else if is_stdout_output(&path) {
+                write_to_stdout(&content, pager_command(cli.no_pager))?;
+            } else if let Some(compression) = compress::Compression::from_path(&path) {
+                let compressed = compression.compress(content.as_bytes()).map_err(|error| {
+                    ExitError::new(ExitStatus::WriteFailed, format!("{:#}", error))
+                })?;
+                write_file(&path, &compressed, "output", !cli.no_create_dirs)?;
+                compressed_size = Some(compressed.len());
+            } else {
+                write_file(&path, &content, "output", !cli.no_create_dirs)?;
+            }
+
+            let token_count = if cli.no_summary_tokens {
+                None
+            } else {
+                Some(token_estimator.estimate(&content))
+            };
+            written_outputs.push(OutputReport {
+                path,
+                format: *format,
+                size_bytes: content.len(),
+                compressed_bytes: compressed_size,
+                tokens: token_count,
+            });
+        }
+    }
+    let to_stdout = outputs.iter().any(|(path, _)| is_stdout_output(path));
+    let contents = contents.unwrap_or_default();
+
+    // Record what was emitted for later runs
+    if let Some(store_path) = &cli.hash_store {
+        append_hash_store(store_path, &contents.new_hashes)?;
+    }
+
+    // Warn about outlier files
+    if let Some(threshold) = cli.warn_file_tokens {
+        let large = large_token_files(&contents.file_metrics, threshold);
+        if !large.is_empty() {
+            eprintln!(
+                "Warning: {} files exceed {} estimated tokens:",
+                large.len(),
+                threshold
+            );
+            for file in large {
+                eprintln!("  {}: {} tokens", file.path.display(), file.tokens);
+            }
+        }
+    }
+
+    // Write the sidecar manifest
+    if let Some(manifest_path) = &cli.manifest_json {
+        let manifest =
+            generate_manifest(&repository_name, &contents.file_metrics).to_pretty_string() + "\n";
+        write_file(manifest_path, &manifest, "manifest", !cli.no_create_dirs)?;
+    }
+
+    let language_totals =
+        (cli.lang_stats || cli.sloc).then(|| language_totals(&contents.file_metrics));
+    let line_totals = contents
+        .file_metrics
+        .iter()
+        .fold((0, 0), |(lines, non_blank), file| {
+            (lines + file.lines, non_blank + file.non_blank_lines)
+        });
+    let summary = RunSummary {
+        root_path: &target_dir,
+        root_name: cli.root_name.as_deref(),
+        structure_file_count,
+        content_file_count,
+        outputs: &written_outputs,
+        upload: cli.upload.as_deref().zip(upload_status),
+        clipboard: clipboard_command,
+        dir_budget: cli
+            .budget_per_dir
+            .map(|budget| (budget, dir_budget_usage.as_slice())),
+        token_budget: cli.max_tokens.map(|budget| (budget, &token_budget_report)),
+        changed_since: cli
+            .changed_since
+            .as_deref()
+            .map(|rev| (rev, changed_since_count, deleted_since.as_slice())),
+        generated_skipped: cli.skip_generated.then_some(generated_skipped),
+        token_limit_skipped: cli.max_file_tokens.map(|_| token_limit_skipped),
+        size_skipped: (cli.max_file_size.is_some() || cli.min_file_size.is_some())
+            .then_some(size_skipped.as_slice()),
+        max_files_omitted: cli.max_files.map(|_| max_files_omitted.len()),
+        already_sent: cli.hash_store.is_some().then_some(contents.already_sent),
+        license_headers: cli.strip_license_headers.then_some((
+            contents.license_headers_stripped,
+            contents.license_tokens_saved,
+        )),
+        duplicates: cli
+            .dedup
+            .then_some((contents.duplicates, contents.dedup_tokens_saved)),
+        redactions: cli.redact.then_some(contents.redactions),
+        transcoded: (contents.transcoded > 0).then_some(contents.transcoded),
+        pretty_bytes: cli.pretty_bytes,
+        file_stats: cli
+            .stats
+            .then_some((contents.file_metrics.as_slice(), cli.stats_top)),
+        languages: language_totals.as_deref().filter(|_| cli.lang_stats),
+        lines: (!contents.file_metrics.is_empty()).then_some(line_totals),
+        sloc: language_totals.as_deref().filter(|_| cli.sloc),
+        excluded_files: excluded_files
+            .as_deref()
+            .map(|excluded| (excluded, cli.stats_top)),
+    };
+
+    // The summary file is written even in quiet mode; a `.json` path selects JSON
+    if let Some(summary_path) = &cli.summary_file {
+        let text = if OutputFormat::from_path(summary_path) == OutputFormat::Json {
+            summary_json(&summary).to_pretty_string() + "\n"
+        } else {
+            let mut text = Vec::new();
+            print_summary(&mut text, &summary)?;
+            String::from_utf8_lossy(&text).into_owned()
+        };
+        write_file(summary_path, &text, "summary", !cli.no_create_dirs)?;
+    }
+
+    // Print summary unless quiet mode, keeping stdout clean when it carries the dump
+    if !cli.quiet {
+        let mut summary_out: Box<dyn Write> = if to_stdout {
+            Box::new(io::stderr())
+        } else {
+            Box::new(io::stdout())
+        };
+        print_summary(&mut summary_out, &summary)?;
+    }
+
+    Ok(GenerateReport {
+        content_file_count,
+        tokens: written_outputs.first().and_then(|output| output.tokens),
+        output_paths: written_outputs
+            .iter()
+            .map(|output| output.path.clone())
+            .collect(),
+    })
+}
+
+/// How often `--watch` checks the target directory for changes
+const WATCH_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// How long the target directory must stay unchanged before `--watch` regenerates
+const WATCH_DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(300);
+
+/// Each watched file's path, relative to the target directory, mapped to its size and
+/// modification time
+type WatchSnapshot = BTreeMap<PathBuf, (u64, Option<std::time::SystemTime>)>;
+
+/// Records the size and modification time of every file `--watch` reacts to
+///
+/// `.gitignore` rules are honored, so editor swap files and build output that are ignored do
+/// not trigger a rebuild; `.git` is skipped, and so are the outputs, so writing them never
+/// triggers another rebuild.
+///
+/// # Arguments
+/// * `root_path` - The target directory
+/// * `ignore_gitignore` - Whether `--ignore-gitignore` was given
+/// * `outputs` - The outputs inside the target directory, relative to it
+///
+/// # Returns
+/// Each file's path mapped to its size and modification time
+fn watch_snapshot(root_path: &Path, ignore_gitignore: bool, outputs: &[PathBuf]) -> WatchSnapshot {
+    let mut builder = WalkBuilder::new(root_path);
+    builder.hidden(false);
+    if ignore_gitignore {
+        builder.git_ignore(false);
+        builder.git_exclude(false);
+        builder.git_global(false);
+    } else {
+        builder.add_custom_ignore_filename(".gitignore");
+    }
+    builder.filter_entry(|entry| entry.file_name() != ".git");
+
+    builder
+        .build()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_some_and(|ft| ft.is_file()))
+        .filter_map(|entry| {
+            let relative_path = entry.path().strip_prefix(root_path).ok()?.to_path_buf();
+            if outputs.contains(&relative_path) {
+                return None;
+            }
+            let metadata = entry.metadata().ok()?;
+            Some((relative_path, (metadata.len(), metadata.modified().ok())))
+        })
+        .collect()
+}
+
+/// Takes snapshots until two in a row agree, so a burst of saves is seen as one change
+///
+/// # Arguments
+/// * `current` - The snapshot that showed the change
+/// * `take_snapshot` - Takes a new snapshot of the target directory
+/// * `debounce` - How long to wait before each new snapshot
+///
+/// # Returns
+/// The snapshot once the directory has stopped changing
+fn settle_snapshot(
+    mut current: WatchSnapshot,
+    take_snapshot: &mut dyn FnMut() -> WatchSnapshot,
+    debounce: std::time::Duration,
+) -> WatchSnapshot {
+    loop {
+        std::thread::sleep(debounce);
+        let settled = take_snapshot();
+        if settled == current {
+            return current;
+        }
+        current = settled;
+    }
+}
+
+/// Regenerates the outputs whenever files in the target directory change, until interrupted
+///
+/// Changes are found by polling. A rebuild starts once the directory has been still for
+/// `WATCH_DEBOUNCE`, so a burst of saves causes a single rebuild. Each rebuild runs the
+/// whole pipeline with the same arguments and prints one line instead of the summary; a
+/// failed rebuild is reported and watching continues.
+///
+/// # Arguments
+/// * `cli` - The parsed arguments, with the project defaults applied
+/// * `target_dir` - The resolved target directory
+/// * `output_paths` - The files written by the first run
+fn watch(cli: &Cli, target_dir: &Path, output_paths: &[PathBuf]) -> Result<()> {
+    let mut rebuild_cli = cli.clone();
+    rebuild_cli.quiet = true;
+    // Outputs inside the target directory would otherwise be dumped into the next rebuild
+    let canonical_root = fs::canonicalize(target_dir).context("Failed to resolve target path")?;
+    let mut outputs = Vec::new();
+    for path in output_paths {
+        let inside = fs::canonicalize(path)
+            .ok()
+            .and_then(|path| Some(path.strip_prefix(&canonical_root).ok()?.to_path_buf()));
+        if let Some(relative_path) = inside {
+            let pattern = relative_path.to_string_lossy().replace('\\', "/");
+            rebuild_cli.exclude.push(globset::escape(&pattern));
+            outputs.push(relative_path);
+        }
+    }
+    eprintln!(
+        "Watching {} for changes (Ctrl-C to stop)",
+        target_dir.display()
+    );
+
+    let mut take_snapshot = || watch_snapshot(target_dir, cli.ignore_gitignore, &outputs);
+    let mut snapshot = take_snapshot();
+    loop {
+        std::thread::sleep(WATCH_POLL_INTERVAL);
+        let current = take_snapshot();
+        if current == snapshot {
+            continue;
+        }
+        let current = settle_snapshot(current, &mut take_snapshot, WATCH_DEBOUNCE);
+
+        match generate(&rebuild_cli, target_dir.to_path_buf()) {
+            Ok(report) => {
+                let tokens = report.tokens.map_or("skipped".to_string(), format_count);
+                eprintln!(
+                    "Regenerated ({} files, {} tokens)",
+                    format_count(report.content_file_count),
+                    tokens
+                );
+            }
+            Err(error) => eprintln!("Regeneration failed: {:#}", error),
+        }
+        // Compare against what was rebuilt, so edits made during the rebuild are picked up
+        snapshot = current;
+    }
 }
@@ -0,0 +1,61 @@
+/// Formats a machine-parseable notice marking content left out of a file body
+///
+/// Notices are single lines of the form `[[repodump:<kind> key=value ...]]` so that tools
+/// can find them with a regular expression such as `^\[\[repodump:(\w+)((?: \w+=\S+)*)\]\]$`.
+///
+/// # Arguments
+/// * `kind` - What happened to the content, e.g. `truncated` or `omitted`
+/// * `fields` - `key=value` pairs describing the left out content; values contain no spaces
+///
+/// # Returns
+/// The notice line, ending with a newline
+///
+/// # Examples
+/// ```
+/// assert_eq!(
+///     format_notice("omitted", &[("bytes", "2048".to_string())]),
+///     "[[repodump:omitted bytes=2048]]\n"
+/// );
+/// ```
+pub(crate) fn format_notice(kind: &str, fields: &[(&str, String)]) -> String {
+    let mut notice = format!("[[repodump:{}", kind);
+    for (key, value) in fields {
+        notice.push_str(&format!(" {}={}", key, value));
+    }
+    notice.push_str("]]\n");
+    notice
+}
+
+/// Formats the notice for a run of lines missing from the middle or end of a file
+///
+/// # Arguments
+/// * `first` - First left out line, counting from 1
+/// * `last` - Last left out line, inclusive
+/// * `reason` - Why the lines were left out, e.g. `unchanged`
+///
+/// # Returns
+/// A notice such as `[[repodump:truncated lines=120..2000 reason=unchanged]]`
+pub(crate) fn truncated_lines(first: usize, last: usize, reason: &str) -> String {
+    format_notice(
+        "truncated",
+        &[
+            ("lines", format!("{}..{}", first, last)),
+            ("reason", reason.to_string()),
+        ],
+    )
+}
+
+/// Formats the notice for a file whose whole body is left out
+///
+/// # Arguments
+/// * `bytes` - Size of the file in bytes
+/// * `reason` - Why the body was left out, e.g. `binary-too-large`
+///
+/// # Returns
+/// A notice such as `[[repodump:omitted bytes=5242880 reason=binary-too-large]]`
+pub(crate) fn omitted_body(bytes: usize, reason: &str) -> String {
+    format_notice(
+        "omitted",
+        &[("bytes", bytes.to_string()), ("reason", reason.to_string())],
+    )
+}
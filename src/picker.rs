@@ -0,0 +1,146 @@
+use anyhow::{Context, Result};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// Commands understood by the picker, shown under the list
+const HELP: &str = "Toggle with numbers or ranges (3 5-8), a directory (src/) or a glob (*.md); \
+a = all, n = none, empty line = done, q = cancel";
+
+/// Lets the user toggle files on and off in a numbered checklist
+///
+/// Every file starts selected. After each command the list is shown again, until an empty
+/// line accepts the selection.
+///
+/// # Arguments
+/// * `files` - The candidate files, in the order they are listed
+/// * `input` - Where commands are read from, normally the terminal
+/// * `output` - Where the list is drawn, normally stderr
+///
+/// # Returns
+/// The selected files in their original order, or `None` if the user cancelled
+pub(crate) fn pick_files(
+    files: &[PathBuf],
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+) -> Result<Option<Vec<PathBuf>>> {
+    let mut selected = vec![true; files.len()];
+    loop {
+        let count = selected.iter().filter(|&&on| on).count();
+        for (i, (file, on)) in files.iter().zip(&selected).enumerate() {
+            let mark = if *on { 'x' } else { ' ' };
+            writeln!(output, "{:>4} [{}] {}", i + 1, mark, file.display())?;
+        }
+        writeln!(output, "{}", HELP)?;
+        write!(output, "{} of {} selected> ", count, files.len())?;
+        output.flush()?;
+
+        let mut line = String::new();
+        if input
+            .read_line(&mut line)
+            .context("Failed to read the selection")?
+            == 0
+        {
+            // End of input cancels, like Ctrl-D at a shell prompt
+            writeln!(output)?;
+            return Ok(None);
+        }
+        match line.trim() {
+            "" => break,
+            "q" => return Ok(None),
+            command => {
+                if let Err(message) = apply_command(files, &mut selected, command) {
+                    writeln!(output, "{}", message)?;
+                }
+            }
+        }
+    }
+    Ok(Some(
+        files
+            .iter()
+            .zip(selected)
+            .filter(|(_, on)| *on)
+            .map(|(file, _)| file.clone())
+            .collect(),
+    ))
+}
+
+/// Applies one picker command to the selection
+///
+/// Each word toggles what it names: a number or range of list entries, the files under a
+/// directory, or the files matching a glob. `a` and `n` select all or none.
+///
+/// # Returns
+/// An error message for a word that names nothing
+///
+/// # Examples
+/// ```
+/// let files = [PathBuf::from("a.rs"), PathBuf::from("src/b.rs")];
+/// let mut selected = vec![true, true];
+/// apply_command(&files, &mut selected, "src/")?;
+/// assert_eq!(selected, [true, false]);
+/// ```
+pub(crate) fn apply_command(
+    files: &[PathBuf],
+    selected: &mut [bool],
+    command: &str,
+) -> Result<(), String> {
+    for word in command.split_whitespace() {
+        match word {
+            "a" => selected.fill(true),
+            "n" => selected.fill(false),
+            _ => {
+                let targets = resolve_word(files, word)?;
+                if targets_single(word) {
+                    for i in targets {
+                        selected[i] = !selected[i];
+                    }
+                } else {
+                    // A group is turned on unless all of it already is
+                    let turn_on = !targets.iter().all(|&i| selected[i]);
+                    for i in targets {
+                        selected[i] = turn_on;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether a word names single entries that flip one by one, rather than a group
+fn targets_single(word: &str) -> bool {
+    word.chars().all(|c| c.is_ascii_digit() || c == '-')
+}
+
+/// Finds the list entries a word names
+fn resolve_word(files: &[PathBuf], word: &str) -> Result<Vec<usize>, String> {
+    if targets_single(word) {
+        let (start, end) = word.split_once('-').unwrap_or((word, word));
+        let parse = |number: &str| {
+            number
+                .parse::<usize>()
+                .ok()
+                .filter(|&n| (1..=files.len()).contains(&n))
+                .ok_or_else(|| format!("No entry {} in the list", word))
+        };
+        let (start, end) = (parse(start)?, parse(end)?);
+        return Ok((start.min(end) - 1..start.max(end)).collect());
+    }
+    let targets: Vec<usize> = if word.contains(['*', '?', '[']) {
+        let glob = globset::Glob::new(word)
+            .map_err(|error| format!("Invalid glob {}: {}", word, error))?
+            .compile_matcher();
+        (0..files.len())
+            .filter(|&i| glob.is_match(&files[i]))
+            .collect()
+    } else {
+        let path = Path::new(word.trim_end_matches('/'));
+        (0..files.len())
+            .filter(|&i| files[i].starts_with(path))
+            .collect()
+    };
+    if targets.is_empty() {
+        return Err(format!("Nothing matches {}", word));
+    }
+    Ok(targets)
+}
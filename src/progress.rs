@@ -0,0 +1,123 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Delay before the indicator first appears, so quick runs never show it
+const FIRST_DRAW_DELAY: Duration = Duration::from_millis(200);
+
+/// Minimum time between redraws
+const REDRAW_INTERVAL: Duration = Duration::from_millis(80);
+
+/// Width of the bar in characters
+const BAR_WIDTH: usize = 30;
+
+/// Frames of the spinner shown while the total is unknown
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+/// A progress indicator drawn on one line of stderr
+///
+/// Shows a spinner with a count while the total is unknown and a bar once it is known. The
+/// counter can be advanced from several threads at once.
+pub(crate) struct Progress {
+    /// What is being counted, such as `Reading files`
+    label: &'static str,
+    /// Number of items expected, or `None` for a spinner
+    total: Option<usize>,
+    /// Items processed so far
+    count: AtomicUsize,
+    /// When the indicator was created
+    started: Instant,
+    /// When the line was last drawn, or `None` if it never was
+    last_draw: Mutex<Option<Instant>>,
+    /// Whether the line has been cleared for good
+    finished: AtomicBool,
+}
+
+impl Progress {
+    /// Creates an indicator, drawn once the first item is counted after a short delay
+    ///
+    /// # Arguments
+    /// * `label` - What is being counted
+    /// * `total` - Number of items expected, or `None` to show a spinner
+    pub(crate) fn new(label: &'static str, total: Option<usize>) -> Progress {
+        Progress {
+            label,
+            total,
+            count: AtomicUsize::new(0),
+            started: Instant::now(),
+            last_draw: Mutex::new(None),
+            finished: AtomicBool::new(false),
+        }
+    }
+
+    /// Counts one processed item and redraws the line if it is due
+    pub(crate) fn inc(&self) {
+        let count = self.count.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.finished.load(Ordering::Relaxed) {
+            return;
+        }
+        let now = Instant::now();
+        if now.duration_since(self.started) < FIRST_DRAW_DELAY {
+            return;
+        }
+        // Skip the redraw rather than wait while another thread draws
+        let Ok(mut last_draw) = self.last_draw.try_lock() else {
+            return;
+        };
+        if last_draw.is_some_and(|last| now.duration_since(last) < REDRAW_INTERVAL) {
+            return;
+        }
+        *last_draw = Some(now);
+        eprint!("\r\x1b[2K{}", self.render(count));
+        let _ = std::io::stderr().flush();
+    }
+
+    /// Clears the line if it was drawn; later items are still counted but not shown
+    pub(crate) fn finish(&self) {
+        if self.finished.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        let drawn = self
+            .last_draw
+            .lock()
+            .map_or(true, |last_draw| last_draw.is_some());
+        if drawn {
+            eprint!("\r\x1b[2K");
+            let _ = std::io::stderr().flush();
+        }
+    }
+
+    /// Renders the indicator line for a count
+    ///
+    /// # Examples
+    /// ```
+    /// let progress = Progress::new("Reading files", Some(4));
+    /// assert_eq!(progress.render(1), "[=======>                      ] 1/4 Reading files");
+    /// ```
+    pub(crate) fn render(&self, count: usize) -> String {
+        match self.total {
+            Some(total) if total > 0 => {
+                let filled = (count.min(total) * BAR_WIDTH / total).min(BAR_WIDTH);
+                let mut bar = "=".repeat(filled);
+                if filled < BAR_WIDTH {
+                    bar.push('>');
+                }
+                format!(
+                    "[{:<width$}] {}/{} {}",
+                    bar,
+                    count,
+                    total,
+                    self.label,
+                    width = BAR_WIDTH
+                )
+            }
+            _ => format!(
+                "{} {}: {}",
+                SPINNER[count % SPINNER.len()],
+                self.label,
+                count
+            ),
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use crate::{ExitError, ExitStatus};
+use anyhow::Result;
+use regex::Regex;
+
+/// Text that replaces each secret
+pub(crate) const REDACTED: &str = "[REDACTED]";
+
+/// Patterns of common secrets, applied in order
+///
+/// Where a pattern has a capture group, only the first group is replaced, so the name of a
+/// `KEY=value` assignment stays readable.
+const DEFAULT_PATTERNS: &[&str] = &[
+    // PEM private key blocks
+    r"-----BEGIN [A-Z0-9 ]*PRIVATE KEY-----[\s\S]*?-----END [A-Z0-9 ]*PRIVATE KEY-----",
+    // AWS access key IDs
+    r"\b(?:AKIA|ASIA|AGPA|AIDA|AROA)[0-9A-Z]{16}\b",
+    // JSON Web Tokens
+    r"\beyJ[A-Za-z0-9_-]{8,}\.eyJ[A-Za-z0-9_-]{8,}\.[A-Za-z0-9_-]{8,}",
+    // GitHub, Slack and Stripe tokens
+    r"\bgh[pousr]_[A-Za-z0-9]{36,}\b",
+    r"\bxox[abposr]-[A-Za-z0-9-]{10,}",
+    r"\b[sr]k_(?:live|test)_[A-Za-z0-9]{16,}\b",
+    // `.env`-style assignments to names that look secret
+    r#"(?im)^\s*(?:export\s+)?[A-Z0-9_.-]*(?:SECRET|TOKEN|PASSWORD|PASSWD|API_?KEY|ACCESS_?KEY|PRIVATE_?KEY|CREDENTIALS?)[A-Z0-9_.-]*\s*[=:]\s*["']?([^\s"'#][^"'\r\n]*?)["']?\s*$"#,
+];
+
+/// Replaces secrets in file contents with `[REDACTED]`
+#[derive(Clone)]
+pub(crate) struct Redactor {
+    patterns: Vec<Regex>,
+}
+
+impl Redactor {
+    /// Builds a redactor from the built-in patterns and extra ones
+    ///
+    /// # Arguments
+    /// * `extra_patterns` - Regular expressions given with `--redact-pattern`
+    ///
+    /// # Returns
+    /// The redactor, or an error naming the first invalid pattern
+    pub(crate) fn new(extra_patterns: &[String]) -> Result<Redactor> {
+        let mut patterns: Vec<Regex> = DEFAULT_PATTERNS
+            .iter()
+            .map(|pattern| Regex::new(pattern).expect("valid pattern"))
+            .collect();
+        for pattern in extra_patterns {
+            let regex = Regex::new(pattern).map_err(|error| {
+                ExitError::new(
+                    ExitStatus::BadArguments,
+                    format!("Invalid redact pattern {}: {}", pattern, error),
+                )
+            })?;
+            patterns.push(regex);
+        }
+        Ok(Redactor { patterns })
+    }
+
+    /// Replaces every match of the patterns in the text
+    ///
+    /// # Arguments
+    /// * `text` - The text to redact
+    ///
+    /// # Returns
+    /// The redacted text and the number of secrets replaced
+    ///
+    /// # Examples
+    /// ```
+    /// let redactor = Redactor::new(&[])?;
+    /// let (text, count) = redactor.redact("API_TOKEN=abc123\n");
+    /// assert_eq!((text.as_str(), count), ("API_TOKEN=[REDACTED]\n", 1));
+    /// ```
+    pub(crate) fn redact(&self, text: &str) -> (String, usize) {
+        let mut text = text.to_string();
+        let mut count = 0;
+        for pattern in &self.patterns {
+            let mut redacted = String::with_capacity(text.len());
+            let mut last = 0;
+            for captures in pattern.captures_iter(&text) {
+                let secret = captures
+                    .get(1)
+                    .or_else(|| captures.get(0))
+                    .expect("match has a whole-match group");
+                if secret.is_empty() || secret.as_str() == REDACTED {
+                    continue;
+                }
+                redacted.push_str(&text[last..secret.start()]);
+                redacted.push_str(REDACTED);
+                last = secret.end();
+                count += 1;
+            }
+            redacted.push_str(&text[last..]);
+            text = redacted;
+        }
+        (text, count)
+    }
+}
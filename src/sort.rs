@@ -0,0 +1,237 @@
+use clap::ValueEnum;
+use globset::GlobSet;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Order in which files appear in the contents section
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum SortMode {
+    /// Lexicographic by path
+    #[default]
+    Path,
+    /// Entrypoints, READMEs and configs first; tests and generated files last
+    Relevance,
+    /// Most recently committed first; untracked files last
+    GitRecency,
+    /// Largest first
+    Size,
+    /// Most recently modified first
+    Mtime,
+    /// By extension, then by path; files without an extension first
+    Ext,
+}
+
+/// File stems that usually mark a program or library entrypoint
+const ENTRYPOINT_STEMS: &[&str] = &["main", "lib", "index", "app", "__main__", "__init__", "mod"];
+
+/// File names of common project manifests and build configuration
+const CONFIG_NAMES: &[&str] = &[
+    "cargo.toml",
+    "package.json",
+    "pyproject.toml",
+    "setup.py",
+    "setup.cfg",
+    "go.mod",
+    "pom.xml",
+    "build.gradle",
+    "makefile",
+    "dockerfile",
+    "cmakelists.txt",
+    "tsconfig.json",
+];
+
+/// Extensions of configuration files
+const CONFIG_EXTENSIONS: &[&str] = &["toml", "yaml", "yml", "ini", "cfg", "conf"];
+
+/// Directory names whose files are likely generated or vendored
+const GENERATED_DIRS: &[&str] = &["dist", "build", "vendor", "generated", "node_modules"];
+
+/// Directory names whose files are tests
+const TEST_DIRS: &[&str] = &["test", "tests", "spec", "specs", "__tests__", "testdata"];
+
+/// User adjustments to the relevance heuristic
+#[derive(Default)]
+pub(crate) struct RelevanceTweaks {
+    /// Files matching these globs are placed before everything else
+    pub(crate) boost: Option<GlobSet>,
+    /// Files matching these globs are placed after everything else
+    pub(crate) demote: Option<GlobSet>,
+}
+
+/// Scores a path with the relevance heuristic; lower scores sort first
+///
+/// The tiers are: entrypoints (0), READMEs (1), manifests and config files (2), other
+/// files (3), other documentation (4), tests (5), and lockfiles, minified, generated or
+/// vendored files (6). Boosted files score below every tier and demoted files above.
+///
+/// # Arguments
+/// * `path` - Path relative to the root
+/// * `tweaks` - User supplied boost/demote globs
+///
+/// # Returns
+/// The relevance tier of the file
+pub(crate) fn relevance_score(path: &Path, tweaks: &RelevanceTweaks) -> i32 {
+    if tweaks
+        .boost
+        .as_ref()
+        .is_some_and(|globs| globs.is_match(path))
+    {
+        return -1;
+    }
+    if tweaks
+        .demote
+        .as_ref()
+        .is_some_and(|globs| globs.is_match(path))
+    {
+        return 7;
+    }
+
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let stem = name.split('.').next().unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let dirs: Vec<String> = path
+        .parent()
+        .into_iter()
+        .flat_map(|parent| parent.components())
+        .map(|component| component.as_os_str().to_string_lossy().to_lowercase())
+        .collect();
+
+    let is_generated = extension == "lock"
+        || name == "package-lock.json"
+        || name.contains(".min.")
+        || name.contains(".generated.")
+        || name.ends_with(".pb.go")
+        || dirs
+            .iter()
+            .any(|dir| GENERATED_DIRS.contains(&dir.as_str()));
+
+    if is_generated {
+        6
+    } else if is_test_path(path) {
+        5
+    } else if ENTRYPOINT_STEMS.contains(&stem) && !CONFIG_EXTENSIONS.contains(&extension.as_str()) {
+        0
+    } else if stem == "readme" {
+        1
+    } else if CONFIG_NAMES.contains(&name.as_str())
+        || CONFIG_EXTENSIONS.contains(&extension.as_str())
+    {
+        2
+    } else if matches!(extension.as_str(), "md" | "rst" | "txt" | "adoc") {
+        4
+    } else {
+        3
+    }
+}
+
+/// Determines if a path looks like a test file
+///
+/// Files under a test directory (`tests/`, `spec/`, `__tests__/`, ...) and files named like
+/// `test_*`, `*_test`, `*_spec`, `*.test.*` or `*.spec.*` are tests.
+///
+/// # Arguments
+/// * `path` - Path relative to the root
+///
+/// # Returns
+/// `true` if the file is a test
+///
+/// # Examples
+/// ```
+/// assert!(is_test_path(Path::new("tests/cli.rs")));
+/// assert!(is_test_path(Path::new("src/app.test.ts")));
+/// assert!(!is_test_path(Path::new("src/main.rs")));
+/// ```
+pub(crate) fn is_test_path(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let stem = name.split('.').next().unwrap_or_default();
+    let in_test_dir = path
+        .parent()
+        .into_iter()
+        .flat_map(|parent| parent.components())
+        .any(|component| {
+            let dir = component.as_os_str().to_string_lossy().to_lowercase();
+            TEST_DIRS.contains(&dir.as_str())
+        });
+
+    in_test_dir
+        || stem.starts_with("test_")
+        || stem.ends_with("_test")
+        || stem.ends_with("_spec")
+        || name.contains(".test.")
+        || name.contains(".spec.")
+}
+
+/// Moves test files after all other files, keeping the order within each group
+///
+/// # Arguments
+/// * `files` - Relative file paths in their final order
+///
+/// # Returns
+/// The number of non-test files, which now come first
+pub(crate) fn partition_tests(files: &mut [PathBuf]) -> usize {
+    files.sort_by_key(|path| is_test_path(path));
+    files.iter().filter(|path| !is_test_path(path)).count()
+}
+
+/// Sorts files in place according to the sort mode
+///
+/// Ties are broken by path. `SortMode::Size` and `SortMode::Mtime` stat each file once;
+/// files that cannot be stat-ed sort last.
+///
+/// # Arguments
+/// * `root_path` - The root directory the paths are relative to
+/// * `files` - Relative file paths, expected to already be in path order
+/// * `mode` - The sort mode
+/// * `tweaks` - Adjustments used by `SortMode::Relevance`
+/// * `commit_times` - Time of the latest commit touching each file, used by
+///   `SortMode::GitRecency`; files without a time are untracked
+pub(crate) fn sort_files(
+    root_path: &Path,
+    files: &mut [PathBuf],
+    mode: SortMode,
+    tweaks: &RelevanceTweaks,
+    commit_times: &BTreeMap<PathBuf, i64>,
+) {
+    let metadata = |path: &Path| fs::metadata(root_path.join(path)).ok();
+    match mode {
+        SortMode::Size => files.sort_by_cached_key(|path| {
+            let size = metadata(path).map(|metadata| metadata.len());
+            (Reverse(size), path.clone())
+        }),
+        SortMode::Mtime => files.sort_by_cached_key(|path| {
+            let modified = metadata(path).and_then(|metadata| metadata.modified().ok());
+            (Reverse(modified), path.clone())
+        }),
+        SortMode::Ext => files.sort_by_cached_key(|path| {
+            let extension = path
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase());
+            (extension, path.clone())
+        }),
+        SortMode::Path => files.sort(),
+        SortMode::GitRecency => files.sort_by_cached_key(|path| {
+            (
+                Reverse(commit_times.get(path).copied().unwrap_or(i64::MIN)),
+                path.clone(),
+            )
+        }),
+        SortMode::Relevance => files.sort_by_cached_key(|path| {
+            (
+                relevance_score(path, tweaks),
+                path.components().count(),
+                path.clone(),
+            )
+        }),
+    }
+}
@@ -0,0 +1,340 @@
+use crate::notice;
+use regex::Regex;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+/// How far a definition extends after its first line
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Extent {
+    /// Up to the bracket closing the definition's body, or the end of the statement
+    Brackets,
+    /// Over the lines indented deeper than the definition, as in Python
+    Indentation,
+}
+
+/// What `--symbol` needs to know about a language to find definitions without a parser
+struct SymbolSyntax {
+    /// Patterns of a definition's first line; `{name}` stands for the escaped symbol name
+    definitions: &'static [&'static str],
+    /// Pattern of the first line of a top-level import statement
+    import: &'static str,
+    /// Line prefixes of doc comments, attributes and decorators kept above a definition
+    leading: &'static [&'static str],
+    /// Start of a comment running to the end of the line
+    line_comment: &'static str,
+    /// Whether `'` quotes strings rather than single characters
+    single_quote_strings: bool,
+    /// How far a definition extends
+    extent: Extent,
+}
+
+const RUST: SymbolSyntax = SymbolSyntax {
+    definitions: &[
+        r#"^\s*(?:pub(?:\([^)]*\))?\s+)?(?:(?:async|const|unsafe|default|extern(?:\s+"[^"]*")?)\s+)*(?:fn|struct|enum|union|trait|type|mod|const|static(?:\s+mut)?)\s+{name}\b"#,
+        r"^\s*macro_rules!\s*{name}\b",
+        // `impl` blocks of the type, or of the trait
+        r"^\s*(?:unsafe\s+)?impl\b(?:<[^{]*?>)?\s+(?:[^{;]*?\s+for\s+)?(?:\w+::)*{name}\b",
+    ],
+    import: r"^(?:pub(?:\([^)]*\))?\s+)?use\s",
+    leading: &["///", "//", "#["],
+    line_comment: "//",
+    single_quote_strings: false,
+    extent: Extent::Brackets,
+};
+
+const PYTHON: SymbolSyntax = SymbolSyntax {
+    definitions: &[
+        r"^\s*(?:async\s+)?(?:def|class)\s+{name}\b",
+        r"^{name}\s*(?::[^=]*)?=(?:[^=]|$)",
+    ],
+    import: r"^(?:import|from)\s",
+    leading: &["@", "#"],
+    line_comment: "#",
+    single_quote_strings: true,
+    extent: Extent::Indentation,
+};
+
+const JAVASCRIPT: SymbolSyntax = SymbolSyntax {
+    definitions: &[
+        r"^\s*(?:export\s+)?(?:default\s+)?(?:declare\s+)?(?:abstract\s+)?(?:async\s+)?(?:function\s*\*?|class|interface|type|enum|const|let|var)\s*{name}\b",
+    ],
+    import: r"^import\b",
+    leading: &["@", "//", "/*", "*"],
+    line_comment: "//",
+    single_quote_strings: true,
+    extent: Extent::Brackets,
+};
+
+const GO: SymbolSyntax = SymbolSyntax {
+    definitions: &[
+        r"^func\s+(?:\([^)]*\)\s*)?{name}\b",
+        r"^\s*type\s+{name}\b",
+        r"^(?:var|const)\s+{name}\b",
+    ],
+    import: r"^import\b",
+    leading: &["//"],
+    line_comment: "//",
+    single_quote_strings: false,
+    extent: Extent::Brackets,
+};
+
+/// Line endings after which a statement always continues on the next line
+const CONTINUATIONS: &[&str] = &[
+    "=", ",", "(", "[", "{", "=>", "->", "+", "-", "|", "&", ".", ":", "\\", "<",
+];
+
+/// Line starts that continue the statement of the previous line
+const CONTINUED_BY: &[&str] = &[
+    "{", "where", "->", ".", "=", "?", ":", "|", "&", "+", ")", "]",
+];
+
+/// Chooses the syntax rules for a file from its extension
+fn syntax_for_path(path: &Path) -> Option<&'static SymbolSyntax> {
+    let extension = path.extension()?.to_string_lossy().to_lowercase();
+    match extension.as_str() {
+        "rs" => Some(&RUST),
+        "py" | "pyi" => Some(&PYTHON),
+        "js" | "jsx" | "mjs" | "cjs" | "ts" | "tsx" | "mts" | "cts" => Some(&JAVASCRIPT),
+        "go" => Some(&GO),
+        _ => None,
+    }
+}
+
+/// Tracks bracket depth line by line, skipping brackets in strings and comments
+struct BracketScanner<'a> {
+    syntax: &'a SymbolSyntax,
+    /// Quote of the string the scan is inside of
+    in_string: Option<char>,
+    in_block_comment: bool,
+    braces: i32,
+    parens: i32,
+    /// Whether a `{` has been seen
+    opened: bool,
+}
+
+impl<'a> BracketScanner<'a> {
+    fn new(syntax: &'a SymbolSyntax) -> Self {
+        BracketScanner {
+            syntax,
+            in_string: None,
+            in_block_comment: false,
+            braces: 0,
+            parens: 0,
+            opened: false,
+        }
+    }
+
+    fn scan(&mut self, line: &str) {
+        let chars: Vec<(usize, char)> = line.char_indices().collect();
+        let char_at = |i: usize| chars.get(i).map(|(_, c)| *c);
+        let mut i = 0;
+        while i < chars.len() {
+            let (at, c) = chars[i];
+            let rest = &line[at..];
+            if self.in_block_comment {
+                if rest.starts_with("*/") {
+                    self.in_block_comment = false;
+                    i += 1;
+                }
+            } else if let Some(quote) = self.in_string {
+                if c == '\\' {
+                    i += 1;
+                } else if c == quote {
+                    self.in_string = None;
+                }
+            } else if rest.starts_with(self.syntax.line_comment) {
+                break;
+            } else if self.syntax.line_comment == "//" && rest.starts_with("/*") {
+                self.in_block_comment = true;
+                i += 1;
+            } else {
+                match c {
+                    '"' | '`' => self.in_string = Some(c),
+                    '\'' if self.syntax.single_quote_strings => self.in_string = Some(c),
+                    // A character literal such as '{' or '\n'; otherwise a Rust lifetime
+                    '\'' if char_at(i + 1) == Some('\\') => {
+                        i += 2;
+                        while char_at(i).is_some_and(|c| c != '\'') {
+                            i += 1;
+                        }
+                    }
+                    '\'' if char_at(i + 2) == Some('\'') => i += 2,
+                    '{' => {
+                        self.braces += 1;
+                        self.opened = true;
+                    }
+                    '}' => self.braces -= 1,
+                    '(' | '[' => self.parens += 1,
+                    ')' | ']' => self.parens -= 1,
+                    _ => {}
+                }
+            }
+            i += 1;
+        }
+        // Single-quoted strings never span lines
+        if self.in_string == Some('\'') {
+            self.in_string = None;
+        }
+    }
+}
+
+/// Finds the last line of a bracketed definition or statement starting at `start`
+fn bracket_extent(lines: &[&str], start: usize, syntax: &SymbolSyntax) -> usize {
+    let mut scanner = BracketScanner::new(syntax);
+    for (i, line) in lines.iter().enumerate().skip(start) {
+        scanner.scan(line);
+        if scanner.in_string.is_some() || scanner.in_block_comment {
+            continue;
+        }
+        if scanner.opened {
+            if scanner.braces <= 0 && scanner.parens <= 0 {
+                return i;
+            }
+            continue;
+        }
+        if scanner.parens > 0 {
+            continue;
+        }
+        let trimmed = line.trim_end();
+        if trimmed.ends_with(';') {
+            return i;
+        }
+        if CONTINUATIONS.iter().any(|ending| trimmed.ends_with(ending)) {
+            continue;
+        }
+        let next = lines[i + 1..]
+            .iter()
+            .map(|line| line.trim())
+            .find(|line| !line.is_empty());
+        if next.is_some_and(|next| CONTINUED_BY.iter().any(|start| next.starts_with(start))) {
+            continue;
+        }
+        return i;
+    }
+    lines.len() - 1
+}
+
+/// Finds the last line of an indented definition starting at `start`
+fn indentation_extent(lines: &[&str], start: usize, syntax: &SymbolSyntax) -> usize {
+    let indent = |line: &str| line.len() - line.trim_start().len();
+    let base_indent = indent(lines[start]);
+    let mut scanner = BracketScanner::new(syntax);
+    scanner.scan(lines[start]);
+    let mut end = start;
+    for (i, line) in lines.iter().enumerate().skip(start + 1) {
+        let continued = scanner.parens > 0 || scanner.in_string.is_some();
+        if !continued && !line.trim().is_empty() && indent(line) <= base_indent {
+            break;
+        }
+        scanner.scan(line);
+        if !line.trim().is_empty() {
+            end = i;
+        }
+    }
+    end
+}
+
+/// Extracts the definitions of a symbol from a source file, with the file's imports
+///
+/// Definitions are found with per-language patterns rather than a parser: functions, types,
+/// traits, constants and `impl` blocks in Rust; functions, classes and module-level
+/// assignments in Python; functions, classes, interfaces, types and variables in
+/// JavaScript and TypeScript; functions, methods, types, variables and constants in Go.
+/// Doc comments, attributes and decorators directly above a definition are kept. Other lines
+/// in between are replaced with `truncated` notices, so line numbers stay traceable.
+///
+/// # Arguments
+/// * `path` - Path of the file, which selects the language by extension
+/// * `source` - The file contents
+/// * `name` - The symbol to look for
+///
+/// # Returns
+/// The excerpt, or `None` if the language is not supported or the file does not define
+/// the symbol
+///
+/// # Examples
+/// ```
+/// let source = "use std::fmt;\n\nfn helper() {}\n\nfn target() {\n    helper();\n}\n";
+/// let excerpt = extract_symbol(Path::new("lib.rs"), source, "target").unwrap();
+/// assert!(excerpt.starts_with("use std::fmt;\n[[repodump:truncated lines=2..4"));
+/// ```
+pub(crate) fn extract_symbol(path: &Path, source: &str, name: &str) -> Option<String> {
+    let syntax = syntax_for_path(path)?;
+    let definitions: Vec<Regex> = syntax
+        .definitions
+        .iter()
+        .map(|pattern| {
+            Regex::new(&pattern.replace("{name}", &regex::escape(name))).expect("valid pattern")
+        })
+        .collect();
+    let import = Regex::new(syntax.import).expect("valid pattern");
+    let lines: Vec<&str> = source.lines().collect();
+
+    let mut kept: Vec<RangeInclusive<usize>> = Vec::new();
+    let mut found = false;
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        let is_definition = definitions.iter().any(|pattern| pattern.is_match(line));
+        if !is_definition && !import.is_match(line) {
+            i += 1;
+            continue;
+        }
+        let end = if is_definition && syntax.extent == Extent::Indentation {
+            indentation_extent(&lines, i, syntax)
+        } else {
+            bracket_extent(&lines, i, syntax)
+        };
+        let mut begin = i;
+        if is_definition {
+            found = true;
+            while begin > 0 {
+                let previous = lines[begin - 1].trim_start();
+                if !syntax
+                    .leading
+                    .iter()
+                    .any(|prefix| previous.starts_with(prefix))
+                {
+                    break;
+                }
+                begin -= 1;
+            }
+        }
+        kept.push(begin..=end);
+        i = end + 1;
+    }
+    if !found {
+        return None;
+    }
+
+    let mut excerpt = String::new();
+    let mut next_line = 0;
+    for range in kept {
+        let begin = (*range.start()).max(next_line);
+        let gap = &lines[next_line..begin];
+        // Blank lines between kept lines are kept rather than noted
+        let start = if gap.iter().all(|line| line.trim().is_empty()) {
+            next_line
+        } else {
+            excerpt.push_str(&notice::truncated_lines(
+                next_line + 1,
+                begin,
+                "outside-symbol",
+            ));
+            begin
+        };
+        for line in &lines[start..=*range.end()] {
+            excerpt.push_str(line);
+            excerpt.push('\n');
+        }
+        next_line = range.end() + 1;
+    }
+    if next_line < lines.len() {
+        excerpt.push_str(&notice::truncated_lines(
+            next_line + 1,
+            lines.len(),
+            "outside-symbol",
+        ));
+    }
+    Some(excerpt)
+}
@@ -0,0 +1,54 @@
+use clap::ValueEnum;
+
+/// Common jobs with a built-in prompt, selected with `--task`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Task {
+    /// Review the code for bugs, risks and improvements
+    Review,
+    /// Write documentation for the code
+    Document,
+    /// Propose refactorings that simplify the code
+    Refactor,
+    /// Explain how the code works
+    Explain,
+}
+
+impl Task {
+    /// The built-in prompt of the task, used when no `--prompt` is given
+    ///
+    /// # Examples
+    /// ```
+    /// assert!(Task::Review.prompt().starts_with("Review"));
+    /// ```
+    pub(crate) fn prompt(self) -> &'static str {
+        match self {
+            Task::Review => {
+                "Review the code above as an experienced maintainer of this repository. \
+                 Point out bugs, edge cases that are not handled, security problems and \
+                 code that is hard to maintain. For each finding, name the file and the \
+                 relevant code, explain why it is a problem, and suggest a concrete fix. \
+                 List the most serious findings first."
+            }
+            Task::Document => {
+                "Write documentation for the code above. Start with an overview of what \
+                 the project does and how its parts fit together, then document each \
+                 public module, type and function: its purpose, parameters, return value \
+                 and errors. Follow the documentation conventions already used in the \
+                 code, and include short usage examples where they help."
+            }
+            Task::Refactor => {
+                "Suggest refactorings that would make the code above simpler, clearer or \
+                 easier to test without changing its behavior. Focus on duplication, \
+                 overly long functions, unclear names and tangled responsibilities. For \
+                 each suggestion, show the affected code before and after, and explain \
+                 the benefit and any risk."
+            }
+            Task::Explain => {
+                "Explain how the code above works to a developer who is new to the \
+                 project. Describe its purpose, its overall architecture, the main data \
+                 flow from input to output, and the role of each important file. Call \
+                 out any non-obvious design decisions."
+            }
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use clap::ValueEnum;
+
+/// A built-in rewrite of a text file's contents, selected per extension with `--transform`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Transform {
+    /// Remove whitespace outside of strings in JSON
+    MinifyJson,
+    /// Remove a leading `---` YAML or `+++` TOML front-matter block
+    StripFrontmatter,
+    /// Remove lines holding only a comment, keeping a leading shebang
+    StripComments,
+    /// Remove a leading license header, as `--strip-license-headers` does
+    StripLicenseHeader,
+    /// Collapse runs of blank lines into a single blank line
+    CollapseBlankLines,
+    /// Remove whitespace at the end of each line
+    TrimTrailingWhitespace,
+}
+
+/// The transforms applied to files with one extension, parsed from `EXT=NAME[,NAME...]`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct TransformRule {
+    /// Lowercase extension without the leading dot
+    pub(crate) extension: String,
+    /// Transforms in the order they are applied
+    pub(crate) transforms: Vec<Transform>,
+}
+
+impl TransformRule {
+    /// Parses a rule such as `json=minify-json` or `.py=strip-comments,collapse-blank-lines`
+    ///
+    /// # Arguments
+    /// * `text` - The rule as given to `--transform`
+    ///
+    /// # Returns
+    /// The rule, or a message naming the unknown transform or missing part
+    pub(crate) fn parse(text: &str) -> Result<TransformRule, String> {
+        let (extension, names) = text
+            .split_once('=')
+            .ok_or_else(|| format!("expected EXT=TRANSFORM[,TRANSFORM...], got `{}`", text))?;
+        let extension = extension.trim().trim_start_matches('.').to_lowercase();
+        if extension.is_empty() {
+            return Err(format!("missing extension in `{}`", text));
+        }
+        let transforms = names
+            .split(',')
+            .map(|name| Transform::from_str(name.trim(), true))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(TransformRule {
+            extension,
+            transforms,
+        })
+    }
+}
+
+/// Applies transforms to a file's contents in order
+///
+/// # Arguments
+/// * `content` - The file contents
+/// * `extension` - Lowercase extension of the file, which selects the comment syntax
+/// * `transforms` - The transforms to apply
+///
+/// # Returns
+/// The transformed contents
+///
+/// # Examples
+/// ```
+/// let minified = apply("{\n  \"a\": [1, 2]\n}\n", "json", &[Transform::MinifyJson]);
+/// assert_eq!(minified, "{\"a\":[1,2]}\n");
+/// ```
+pub(crate) fn apply(content: &str, extension: &str, transforms: &[Transform]) -> String {
+    let mut content = content.to_string();
+    for transform in transforms {
+        content = match transform {
+            Transform::MinifyJson => minify_json(&content),
+            Transform::StripFrontmatter => strip_frontmatter(&content),
+            Transform::StripComments => strip_comment_lines(&content, extension),
+            Transform::StripLicenseHeader => {
+                let markers: Vec<String> = crate::DEFAULT_LICENSE_MARKERS
+                    .iter()
+                    .map(|marker| marker.to_string())
+                    .collect();
+                crate::strip_license_header(&content, &markers).unwrap_or(content)
+            }
+            Transform::CollapseBlankLines => collapse_blank_lines(&content),
+            Transform::TrimTrailingWhitespace => content
+                .split_inclusive('\n')
+                .map(|line| {
+                    let newline = if line.ends_with('\n') { "\n" } else { "" };
+                    format!("{}{}", line.trim_end(), newline)
+                })
+                .collect(),
+        };
+    }
+    content
+}
+
+/// Removes whitespace outside of strings, leaving invalid JSON otherwise untouched
+fn minify_json(content: &str) -> String {
+    let mut minified = String::with_capacity(content.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in content.chars() {
+        if in_string {
+            minified.push(c);
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+        } else if c == '"' {
+            in_string = true;
+            minified.push(c);
+        } else if !c.is_whitespace() {
+            minified.push(c);
+        }
+    }
+    if in_string {
+        // An unterminated string means this is not JSON
+        return content.to_string();
+    }
+    if content.ends_with('\n') {
+        minified.push('\n');
+    }
+    minified
+}
+
+/// Removes a front-matter block delimited by `---` or `+++` lines at the very start
+fn strip_frontmatter(content: &str) -> String {
+    let mut lines = content.split_inclusive('\n');
+    let Some(delimiter) = lines.next().map(str::trim_end) else {
+        return content.to_string();
+    };
+    if delimiter != "---" && delimiter != "+++" {
+        return content.to_string();
+    }
+    let mut offset = content.find('\n').map_or(content.len(), |i| i + 1);
+    for line in lines {
+        offset += line.len();
+        if line.trim_end() == delimiter {
+            return content[offset..]
+                .trim_start_matches(['\r', '\n'])
+                .to_string();
+        }
+    }
+    // No closing delimiter, so this was not front matter
+    content.to_string()
+}
+
+/// Removes lines that hold only a comment in the language of the extension
+fn strip_comment_lines(content: &str, extension: &str) -> String {
+    let prefix = match extension {
+        "py" | "pyi" | "sh" | "bash" | "zsh" | "rb" | "pl" | "r" | "yaml" | "yml" | "toml"
+        | "ini" | "cfg" | "conf" | "mk" | "cmake" | "dockerfile" => "#",
+        "sql" | "lua" | "hs" => "--",
+        "el" | "clj" | "lisp" | "scm" | "asm" => ";",
+        _ => "//",
+    };
+    content
+        .split_inclusive('\n')
+        .enumerate()
+        .filter(|(i, line)| {
+            let line = line.trim_start();
+            let is_comment =
+                line.starts_with(prefix) && !(prefix == "#" && *i == 0 && line.starts_with("#!"));
+            !is_comment
+        })
+        .map(|(_, line)| line)
+        .collect()
+}
+
+/// Collapses runs of blank lines into one blank line
+fn collapse_blank_lines(content: &str) -> String {
+    let mut collapsed = String::with_capacity(content.len());
+    let mut previous_blank = false;
+    for line in content.split_inclusive('\n') {
+        let blank = line.trim().is_empty();
+        if !(blank && previous_blank) {
+            collapsed.push_str(line);
+        }
+        previous_blank = blank;
+    }
+    collapsed
+}